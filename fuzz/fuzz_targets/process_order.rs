@@ -0,0 +1,92 @@
+#![no_main]
+
+use arbitrary::{Arbitrary, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use trading_engine::{
+    Operation, Order, OrderBook, OrderStatus, OrderType, Side, TimeInForce, TradingPair,
+};
+
+// Raw shape of an order as generated from fuzzer bytes. Numeric fields stay strings
+// so malformed amounts and prices reach the book's own parsing.
+#[derive(Debug, Arbitrary)]
+struct FuzzOrder {
+    type_op: u8,
+    account_id: String,
+    amount: String,
+    order_id: String,
+    pair: String,
+    limit_price: String,
+    sell: bool,
+    time_in_force: Option<u8>,
+    market: bool,
+    new_amount: Option<String>,
+    post_only: bool,
+    expires_at_ms: Option<u64>,
+    visible_amount: Option<(i64, u32)>,
+    timestamp: u64,
+}
+
+impl From<FuzzOrder> for Order {
+    fn from(raw: FuzzOrder) -> Self {
+        Order {
+            type_op: match raw.type_op % 4 {
+                0 => Operation::Create,
+                1 => Operation::Delete,
+                2 => Operation::Modify,
+                _ => Operation::CancelReplace,
+            },
+            account_id: raw.account_id,
+            amount: raw.amount,
+            order_id: raw.order_id,
+            // Invalid pairs can't be constructed, so they all land on one book
+            pair: raw
+                .pair
+                .parse()
+                .unwrap_or_else(|_| TradingPair::new("BTC", "USDC").unwrap()),
+            limit_price: raw.limit_price,
+            side: if raw.sell { Side::Sell } else { Side::Buy },
+            time_in_force: raw.time_in_force.map(|tif| match tif % 3 {
+                0 => TimeInForce::Gtc,
+                1 => TimeInForce::Ioc,
+                _ => TimeInForce::Fok,
+            }),
+            order_type: if raw.market {
+                OrderType::Market
+            } else {
+                OrderType::Limit
+            },
+            new_amount: raw.new_amount,
+            post_only: raw.post_only,
+            expires_at_ms: raw.expires_at_ms,
+            visible_amount: raw
+                .visible_amount
+                .and_then(|(num, scale)| Decimal::try_new(num, scale).ok()),
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: raw.timestamp,
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Corpus seeds are JSON order lists; any other input is decoded with arbitrary
+    let orders: Vec<Order> = serde_json::from_slice(data).unwrap_or_else(|_| {
+        Vec::<FuzzOrder>::arbitrary_take_rest(Unstructured::new(data))
+            .map(|raw| raw.into_iter().map(Order::from).collect())
+            .unwrap_or_default()
+    });
+
+    let mut order_book = OrderBook::default();
+    for order in orders {
+        let _ = order_book.process_order(order);
+    }
+
+    // Whatever the input, accepted orders must leave the book consistent
+    if let Err(violations) = order_book.validate_invariants() {
+        panic!("book invariants violated: {violations:?}");
+    }
+});
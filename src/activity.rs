@@ -0,0 +1,116 @@
+use crate::NANOS_PER_MILLI;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+
+// How far back order activity is kept. Rates asked for over a longer window only
+// see this much history.
+const ACTIVITY_RETENTION_MS: u64 = 5 * 60 * 1_000;
+
+// Recent order activity, globally and per account, for rate queries. Every buffer
+// holds timestamps (ns) oldest first and drops them once they pass the retention.
+#[derive(Debug, Default)]
+pub(crate) struct ActivityLog {
+    arrivals: VecDeque<u64>,
+    accounts: HashMap<String, AccountActivity>,
+}
+
+#[derive(Debug, Default)]
+struct AccountActivity {
+    orders_submitted: VecDeque<u64>,
+    orders_cancelled: VecDeque<u64>,
+    // Order amounts as submitted and as filled, by time of submission and of fill
+    submitted_volume: VecDeque<(u64, Decimal)>,
+    filled_volume: VecDeque<(u64, Decimal)>,
+}
+
+impl ActivityLog {
+    pub(crate) fn record_arrival(&mut self, account_id: &str, amount: Decimal, now: u64) {
+        push(&mut self.arrivals, now);
+        let account = self.account(account_id);
+        push(&mut account.orders_submitted, now);
+        push_volume(&mut account.submitted_volume, now, amount);
+    }
+
+    pub(crate) fn record_fill(&mut self, account_id: &str, amount: Decimal, now: u64) {
+        push_volume(&mut self.account(account_id).filled_volume, now, amount);
+    }
+
+    pub(crate) fn record_cancel(&mut self, account_id: &str, now: u64) {
+        push(&mut self.account(account_id).orders_cancelled, now);
+    }
+
+    pub(crate) fn arrival_count(&self, since: u64) -> usize {
+        count_since(&self.arrivals, since)
+    }
+
+    pub(crate) fn account_arrival_count(&self, account_id: &str, since: u64) -> usize {
+        self.accounts
+            .get(account_id)
+            .map_or(0, |account| count_since(&account.orders_submitted, since))
+    }
+
+    // (account id, cancelled, submitted) since `since`, for each account with either
+    pub(crate) fn cancel_counts(&self, since: u64) -> impl Iterator<Item = (&str, usize, usize)> {
+        self.accounts
+            .iter()
+            .filter_map(move |(account_id, account)| {
+                let cancelled = count_since(&account.orders_cancelled, since);
+                let submitted = count_since(&account.orders_submitted, since);
+                (cancelled + submitted > 0).then_some((account_id.as_str(), cancelled, submitted))
+            })
+    }
+
+    pub(crate) fn account_cancel_count(&self, account_id: &str, since: u64) -> usize {
+        self.accounts
+            .get(account_id)
+            .map_or(0, |account| count_since(&account.orders_cancelled, since))
+    }
+
+    // (submitted, filled) volume of the account since `since`
+    pub(crate) fn account_volumes(&self, account_id: &str, since: u64) -> (Decimal, Decimal) {
+        self.accounts
+            .get(account_id)
+            .map(|account| {
+                (
+                    volume_since(&account.submitted_volume, since),
+                    volume_since(&account.filled_volume, since),
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn account(&mut self, account_id: &str) -> &mut AccountActivity {
+        self.accounts.entry(account_id.to_string()).or_default()
+    }
+}
+
+fn push(timestamps: &mut VecDeque<u64>, now: u64) {
+    let cutoff = now.saturating_sub(ACTIVITY_RETENTION_MS * NANOS_PER_MILLI);
+    while timestamps
+        .front()
+        .is_some_and(|&timestamp| timestamp < cutoff)
+    {
+        timestamps.pop_front();
+    }
+    timestamps.push_back(now);
+}
+
+fn push_volume(volumes: &mut VecDeque<(u64, Decimal)>, now: u64, amount: Decimal) {
+    let cutoff = now.saturating_sub(ACTIVITY_RETENTION_MS * NANOS_PER_MILLI);
+    while volumes
+        .front()
+        .is_some_and(|&(timestamp, _)| timestamp < cutoff)
+    {
+        volumes.pop_front();
+    }
+    volumes.push_back((now, amount));
+}
+
+fn volume_since(volumes: &VecDeque<(u64, Decimal)>, since: u64) -> Decimal {
+    let start = volumes.partition_point(|&(timestamp, _)| timestamp < since);
+    volumes.range(start..).map(|(_, amount)| amount).sum()
+}
+
+fn count_since(timestamps: &VecDeque<u64>, since: u64) -> usize {
+    timestamps.len() - timestamps.partition_point(|&timestamp| timestamp < since)
+}
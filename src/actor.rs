@@ -0,0 +1,91 @@
+use crate::{Order, OrderBook, OrderBookConfig, OrderBookError, OrderBookSnapshot, Trade};
+use std::fmt;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+// Requests waiting for the actor before submitters are made to wait
+const ACTOR_QUEUE_CAPACITY: usize = 1024;
+
+enum Command {
+    Submit(
+        Box<Order>,
+        oneshot::Sender<Result<Vec<Trade>, OrderBookError>>,
+    ),
+    Snapshot(oneshot::Sender<OrderBookSnapshot>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActorError {
+    // The actor task is gone, e.g. it panicked or was aborted
+    Stopped,
+    // The book rejected the order
+    Rejected(OrderBookError),
+}
+
+impl fmt::Display for ActorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActorError::Stopped => write!(f, "order book actor stopped"),
+            ActorError::Rejected(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ActorError {}
+
+// Sends requests to a book owned by an actor task. Clones talk to the same book,
+// and the actor stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct OrderBookHandle {
+    sender: mpsc::Sender<Command>,
+}
+
+// Starts a task that owns a new book and applies requests to it one at a time, in
+// the order they were sent. Must be called from within a tokio runtime. Fails
+// without starting the task if the config is invalid.
+pub fn spawn_actor(
+    config: OrderBookConfig,
+) -> Result<(OrderBookHandle, JoinHandle<()>), OrderBookError> {
+    let mut order_book = OrderBook::new(config)?;
+    let (sender, mut receiver) = mpsc::channel(ACTOR_QUEUE_CAPACITY);
+    let task = tokio::spawn(async move {
+        while let Some(command) = receiver.recv().await {
+            // A requester that stopped waiting doesn't need the reply
+            match command {
+                Command::Submit(order, reply) => {
+                    let result = order_book.process_order(*order).map(|(_, trades)| trades);
+                    let _ = reply.send(result);
+                }
+                Command::Snapshot(reply) => {
+                    let _ = reply.send(order_book.snapshot());
+                }
+            }
+        }
+    });
+    Ok((OrderBookHandle { sender }, task))
+}
+
+impl OrderBookHandle {
+    pub async fn submit(&self, order: Order) -> Result<Vec<Trade>, ActorError> {
+        let (reply, response) = oneshot::channel();
+        self.request(Command::Submit(Box::new(order), reply))
+            .await?;
+        response
+            .await
+            .map_err(|_| ActorError::Stopped)?
+            .map_err(ActorError::Rejected)
+    }
+
+    pub async fn snapshot(&self) -> Result<OrderBookSnapshot, ActorError> {
+        let (reply, response) = oneshot::channel();
+        self.request(Command::Snapshot(reply)).await?;
+        response.await.map_err(|_| ActorError::Stopped)
+    }
+
+    async fn request(&self, command: Command) -> Result<(), ActorError> {
+        self.sender
+            .send(command)
+            .await
+            .map_err(|_| ActorError::Stopped)
+    }
+}
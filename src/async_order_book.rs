@@ -0,0 +1,71 @@
+use crate::{
+    EventReceiver, Order, OrderBook, OrderBookError, OrderBookEvent, OrderBookSnapshot, Trade,
+};
+use futures_util::{Stream, StreamExt};
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast};
+
+// Events a subscriber can fall behind by before it starts missing them
+const EVENT_CAPACITY: usize = 1024;
+
+// Shares one book between tasks. Each call holds the lock for a single order, so
+// orders from different tasks are matched one at a time in the order they get it.
+#[derive(Debug, Clone)]
+pub struct AsyncOrderBook {
+    inner: Arc<Mutex<Inner>>,
+    events: broadcast::Sender<OrderBookEvent>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    order_book: OrderBook,
+    // The book's own event feed, forwarded to `events` after each order
+    events: EventReceiver,
+}
+
+impl AsyncOrderBook {
+    pub fn new(mut order_book: OrderBook) -> Self {
+        let events = order_book.subscribe();
+        AsyncOrderBook {
+            inner: Arc::new(Mutex::new(Inner { order_book, events })),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub async fn process_order(&self, order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        let mut inner = self.inner.lock().await;
+        let result = inner.order_book.process_order(order);
+        // Published under the lock so subscribers see events in processing order.
+        // Sending only fails when nobody is subscribed.
+        while let Ok(event) = inner.events.try_recv() {
+            let _ = self.events.send(event);
+        }
+        result.map(|(_, trades)| trades)
+    }
+
+    pub async fn snapshot(&self) -> OrderBookSnapshot {
+        self.inner.lock().await.order_book.snapshot()
+    }
+
+    // Receives the events of every order processed after subscribing. A receiver
+    // that falls more than EVENT_CAPACITY events behind gets RecvError::Lagged and
+    // skips ahead to the oldest event still buffered.
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderBookEvent> {
+        self.events.subscribe()
+    }
+
+    // Processes the orders one after another as they arrive and yields the trades
+    // each one made. A rejected order yields no trades.
+    pub fn process_order_batch<'a>(
+        &'a self,
+        orders: impl Stream<Item = Order> + 'a,
+    ) -> impl Stream<Item = Vec<Trade>> + 'a {
+        orders.then(move |order| async move { self.process_order(order).await.unwrap_or_default() })
+    }
+}
+
+impl Default for AsyncOrderBook {
+    fn default() -> Self {
+        AsyncOrderBook::new(OrderBook::default())
+    }
+}
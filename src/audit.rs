@@ -0,0 +1,45 @@
+use crate::{Operation, OrderBookEntry};
+use serde::{Deserialize, Serialize};
+
+// Record of every order action an OrderBook accepted, in the order it applied them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+// One accepted action. The states are the order as it rested in the book before and
+// after the action; None means it wasn't resting at that point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp_ns: u64,
+    pub operation: Operation,
+    pub order_id: String,
+    pub account_id: String,
+    pub before_state: Option<OrderBookEntry>,
+    pub after_state: Option<OrderBookEntry>,
+    // Ids of the trades the action produced
+    pub resulting_trades: Vec<String>,
+}
+
+// Returned by OrderBook::audit_log when auditing is off
+pub(crate) static EMPTY_AUDIT_LOG: AuditLog = AuditLog {
+    entries: Vec::new(),
+};
+
+impl AuditLog {
+    pub(crate) fn record(&mut self, entry: AuditEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
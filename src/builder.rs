@@ -0,0 +1,110 @@
+use crate::{
+    Operation, Order, OrderStatus, OrderType, Side, TimeInForce, TradingPair, generate_order_id,
+    get_current_timestamp, parse_amount, parse_price,
+};
+use rust_decimal::Decimal;
+use std::fmt;
+
+// Fluent constructor for CREATE limit orders. The order id and timestamp are
+// generated when not set explicitly.
+#[derive(Debug, Clone)]
+pub struct OrderBuilder {
+    side: Side,
+    pair: String,
+    amount: String,
+    limit_price: String,
+    account_id: String,
+    order_id: Option<String>,
+    time_in_force: Option<TimeInForce>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderBuilderError {
+    InvalidPair(String),
+    InvalidAmount(String),
+    InvalidPrice(String),
+}
+
+impl fmt::Display for OrderBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderBuilderError::InvalidPair(pair) => write!(f, "invalid pair: {}", pair),
+            OrderBuilderError::InvalidAmount(amount) => write!(f, "invalid amount: {}", amount),
+            OrderBuilderError::InvalidPrice(price) => write!(f, "invalid price: {}", price),
+        }
+    }
+}
+
+impl std::error::Error for OrderBuilderError {}
+
+impl OrderBuilder {
+    pub fn buy(pair: &str, amount: &str, price: &str) -> Self {
+        Self::new(Side::Buy, pair, amount, price)
+    }
+
+    pub fn sell(pair: &str, amount: &str, price: &str) -> Self {
+        Self::new(Side::Sell, pair, amount, price)
+    }
+
+    fn new(side: Side, pair: &str, amount: &str, price: &str) -> Self {
+        OrderBuilder {
+            side,
+            pair: pair.to_string(),
+            amount: amount.to_string(),
+            limit_price: price.to_string(),
+            account_id: String::new(),
+            order_id: None,
+            time_in_force: None,
+        }
+    }
+
+    pub fn account(mut self, account_id: &str) -> Self {
+        self.account_id = account_id.to_string();
+        self
+    }
+
+    pub fn order_id(mut self, order_id: &str) -> Self {
+        self.order_id = Some(order_id.to_string());
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = Some(time_in_force);
+        self
+    }
+
+    pub fn build(self) -> Result<Order, OrderBuilderError> {
+        let pair: TradingPair = self
+            .pair
+            .parse()
+            .map_err(|_| OrderBuilderError::InvalidPair(self.pair.clone()))?;
+        if !parse_amount(&self.amount).is_ok_and(|amount| amount > Decimal::ZERO) {
+            return Err(OrderBuilderError::InvalidAmount(self.amount));
+        }
+        if !parse_price(&self.limit_price).is_ok_and(|price| price > Decimal::ZERO) {
+            return Err(OrderBuilderError::InvalidPrice(self.limit_price));
+        }
+
+        Ok(Order {
+            type_op: Operation::Create,
+            account_id: self.account_id,
+            amount: self.amount,
+            order_id: self.order_id.unwrap_or_else(generate_order_id),
+            pair,
+            limit_price: self.limit_price,
+            side: self.side,
+            time_in_force: self.time_in_force,
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        })
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{Order, Trade};
+use std::fmt;
+use std::io::{Read, Write};
+
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(csv::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Csv(err) => write!(f, "CSV error: {}", err),
+            CsvError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<csv::Error> for CsvError {
+    fn from(err: csv::Error) -> Self {
+        CsvError::Csv(err)
+    }
+}
+
+impl From<std::io::Error> for CsvError {
+    fn from(err: std::io::Error) -> Self {
+        CsvError::Io(err)
+    }
+}
+
+// Reads orders from CSV with a header row. Columns are named like the JSON fields,
+// and the ones that are optional in JSON may be left out or left empty.
+pub fn orders_from_csv(reader: impl Read) -> Result<Vec<Order>, CsvError> {
+    csv::Reader::from_reader(reader)
+        .deserialize()
+        .map(|record| record.map_err(CsvError::from))
+        .collect()
+}
+
+// Writes trades as CSV with a header row of the JSON field names
+pub fn trades_to_csv(trades: &[Trade], writer: impl Write) -> Result<(), CsvError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for trade in trades {
+        writer.serialize(trade)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
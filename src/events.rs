@@ -0,0 +1,103 @@
+use crate::{OrderBookEntry, Side, Trade};
+use rust_decimal::Decimal;
+
+#[derive(Debug, Clone)]
+pub enum OrderBookEvent {
+    TradeExecuted(Trade),
+    OrderAdded(OrderBookEntry),
+    // Order id of an order that left the book, filled or cancelled
+    OrderRemoved(String),
+    // Order id and new resting amount
+    OrderModified(String, Decimal),
+    // Order id of an order cancelled rather than filled, whether by its owner, an
+    // OCO fill, expiry or the book itself
+    OrderCancelled(String),
+    // Side, price and new total amount of a price level the last operation changed,
+    // zero once the level is empty
+    DepthChanged(Side, Decimal, Decimal),
+    // Price that breached the circuit breaker, and the time matching resumes
+    CircuitBreakerTripped(Decimal, u64),
+    // Order id of a market order and the limit price it was capped at
+    MarketOrderProtected(String, Decimal),
+}
+
+#[cfg(not(feature = "broadcast"))]
+pub type EventReceiver = std::sync::mpsc::Receiver<OrderBookEvent>;
+
+#[cfg(feature = "broadcast")]
+pub type EventReceiver = tokio::sync::broadcast::Receiver<OrderBookEvent>;
+
+// Fans order book events out to every subscriber. Uses std channels by default and a
+// tokio broadcast channel with the `broadcast` feature.
+#[cfg(not(feature = "broadcast"))]
+#[derive(Debug, Default)]
+pub(crate) struct EventBus {
+    subscribers: Vec<std::sync::mpsc::Sender<OrderBookEvent>>,
+    held: Option<Vec<OrderBookEvent>>,
+}
+
+#[cfg(not(feature = "broadcast"))]
+impl EventBus {
+    pub(crate) fn subscribe(&mut self) -> EventReceiver {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn send(&mut self, event: OrderBookEvent) {
+        // Drop subscribers whose receiver has gone away
+        self.subscribers
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(feature = "broadcast")]
+const BROADCAST_CAPACITY: usize = 1024;
+
+#[cfg(feature = "broadcast")]
+#[derive(Debug, Default)]
+pub(crate) struct EventBus {
+    sender: Option<tokio::sync::broadcast::Sender<OrderBookEvent>>,
+    held: Option<Vec<OrderBookEvent>>,
+}
+
+#[cfg(feature = "broadcast")]
+impl EventBus {
+    pub(crate) fn subscribe(&mut self) -> EventReceiver {
+        self.sender
+            .get_or_insert_with(|| tokio::sync::broadcast::channel(BROADCAST_CAPACITY).0)
+            .subscribe()
+    }
+
+    fn send(&mut self, event: OrderBookEvent) {
+        // Sending only fails when nobody is subscribed
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+impl EventBus {
+    pub(crate) fn publish(&mut self, event: OrderBookEvent) {
+        match &mut self.held {
+            Some(held) => held.push(event),
+            None => self.send(event),
+        }
+    }
+
+    // Keeps events back until release or discard, for an operation that may be
+    // rolled back
+    pub(crate) fn hold(&mut self) {
+        self.held.get_or_insert_with(Vec::new);
+    }
+
+    pub(crate) fn release(&mut self) {
+        for event in self.held.take().into_iter().flatten() {
+            self.send(event);
+        }
+    }
+
+    pub(crate) fn discard(&mut self) {
+        self.held = None;
+    }
+}
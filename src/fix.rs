@@ -0,0 +1,206 @@
+use crate::{
+    NANOS_PER_MILLI, Operation, Order, OrderStatus, OrderType, Side, Trade, TradingPair,
+    get_current_timestamp, parse_amount, parse_price,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+
+// Field separator on the wire
+const SOH: char = '\x01';
+
+const TAG_ACCOUNT: u32 = 1;
+const TAG_CL_ORD_ID: u32 = 11;
+const TAG_MSG_TYPE: u32 = 35;
+const TAG_ORDER_QTY: u32 = 38;
+const TAG_ORD_TYPE: u32 = 40;
+const TAG_PRICE: u32 = 44;
+const TAG_SIDE: u32 = 54;
+const TAG_SYMBOL: u32 = 55;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixParseError {
+    // A field that isn't `tag=value`
+    Malformed(String),
+    // Tag of a required field the message doesn't have
+    MissingField(u32),
+    // Tag and value of a field that can't be used
+    InvalidField(u32, String),
+}
+
+impl fmt::Display for FixParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixParseError::Malformed(field) => write!(f, "malformed FIX field: {:?}", field),
+            FixParseError::MissingField(tag) => write!(f, "missing FIX field {}", tag),
+            FixParseError::InvalidField(tag, value) => {
+                write!(f, "invalid value for FIX field {}: {:?}", tag, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FixParseError {}
+
+// Reads a FIX 4.4 New Order Single (35=D) as sent on the wire, with SOH between
+// fields, into a CREATE order. See parse_new_order for the fields used.
+pub fn parse_fix44_new_order_single(msg: &str) -> Result<Order, FixParseError> {
+    parse_new_order(msg, SOH)
+}
+
+// Same as parse_fix44_new_order_single for messages written with `|` between
+// fields, the usual form in logs and from counterparties that don't send raw FIX
+pub fn parse_fix44_new_order(msg: &str) -> Result<Order, FixParseError> {
+    parse_new_order(msg, '|')
+}
+
+// Builds a FIX 4.4 execution report (35=8) for one fill, from the point of view of
+// the order on `side`, with header, body length and checksum
+pub(crate) fn execution_report(
+    trade: &Trade,
+    order_id: &str,
+    side: Side,
+    status: Option<&OrderStatus>,
+) -> String {
+    let ord_status = match status {
+        Some(OrderStatus::Filled) => "2",
+        Some(OrderStatus::Cancelled) => "4",
+        Some(OrderStatus::Rejected(_)) => "8",
+        _ => "1",
+    };
+    let side = match side {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    };
+    let fields = [
+        (35, "8".to_string()),
+        (37, order_id.to_string()),
+        (17, trade.trade_id.clone()),
+        (1003, trade.trade_id.clone()),
+        (150, "F".to_string()),
+        (39, ord_status.to_string()),
+        (55, trade.pair.to_string()),
+        (54, side.to_string()),
+        (44, trade.price.clone()),
+        (32, trade.amount.clone()),
+        (31, trade.price.clone()),
+        (60, utc_timestamp(trade.timestamp)),
+    ];
+
+    let body: String = fields
+        .iter()
+        .map(|(tag, value)| format!("{}={}{}", tag, value, SOH))
+        .collect();
+    let message = format!("8=FIX.4.4{}9={}{}{}", SOH, body.len(), SOH, body);
+    let checksum = message.bytes().map(u32::from).sum::<u32>() % 256;
+    format!("{}10={:03}{}", message, checksum, SOH)
+}
+
+// Uses ClOrdID (11) as the order id, Symbol (55), Side (54: 1 buy, 2 sell),
+// OrderQty (38), OrdType (40: 1 market, 2 limit) and Account (1). Price (44) is
+// required for limit orders. Other fields are ignored.
+fn parse_new_order(msg: &str, delimiter: char) -> Result<Order, FixParseError> {
+    let mut fields = HashMap::new();
+    for field in msg.split(delimiter).filter(|field| !field.is_empty()) {
+        let (tag, value) = field
+            .split_once('=')
+            .and_then(|(tag, value)| Some((tag.parse::<u32>().ok()?, value)))
+            .ok_or_else(|| FixParseError::Malformed(field.to_string()))?;
+        fields.insert(tag, value);
+    }
+    let required = |tag: u32| {
+        fields
+            .get(&tag)
+            .copied()
+            .ok_or(FixParseError::MissingField(tag))
+    };
+    let invalid = |tag: u32, value: &str| FixParseError::InvalidField(tag, value.to_string());
+
+    if let Some(msg_type) = fields.get(&TAG_MSG_TYPE)
+        && *msg_type != "D"
+    {
+        return Err(invalid(TAG_MSG_TYPE, msg_type));
+    }
+
+    let order_id = required(TAG_CL_ORD_ID)?;
+    let symbol = required(TAG_SYMBOL)?;
+    let pair: TradingPair = symbol.parse().map_err(|_| invalid(TAG_SYMBOL, symbol))?;
+    let side = match required(TAG_SIDE)? {
+        "1" => Side::Buy,
+        "2" => Side::Sell,
+        other => return Err(invalid(TAG_SIDE, other)),
+    };
+    let amount = required(TAG_ORDER_QTY)?;
+    if !parse_amount(amount).is_ok_and(|amount| amount > Decimal::ZERO) {
+        return Err(invalid(TAG_ORDER_QTY, amount));
+    }
+    let (order_type, limit_price) = match required(TAG_ORD_TYPE)? {
+        "1" => (OrderType::Market, String::new()),
+        "2" => {
+            let price = required(TAG_PRICE)?;
+            if parse_price(price).is_err() {
+                return Err(invalid(TAG_PRICE, price));
+            }
+            (OrderType::Limit, price.to_string())
+        }
+        other => return Err(invalid(TAG_ORD_TYPE, other)),
+    };
+
+    Ok(Order {
+        type_op: Operation::Create,
+        account_id: fields
+            .get(&TAG_ACCOUNT)
+            .map(|account| account.to_string())
+            .unwrap_or_default(),
+        amount: amount.to_string(),
+        order_id: order_id.to_string(),
+        pair,
+        limit_price,
+        side,
+        time_in_force: None,
+        order_type,
+        new_amount: None,
+        post_only: false,
+        expires_at_ms: None,
+        visible_amount: None,
+        peg: None,
+        trail_amount: None,
+        trail_pct: None,
+        sequence_number: 0,
+        status: OrderStatus::Open,
+        timestamp: get_current_timestamp(),
+    })
+}
+
+// UTCTimestamp (YYYYMMDD-HH:MM:SS.sss) for a time in nanoseconds since the epoch
+fn utc_timestamp(timestamp_ns: u64) -> String {
+    let millis = timestamp_ns / NANOS_PER_MILLI;
+    let seconds = millis / 1_000;
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let time = seconds % 86_400;
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+        millis % 1_000
+    )
+}
+
+// Gregorian (year, month, day) of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
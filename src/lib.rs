@@ -1,34 +1,497 @@
-use rust_decimal::Decimal;
+use lru::LruCache;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, MathematicalOps};
 use serde::{Deserialize, Serialize};
+use std::num::NonZeroUsize;
 use std::str::FromStr;
 use std::{
-    collections::BTreeMap,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
     time::{SystemTime, UNIX_EPOCH},
 };
 use uuid::Uuid;
+use xxhash_rust::xxh3::Xxh3;
+
+mod activity;
+#[cfg(feature = "async")]
+mod actor;
+#[cfg(feature = "async")]
+mod async_order_book;
+mod audit;
+mod builder;
+mod csv_io;
+mod events;
+mod fix;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod position;
+mod rate_limit;
+mod simulator;
+
+use activity::ActivityLog;
+#[cfg(feature = "async")]
+pub use actor::{ActorError, OrderBookHandle, spawn_actor};
+#[cfg(feature = "async")]
+pub use async_order_book::AsyncOrderBook;
+use audit::EMPTY_AUDIT_LOG;
+pub use audit::{AuditEntry, AuditLog};
+pub use builder::{OrderBuilder, OrderBuilderError};
+pub use csv_io::{CsvError, orders_from_csv, trades_to_csv};
+use events::EventBus;
+pub use events::{EventReceiver, OrderBookEvent};
+pub use fix::{FixParseError, parse_fix44_new_order, parse_fix44_new_order_single};
+#[cfg(feature = "metrics")]
+use metrics::Metrics;
+pub use position::{Position, positions_from_trades};
+use rate_limit::TokenBucket;
+pub use simulator::{FillSimulator, SimulatedClock};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSideError(String);
+
+impl fmt::Display for ParseSideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid side: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSideError {}
+
+impl FromStr for Side {
+    type Err = ParseSideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "BUY" => Ok(Side::Buy),
+            "SELL" => Ok(Side::Sell),
+            _ => Err(ParseSideError(s.to_string())),
+        }
+    }
+}
+
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Buy => write!(f, "BUY"),
+            Side::Sell => write!(f, "SELL"),
+        }
+    }
+}
+
+// Which side initiated a trade, as inferred by the tick rule
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TradeSide {
+    // Trades recorded before classification existed deserialize as buyer-initiated
+    #[default]
+    Buyer,
+    Seller,
+}
+
+// A trade's price against the last different trade price. A zero tick trades at the
+// same price as the trade before it and keeps the direction of the last move.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TickDirection {
+    UpTick,
+    DownTick,
+    // Also used for trades recorded before tick directions existed
+    #[default]
+    ZeroUpTick,
+    ZeroDownTick,
+}
+
+impl From<Side> for TradeSide {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Buy => TradeSide::Buyer,
+            Side::Sell => TradeSide::Seller,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Operation {
+    Create,
+    Delete,
+    Modify,
+    // Atomically replaces the resting order with the same id by this order
+    #[serde(rename = "CANCEL_REPLACE")]
+    CancelReplace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOperationError(String);
+
+impl fmt::Display for ParseOperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid operation: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseOperationError {}
+
+impl FromStr for Operation {
+    type Err = ParseOperationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "CREATE" => Ok(Operation::Create),
+            "DELETE" => Ok(Operation::Delete),
+            "MODIFY" => Ok(Operation::Modify),
+            "CANCEL_REPLACE" => Ok(Operation::CancelReplace),
+            _ => Err(ParseOperationError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Create => write!(f, "CREATE"),
+            Operation::Delete => write!(f, "DELETE"),
+            Operation::Modify => write!(f, "MODIFY"),
+            Operation::CancelReplace => write!(f, "CANCEL_REPLACE"),
+        }
+    }
+}
+
+// A market such as BTC/USDC. Both currencies are 2-10 ASCII letters and differ.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TradingPair {
+    base: String,
+    quote: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTradingPairError(String);
+
+impl fmt::Display for ParseTradingPairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid trading pair: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseTradingPairError {}
+
+impl TradingPair {
+    pub fn new(base: &str, quote: &str) -> Result<Self, ParseTradingPairError> {
+        let valid_currency =
+            |c: &str| (2..=10).contains(&c.len()) && c.chars().all(|ch| ch.is_ascii_alphabetic());
+        if !valid_currency(base) || !valid_currency(quote) || base == quote {
+            return Err(ParseTradingPairError(format!("{}/{}", base, quote)));
+        }
+
+        Ok(TradingPair {
+            base: base.to_string(),
+            quote: quote.to_string(),
+        })
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
+}
+
+impl FromStr for TradingPair {
+    type Err = ParseTradingPairError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (base, quote) = s
+            .split_once('/')
+            .ok_or_else(|| ParseTradingPairError(s.to_string()))?;
+        TradingPair::new(base, quote).map_err(|_| ParseTradingPairError(s.to_string()))
+    }
+}
+
+impl fmt::Display for TradingPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+impl TryFrom<String> for TradingPair {
+    type Error = ParseTradingPairError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TradingPair> for String {
+    fn from(pair: TradingPair) -> Self {
+        pair.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    // Good-till-cancelled: any unfilled remainder rests in the book
+    #[default]
+    Gtc,
+    // Immediate-or-cancel: fill what is possible, cancel the remainder
+    Ioc,
+    // Fill-or-kill: fill the whole amount immediately or cancel the order
+    Fok,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrderType {
+    #[default]
+    Limit,
+    // Sweeps the opposite side regardless of price; never rests in the book
+    Market,
+}
+
+// Whether a book matches orders as they arrive or collects them for an auction
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AuctionMode {
+    #[default]
+    Continuous,
+    // Orders rest without matching, even when they cross, until run_auction
+    Opening,
+}
+
+// Reference price a pegged order follows when OrderBook::repeg_all runs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PegType {
+    // Midpoint of the best bid and best ask
+    MidPeg,
+    BidPeg,
+    AskPeg,
+}
+
+// How an incoming order's amount is shared between resting orders at one price level
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum MatchingAlgorithm {
+    // Oldest order first
+    #[default]
+    Fifo,
+    // In proportion to each order's size
+    ProRata,
+}
+
+// What to do when an incoming order would trade against a resting order from the
+// same account
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    #[default]
+    Allow,
+    CancelTaker,
+    CancelMaker,
+    CancelBoth,
+}
+
+// Fee rates charged on the notional value of each trade. A missing rate means no fee.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeSchedule {
+    pub fee_rate_maker: Option<Decimal>,
+    pub fee_rate_taker: Option<Decimal>,
+}
+
+impl FeeSchedule {
+    fn fees(&self, price: Decimal, amount: Decimal) -> (Decimal, Decimal) {
+        let notional = amount * price;
+        (
+            notional * self.fee_rate_maker.unwrap_or(Decimal::ZERO),
+            notional * self.fee_rate_taker.unwrap_or(Decimal::ZERO),
+        )
+    }
+}
+
+// Halts matching for `cooldown_ms` when a trade would execute more than
+// `max_move_pct` percent away from `reference_price`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub reference_price: Decimal,
+    pub max_move_pct: Decimal,
+    pub cooldown_ms: u64,
+}
+
+impl CircuitBreaker {
+    fn trips_at(&self, price: Decimal) -> bool {
+        let change = (price - self.reference_price).abs();
+        change * Decimal::ONE_HUNDRED > self.max_move_pct * self.reference_price
+    }
+}
+
+// Inclusive range of limit prices the book accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceBand {
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
-    pub type_op: String,
+    pub type_op: Operation,
     pub account_id: String,
     pub amount: String,
     pub order_id: String,
-    pub pair: String,
+    pub pair: TradingPair,
+    #[serde(default)]
     pub limit_price: String,
-    pub side: String,
-    #[serde(skip)]
+    pub side: Side,
+    // None falls back to the book's default_time_in_force
+    #[serde(default)]
+    pub time_in_force: Option<TimeInForce>,
+    #[serde(default)]
+    pub order_type: OrderType,
+    // Replacement amount for a MODIFY operation
+    #[serde(default)]
+    pub new_amount: Option<String>,
+    // Reject the order instead of letting it take liquidity
+    #[serde(default)]
+    pub post_only: bool,
+    // Good-till-date: the order is dropped from the book once this time has passed
+    #[serde(default)]
+    pub expires_at_ms: Option<u64>,
+    // Iceberg order: only this much is displayed and matched at a time, replenished
+    // from the rest of the amount whenever the displayed slice fills
+    #[serde(default)]
+    pub visible_amount: Option<Decimal>,
+    // Pegged order: limit_price is where it enters, and repeg_all moves it after that
+    #[serde(default)]
+    pub peg: Option<PegType>,
+    // Trailing stop: the order waits off the book with a stop price this far behind
+    // the market (trail_pct is a percentage) and is sent as a market order once the
+    // price comes back to the stop; see OrderBook::update_trailing_stops
+    #[serde(default)]
+    pub trail_amount: Option<Decimal>,
+    #[serde(default)]
+    pub trail_pct: Option<Decimal>,
+    // Assigned by the book each time the order joins a price level; orders at the
+    // same price trade in sequence order regardless of their timestamps
+    #[serde(default)]
+    pub sequence_number: u64,
+    // Maintained by the book while the order rests; see OrderBook::get_order_status
+    #[serde(default)]
+    pub status: OrderStatus,
+    #[serde(default)]
     pub timestamp: u64,
 }
 
+impl Order {
+    // Amount times limit price. Errors for market orders, which have no limit price,
+    // and if either field doesn't parse.
+    pub fn notional_value(&self) -> Result<Decimal, rust_decimal::Error> {
+        Ok(Decimal::from_str(&self.amount)? * Decimal::from_str(&self.limit_price)?)
+    }
+
+    // Whether the order would trade at least partly on arrival: a buy at or above
+    // the best ask, a sell at or below the best bid, or a market order against any
+    // opposite liquidity. False if the limit price doesn't parse. Doesn't look at
+    // halts, post-only or self-trade prevention.
+    pub fn is_marketable(&self, book: &OrderBook) -> bool {
+        book.would_match(self).unwrap_or(false)
+    }
+
+    // Worst acceptable execution price, or None for a market order
+    fn price_limit(&self) -> Result<Option<Decimal>, OrderBookError> {
+        match self.order_type {
+            OrderType::Limit => parse_price(&self.limit_price).map(Some),
+            OrderType::Market => Ok(None),
+        }
+    }
+
+    // Checks that don't depend on the state of the book
+    fn validate(&self) -> Result<(), OrderBookError> {
+        if self.order_id.trim().is_empty() {
+            return Err(OrderBookError::InvalidOrderId(self.order_id.clone()));
+        }
+
+        match self.type_op {
+            Operation::Create | Operation::CancelReplace => {
+                if parse_amount(&self.amount)? <= Decimal::ZERO {
+                    return Err(OrderBookError::InvalidAmount(self.amount.clone()));
+                }
+                if self
+                    .price_limit()?
+                    .is_some_and(|price| price <= Decimal::ZERO)
+                {
+                    return Err(OrderBookError::InvalidPrice(self.limit_price.clone()));
+                }
+            }
+            Operation::Modify => {
+                if let Some(amount) = &self.new_amount
+                    && parse_amount(amount)? < Decimal::ZERO
+                {
+                    return Err(OrderBookError::InvalidAmount(amount.clone()));
+                }
+                if !self.limit_price.is_empty() && parse_price(&self.limit_price)? <= Decimal::ZERO
+                {
+                    return Err(OrderBookError::InvalidPrice(self.limit_price.clone()));
+                }
+            }
+            Operation::Delete => {}
+        }
+
+        Ok(())
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at_ms
+            .is_some_and(|expires_at| expires_at < now / NANOS_PER_MILLI)
+    }
+}
+
+// e.g. `CREATE BUY 1.5 BTC/USDC @ 100 (order 7, account 2)`, with MARKET in place
+// of the price for market orders
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} @ ",
+            self.type_op, self.side, self.amount, self.pair
+        )?;
+        match self.order_type {
+            OrderType::Limit => write!(f, "{}", self.limit_price)?,
+            OrderType::Market => write!(f, "MARKET")?,
+        }
+        write!(f, " (order {}, account {})", self.order_id, self.account_id)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBookEntry {
     pub order_id: String,
     pub account_id: String,
-    pub pair: String,
-    pub side: String,
+    pub pair: TradingPair,
+    pub side: Side,
     pub amount: String,
     pub price: String,
     pub timestamp: u64,
+    // Queue position within the price level, see Order::sequence_number
+    #[serde(default)]
+    pub sequence_number: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,10 +499,474 @@ pub struct Trade {
     pub trade_id: String,
     pub taker_order_id: String,
     pub maker_order_id: String,
-    pub pair: String,
+    #[serde(default)]
+    pub taker_account_id: String,
+    #[serde(default)]
+    pub maker_account_id: String,
+    pub pair: TradingPair,
     pub price: String,
     pub amount: String,
     pub timestamp: u64,
+    #[serde(default)]
+    pub maker_fee: Decimal,
+    #[serde(default)]
+    pub taker_fee: Decimal,
+    // Tick rule classification: an uptick is buyer-initiated, a downtick
+    // seller-initiated, and an unchanged price repeats the previous trade's side
+    #[serde(default)]
+    pub aggressor: TradeSide,
+    #[serde(default)]
+    pub tick_direction: TickDirection,
+    // Side of the taker order. None for trades recorded before it was kept.
+    #[serde(default)]
+    pub taker_side: Option<Side>,
+}
+
+impl Trade {
+    // Price times amount. Errors if either field doesn't parse or the product
+    // overflows.
+    pub fn gross_value(&self) -> Result<Decimal, rust_decimal::Error> {
+        let price = Decimal::from_str(&self.price)?;
+        let amount = Decimal::from_str(&self.amount)?;
+        price
+            .checked_mul(amount)
+            .ok_or(rust_decimal::Error::ExceedsMaximumPossibleValue)
+    }
+
+    // Gross value less a fee charged as a fraction of it, so 0.001 is 10 bps
+    pub fn net_value(&self, fee_rate: Decimal) -> Result<Decimal, rust_decimal::Error> {
+        self.gross_value()?
+            .checked_mul(Decimal::ONE - fee_rate)
+            .ok_or(rust_decimal::Error::ExceedsMaximumPossibleValue)
+    }
+}
+
+// Where an order stands in its lifecycle
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderStatus {
+    // Resting in the book without having traded
+    #[default]
+    Open,
+    // Resting in the book after trading part of its amount
+    PartiallyFilled {
+        filled: Decimal,
+    },
+    Filled,
+    // Left the book, or never rested, with part of its amount unfilled
+    Cancelled,
+    // Failed validation when it was submitted
+    Rejected(OrderBookError),
+}
+
+impl OrderStatus {
+    // Status of a resting order once another `amount` of it has traded
+    fn with_fill(&self, amount: Decimal) -> OrderStatus {
+        let filled = match self {
+            OrderStatus::PartiallyFilled { filled } => *filled + amount,
+            _ => amount,
+        };
+        if filled > Decimal::ZERO {
+            OrderStatus::PartiallyFilled { filled }
+        } else {
+            OrderStatus::Open
+        }
+    }
+}
+
+// How a single order was handled by process_order. remaining_amount is the part
+// left unfilled: still working when the order is open, dropped when cancelled.
+#[derive(Debug, Clone)]
+pub struct FillEvent {
+    pub order_id: String,
+    pub filled_amount: Decimal,
+    pub remaining_amount: Decimal,
+    pub trades: Vec<Trade>,
+    pub status: OrderStatus,
+}
+
+// One-cancels-other: two CREATE orders where any fill on one cancels the other
+#[derive(Debug, Clone)]
+pub struct OcoOrderPair {
+    pub take_profit: Order,
+    pub stop_loss: Order,
+}
+
+#[derive(Debug, Clone)]
+pub struct OcoResult {
+    pub take_profit: FillEvent,
+    // None if the take-profit traded on entry, which settles the pair before the
+    // stop-loss is placed
+    pub stop_loss: Option<FillEvent>,
+    pub trades: Vec<Trade>,
+}
+
+// Criteria for OrderBook::filter_trades. Every criterion that is set must match.
+// The time range is [from_ms, to_ms).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TradeFilter {
+    pub pair: Option<String>,
+    // Matches trades where the account was either the taker or the maker
+    pub account_id: Option<String>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub from_ms: Option<u64>,
+    pub to_ms: Option<u64>,
+}
+
+impl TradeFilter {
+    fn matches(&self, trade: &Trade) -> bool {
+        let price = Decimal::from_str(&trade.price).ok();
+        self.pair
+            .as_ref()
+            .is_none_or(|pair| trade.pair.to_string() == *pair)
+            && self.account_id.as_ref().is_none_or(|account_id| {
+                trade.taker_account_id == *account_id || trade.maker_account_id == *account_id
+            })
+            && self
+                .min_price
+                .is_none_or(|min| price.is_some_and(|price| price >= min))
+            && self
+                .max_price
+                .is_none_or(|max| price.is_some_and(|price| price <= max))
+            && self
+                .from_ms
+                .is_none_or(|from| trade.timestamp >= from.saturating_mul(NANOS_PER_MILLI))
+            && self
+                .to_ms
+                .is_none_or(|to| trade.timestamp < to.saturating_mul(NANOS_PER_MILLI))
+    }
+}
+
+// Serializable copy of a book's resting orders, trade history and trading rules.
+// Orders are stored bids then asks, each in price order and then time priority
+// within a level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBookSnapshot {
+    pub orders: Vec<Order>,
+    pub trades: Vec<Trade>,
+    // Defaults for snapshots taken before the rules were kept
+    #[serde(default)]
+    pub config: OrderBookConfig,
+    #[serde(default)]
+    pub fee_schedule: FeeSchedule,
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+// Incremental change between two snapshots of a book
+#[derive(Debug, Clone)]
+pub enum OrderBookDelta {
+    // New order, appended to the tail of its price level
+    Add(OrderBookEntry),
+    // Order id of an order that left the book
+    Remove(String),
+    // Order id and reduced amount; the order keeps its queue position
+    Modify(String, Decimal),
+}
+
+impl OrderBookSnapshot {
+    // Applies deltas produced by `OrderBook::diff` to bring this snapshot up to date
+    pub fn apply(&mut self, deltas: &[OrderBookDelta]) {
+        let mut order_book = OrderBook::default().with_orders(std::mem::take(&mut self.orders));
+
+        for delta in deltas {
+            match delta {
+                OrderBookDelta::Add(entry) => {
+                    let _ = order_book.add_order(order_from_entry(entry));
+                }
+                OrderBookDelta::Remove(order_id) => {
+                    order_book.take_order(order_id);
+                }
+                OrderBookDelta::Modify(order_id, amount) => {
+                    if let Some(order) = order_book.get_order_by_id_mut(order_id) {
+                        order.amount = amount.to_string();
+                    }
+                }
+            }
+        }
+
+        self.orders = order_book.snapshot().orders;
+    }
+
+    // Same as OrderBook::compute_checksum for the book this snapshot restores to
+    pub fn checksum(&self) -> u64 {
+        orders_checksum(&self.orders)
+    }
+}
+
+// Aggregated view of a single price level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: Decimal,
+    pub total_amount: Decimal,
+    pub order_count: usize,
+    pub cumulative_amount: Decimal, // Total amount at this and all better price levels
+}
+
+// L2 snapshot: bids sorted highest-first, asks lowest-first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBookDepth {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+// Summary of the trades in a recent window, see OrderBook::rolling_window_stats.
+// Everything is zero when the window has no trades.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollingStats {
+    pub trade_count: usize,
+    pub total_volume: Decimal,
+    pub avg_price: Decimal,    // Volume-weighted
+    pub price_stddev: Decimal, // Population standard deviation of the trade prices
+    pub buy_volume: Decimal,   // Traded with a buyer as aggressor
+    pub sell_volume: Decimal,  // Traded with a seller as aggressor
+}
+
+// Traded volume at one price (or price bucket), see OrderBook::volume_profile
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumeProfileLevel {
+    pub buy_volume: Decimal,  // Traded with a buyer as aggressor
+    pub sell_volume: Decimal, // Traded with a seller as aggressor
+    pub trade_count: usize,
+}
+
+// Outcome of matching an order against the book without changing it
+#[derive(Debug, Clone)]
+pub struct SimulationResult {
+    pub estimated_trades: Vec<Trade>,
+    pub unfilled_amount: Decimal,
+    pub average_fill_price: Option<Decimal>,
+}
+
+// A trailing stop waiting for its trigger. The stop price is None until the book
+// has seen a price to trail.
+#[derive(Debug, Clone)]
+pub struct TrailSpec {
+    pub order: Order,
+    pub stop_price: Option<Decimal>,
+}
+
+impl TrailSpec {
+    // Where the stop sits for a market at `price`: below it for a sell, above for a buy
+    fn stop_for(&self, price: Decimal) -> Decimal {
+        let offset = match (self.order.trail_amount, self.order.trail_pct) {
+            (Some(amount), _) => amount,
+            (None, Some(pct)) => price * pct / Decimal::ONE_HUNDRED,
+            (None, None) => Decimal::ZERO,
+        };
+        match self.order.side {
+            Side::Sell => price - offset,
+            Side::Buy => price + offset,
+        }
+    }
+
+    // A sell stop only ever moves up and a buy stop only ever moves down
+    fn trails(&self, stop_price: Decimal) -> bool {
+        match (self.stop_price, self.order.side) {
+            (None, _) => true,
+            (Some(current), Side::Sell) => stop_price > current,
+            (Some(current), Side::Buy) => stop_price < current,
+        }
+    }
+
+    fn is_triggered(&self, price: Decimal) -> bool {
+        match (self.stop_price, self.order.side) {
+            (None, _) => false,
+            (Some(stop_price), Side::Sell) => price <= stop_price,
+            (Some(stop_price), Side::Buy) => price >= stop_price,
+        }
+    }
+}
+
+// Outcome of OrderBook::run_auction. clearing_price is None when no orders crossed.
+#[derive(Debug, Clone)]
+pub struct AuctionResult {
+    pub clearing_price: Option<Decimal>,
+    pub volume: Decimal,
+    pub trades: Vec<Trade>,
+}
+
+// OHLCV bar covering [open_time, open_time + interval)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub trade_count: usize,
+}
+
+// Why trading on a book was suspended with OrderBook::halt_trading
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HaltReason {
+    CircuitBreaker,
+    ScheduledMaintenance,
+    Regulatory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderBookError {
+    InvalidAmount(String),
+    InvalidPrice(String),
+    InvalidOrderId(String),
+    UnknownOrderId(String),
+    DuplicateOrderId(String),
+    PostOnlyRejected(String),
+    PriceOutOfBand(String),
+    InvalidTickSize(String),
+    InvalidLotSize(String),
+    // Order id of an order submitted while trading is halted
+    TradingHalted(String),
+    // Order id of an order that would take its account past set_account_limit
+    ExposureLimitExceeded(String),
+    // Order id of an order from an account over its set_account_rate_limit
+    RateLimitExceeded(String),
+    OrderTooSmall {
+        minimum: Decimal,
+        submitted: Decimal,
+    },
+    OrderTooLarge {
+        maximum: Decimal,
+        submitted: Decimal,
+    },
+    // What is wrong with a config or setting the book was given
+    InvalidConfig(String),
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderBookError::InvalidAmount(amount) => write!(f, "invalid amount: {}", amount),
+            OrderBookError::InvalidPrice(price) => write!(f, "invalid price: {}", price),
+            OrderBookError::InvalidOrderId(id) => write!(f, "invalid order id: {:?}", id),
+            OrderBookError::UnknownOrderId(id) => write!(f, "unknown order id: {}", id),
+            OrderBookError::DuplicateOrderId(id) => write!(f, "duplicate order id: {}", id),
+            OrderBookError::PostOnlyRejected(id) => {
+                write!(f, "post-only order {} would take liquidity", id)
+            }
+            OrderBookError::PriceOutOfBand(price) => {
+                write!(f, "price {} is outside the allowed band", price)
+            }
+            OrderBookError::InvalidTickSize(price) => {
+                write!(f, "price {} is not a multiple of the tick size", price)
+            }
+            OrderBookError::InvalidLotSize(amount) => {
+                write!(f, "amount {} is not a multiple of the lot size", amount)
+            }
+            OrderBookError::TradingHalted(id) => {
+                write!(f, "order {} rejected: trading is halted", id)
+            }
+            OrderBookError::ExposureLimitExceeded(id) => {
+                write!(
+                    f,
+                    "order {} would exceed its account's open notional limit",
+                    id
+                )
+            }
+            OrderBookError::RateLimitExceeded(id) => {
+                write!(f, "order {} rejected: account is over its rate limit", id)
+            }
+            OrderBookError::OrderTooSmall { minimum, submitted } => {
+                write!(
+                    f,
+                    "amount {} is below the minimum of {}",
+                    submitted, minimum
+                )
+            }
+            OrderBookError::OrderTooLarge { maximum, submitted } => {
+                write!(
+                    f,
+                    "amount {} is above the maximum of {}",
+                    submitted, maximum
+                )
+            }
+            OrderBookError::InvalidConfig(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OrderBookError {}
+
+// Internal inconsistency found by OrderBook::validate_invariants
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    // Best bid and best ask, where the bid is at or above the ask
+    CrossedBook(Decimal, Decimal),
+    // Order id and the side of the book it rests on
+    WrongSide(String, Side),
+    // Order id and its amount
+    NonPositiveAmount(String, String),
+    // Order resting in the book but missing from the active id set
+    UntrackedOrder(String),
+    // Id in the active id set with no resting order
+    MissingOrder(String),
+}
+
+// First malformed order in a batch, by position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchError {
+    pub index: usize,
+    pub order_id: String,
+    pub error: OrderBookError,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "order {} at index {} is malformed: {}",
+            self.order_id, self.index, self.error
+        )
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+// Per-book trading rules, fixed when the book is created
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct OrderBookConfig {
+    // Most price levels kept on each side of the book
+    pub max_depth: Option<usize>,
+    // Prices must be a multiple of this
+    pub tick_size: Option<Decimal>,
+    // Amounts must be a multiple of this
+    pub lot_size: Option<Decimal>,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub matching_algorithm: MatchingAlgorithm,
+    // Applies to orders that don't set a time in force
+    pub default_time_in_force: TimeInForce,
+    // Caps market orders at this fraction through the best opposite price (0.05 for
+    // 5%); they trade as IOC limit orders at the cap and any remainder is cancelled
+    pub market_order_protection_pct: Option<Decimal>,
+    // Bounds on the amount of a new order, inclusive
+    pub min_order_size: Option<Decimal>,
+    pub max_order_size: Option<Decimal>,
+    // Decimal places prices and amounts are written with in book output, events and
+    // format_trade, rounding if needed. Stored trades keep the exact values. None
+    // writes them as calculated.
+    pub price_precision: Option<u32>,
+    pub amount_precision: Option<u32>,
+}
+
+impl OrderBookConfig {
+    // Tick and lot sizes are divided by, so they must be positive
+    fn validate(&self) -> Result<(), OrderBookError> {
+        check_increment("tick size", self.tick_size)?;
+        check_increment("lot size", self.lot_size)
+    }
+}
+
+fn check_increment(name: &str, increment: Option<Decimal>) -> Result<(), OrderBookError> {
+    match increment {
+        Some(increment) if increment <= Decimal::ZERO => Err(OrderBookError::InvalidConfig(
+            format!("{} must be positive, got {}", name, increment),
+        )),
+        _ => Ok(()),
+    }
 }
 
 #[derive(Debug)]
@@ -47,297 +974,3341 @@ pub struct OrderBook {
     pub bids: BTreeMap<Decimal, Vec<Order>>, // Buy orders, sorted by price in descending order
     pub asks: BTreeMap<Decimal, Vec<Order>>, // Sell orders, sorted by price in ascending order
     pub trades: Vec<Trade>,
+    order_index: HashMap<String, (Side, Decimal)>, // Order id -> location of the resting order
+    account_orders: HashMap<String, HashSet<String>>, // Account id -> ids of its resting orders
+    last_trade_price: Option<Decimal>,
+    last_aggressor: Option<TradeSide>, // Carried forward when a trade doesn't move the price
+    session_high: Option<Decimal>,
+    session_low: Option<Decimal>,
+    session_volume: Decimal,
+    config: OrderBookConfig,
+    events: EventBus,
+    order_log: Vec<Order>, // Every order process_order accepted, in arrival order
+    max_order_log: Option<usize>, // Oldest logged orders are evicted beyond this many
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    fee_schedule: FeeSchedule,
+    account_positions: HashMap<String, HashMap<String, Position>>, // Account id -> pair -> position
+    iceberg_slices: HashMap<String, Decimal>, // Iceberg order id -> unfilled part of its displayed slice
+    circuit_breaker: Option<CircuitBreaker>,
+    halted_until: Option<u64>, // Matching is suspended until this time after a trip
+    price_band: Option<PriceBand>,
+    sequence_number: u64, // Last sequence number handed to a resting order
+    max_trade_history: Option<usize>, // Oldest trades are evicted beyond this many
+    trade_index: HashMap<String, usize>, // Trade id -> position in the history, counting evicted trades
+    evicted_trades: usize,               // Trades dropped from the front of the history
+    bid_count: usize,                    // Resting buy orders
+    ask_count: usize,                    // Resting sell orders
+    active_order_ids: HashSet<String>,   // Ids of every resting order
+    seen_order_ids: Option<LruCache<String, ()>>, // Recently created order ids, for dropping redeliveries
+    closed_orders: HashMap<String, OrderStatus>, // Order id -> final status once it has left the book
+    audit_log: Option<AuditLog>,                 // Set by with_audit_log
+    session_number: u32, // Incremented by reset at the end of each trading day
+    oco_links: HashMap<String, String>, // OCO order id -> id of the order it cancels when it fills
+    halted_since: Option<(u64, HaltReason)>, // Set by halt_trading: when (ns) and why
+    auction_mode: AuctionMode, // Opening collects orders without matching until run_auction
+    trailing_stop_orders: Vec<(String, TrailSpec)>, // Off-book until update_trailing_stops fires them
+    account_limits: HashMap<String, Decimal>,       // Account id -> max open notional
+    rate_limits: HashMap<String, TokenBucket>,      // Account id -> order token bucket
+    activity: ActivityLog,                          // Recent submissions, for the rate queries
+    // Price levels changed by the operation in progress, published as DepthChanged
+    // once it completes
+    changed_levels: Vec<(Side, Decimal)>,
+    mark_prices: HashMap<String, Decimal>, // Pair -> mark price for unrealized P&L
+    simulated_time: Option<u64>, // Time (ns) used in place of the system clock, see FillSimulator
+}
+
+// Result of walking the book for an incoming order
+struct MatchOutcome {
+    trades: Vec<Trade>,
+    // Amount of the incoming order that traded
+    filled: Decimal,
+    // The incoming order's unfilled remainder must not rest in the book
+    cancel_remainder: bool,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::with_config(OrderBookConfig::default())
+    }
 }
 
 impl OrderBook {
-    pub fn new() -> Self {
+    // Fails if the config has a tick or lot size that isn't positive
+    pub fn new(config: OrderBookConfig) -> Result<Self, OrderBookError> {
+        config.validate()?;
+        Ok(Self::with_config(config))
+    }
+
+    fn with_config(config: OrderBookConfig) -> Self {
         OrderBook {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             trades: Vec::new(),
+            order_index: HashMap::new(),
+            account_orders: HashMap::new(),
+            last_trade_price: None,
+            last_aggressor: None,
+            session_high: None,
+            session_low: None,
+            session_volume: Decimal::ZERO,
+            config,
+            events: EventBus::default(),
+            order_log: Vec::new(),
+            max_order_log: None,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
+            fee_schedule: FeeSchedule::default(),
+            account_positions: HashMap::new(),
+            iceberg_slices: HashMap::new(),
+            circuit_breaker: None,
+            halted_until: None,
+            price_band: None,
+            sequence_number: 0,
+            max_trade_history: None,
+            trade_index: HashMap::new(),
+            evicted_trades: 0,
+            bid_count: 0,
+            ask_count: 0,
+            active_order_ids: HashSet::new(),
+            seen_order_ids: None,
+            closed_orders: HashMap::new(),
+            audit_log: None,
+            session_number: 0,
+            oco_links: HashMap::new(),
+            halted_since: None,
+            auction_mode: AuctionMode::default(),
+            trailing_stop_orders: Vec::new(),
+            account_limits: HashMap::new(),
+            rate_limits: HashMap::new(),
+            activity: ActivityLog::default(),
+            changed_levels: Vec::new(),
+            mark_prices: HashMap::new(),
+            simulated_time: None,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                order_id = %order.order_id,
+                pair = %order.pair,
+                side = %order.side,
+                amount = %order.amount,
+                price = %order.limit_price,
+            )
+        )
+    )]
+    pub fn process_order(
+        &mut self,
+        mut order: Order,
+    ) -> Result<(FillEvent, Vec<Trade>), OrderBookError> {
+        // A CREATE delivered more than once is only processed the first time
+        let dedup_id = match &mut self.seen_order_ids {
+            Some(seen) if order.type_op == Operation::Create => {
+                if seen.contains(&order.order_id) {
+                    let fill = FillEvent {
+                        order_id: order.order_id.clone(),
+                        filled_amount: Decimal::ZERO,
+                        remaining_amount: self
+                            .resting_amount(&order.order_id)
+                            .unwrap_or(Decimal::ZERO),
+                        trades: Vec::new(),
+                        status: self.get_order_status(&order.order_id).unwrap_or_default(),
+                    };
+                    return Ok((fill, Vec::new()));
+                }
+                Some(order.order_id.clone())
+            }
+            _ => None,
+        };
+
+        // Logged once accepted, since halts and rate limits aren't replayed
+        let logged = order.clone();
+        if order.type_op == Operation::Create {
+            let amount = parse_amount(&order.amount).unwrap_or_default();
+            self.activity
+                .record_arrival(&order.account_id, amount, self.now());
+        }
+
+        if order.order_id.trim().is_empty() {
+            return Err(OrderBookError::InvalidOrderId(order.order_id));
+        }
+
+        #[cfg(feature = "metrics")]
+        let (started, pair) = {
+            self.metrics.order_received(&order);
+            (std::time::Instant::now(), order.pair.to_string())
+        };
+
+        // Unfilled amount the order is working with, so the fill event can report
+        // what is left once the operation has run
+        let order_id = order.order_id.clone();
+        let new_amount = order
+            .new_amount
+            .as_deref()
+            .and_then(|amount| parse_amount(amount).ok());
+        // An amend to zero cancels the order, and is reported like a delete
+        let cancelled = match order.type_op {
+            Operation::Delete => true,
+            Operation::Modify => new_amount.is_some_and(|amount| amount.is_zero()),
+            Operation::Create | Operation::CancelReplace => false,
+        };
+        let requested = match order.type_op {
+            Operation::Create | Operation::CancelReplace => parse_amount(&order.amount).ok(),
+            Operation::Modify if !cancelled => {
+                new_amount.or_else(|| self.resting_amount(&order_id))
+            }
+            Operation::Modify | Operation::Delete => self.resting_amount(&order_id),
+        }
+        .unwrap_or(Decimal::ZERO);
+        let audit = self.audit_log.is_some().then(|| {
+            (
+                order.account_id.clone(),
+                self.resting_entry(&order_id),
+                self.now(),
+            )
+        });
+
+        let type_op = order.type_op;
+        if matches!(type_op, Operation::Create | Operation::CancelReplace) {
+            order.status = OrderStatus::Open;
+        }
+        let result = match type_op {
+            // Only cancellations are accepted during a halt
+            _ if self.halted_since.is_some() && type_op != Operation::Delete => {
+                Err(OrderBookError::TradingHalted(order.order_id))
+            }
+            // Cancellations are never rate limited, so an account can always get out
+            _ if type_op != Operation::Delete && !self.take_rate_token(&order.account_id) => {
+                Err(OrderBookError::RateLimitExceeded(order.order_id))
+            }
+            Operation::Create => self
+                .check_exposure(&order)
+                .and_then(|_| self.create_order(order)),
+            Operation::Delete => self.remove_order(&order).map(|_| Vec::new()),
+            Operation::Modify => self.modify_order(order),
+            Operation::CancelReplace => self
+                .check_exposure(&order)
+                .and_then(|_| self.cancel_replace_order(order)),
+        };
+        let mut new_trades = match result {
+            Ok(trades) => trades,
+            // A rejected amend or replacement leaves the resting order as it was, and
+            // a duplicate id belongs to the order already using it
+            Err(err) => {
+                if type_op == Operation::Create
+                    && !matches!(err, OrderBookError::DuplicateOrderId(_))
+                {
+                    self.closed_orders
+                        .insert(order_id, OrderStatus::Rejected(err.clone()));
+                }
+                self.publish_depth_changes();
+                return Err(err);
+            }
+        };
+        self.push_order_log(logged);
+
+        // Add new trades to the trade history
+        self.record_trades(&mut new_trades);
+        self.cancel_oco_siblings(&new_trades);
+        if type_op == Operation::Delete
+            && let Some(sibling) = self.oco_links.remove(&order_id)
+        {
+            self.oco_links.remove(&sibling);
+        }
+        self.publish_depth_changes();
+
+        #[cfg(feature = "metrics")]
+        self.metrics.order_processed(
+            &pair,
+            &new_trades,
+            started.elapsed(),
+            self.bids.len(),
+            self.asks.len(),
+        );
+
+        if let (Some(order_id), Some(seen)) = (dedup_id, &mut self.seen_order_ids) {
+            seen.put(order_id, ());
+        }
+
+        if let Some((account_id, before_state, timestamp_ns)) = audit {
+            let after_state = self.resting_entry(&order_id);
+            if let Some(audit_log) = &mut self.audit_log {
+                audit_log.record(AuditEntry {
+                    timestamp_ns,
+                    operation: type_op,
+                    order_id: order_id.clone(),
+                    account_id,
+                    before_state,
+                    after_state,
+                    resulting_trades: new_trades
+                        .iter()
+                        .map(|trade| trade.trade_id.clone())
+                        .collect(),
+                });
+            }
+        }
+
+        let fill = self.fill_event(&order_id, requested, new_trades.clone(), cancelled);
+        if !self.active_order_ids.contains(&order_id) && self.trailing_stop(&order_id).is_none() {
+            self.closed_orders.insert(order_id, fill.status.clone());
+        }
+        Ok((fill, new_trades))
+    }
+
+    // Enters both orders of an OCO pair and links them so that a fill on either one
+    // cancels the other. If the stop-loss is rejected the take-profit is withdrawn,
+    // so the pair is placed whole or not at all.
+    pub fn process_oco(&mut self, oco: OcoOrderPair) -> Result<OcoResult, OrderBookError> {
+        let OcoOrderPair {
+            take_profit,
+            stop_loss,
+        } = oco;
+        if take_profit.order_id == stop_loss.order_id {
+            return Err(OrderBookError::DuplicateOrderId(stop_loss.order_id));
+        }
+        let take_profit_id = take_profit.order_id.clone();
+        let stop_loss_id = stop_loss.order_id.clone();
+
+        let (mut take_profit, mut trades) = self.process_order(take_profit)?;
+        if !take_profit.trades.is_empty() {
+            return Ok(OcoResult {
+                take_profit,
+                stop_loss: None,
+                trades,
+            });
+        }
+
+        let (stop_loss, stop_loss_trades) = match self.process_order(stop_loss) {
+            Ok(result) => result,
+            Err(err) => {
+                self.cancel_order(&take_profit_id);
+                self.publish_depth_changes();
+                return Err(err);
+            }
+        };
+        trades.extend(stop_loss_trades);
+
+        if !stop_loss.trades.is_empty() {
+            if self.cancel_order(&take_profit_id).is_some() {
+                take_profit.status = OrderStatus::Cancelled;
+                self.publish_depth_changes();
+            }
+        } else if self.active_order_ids.contains(&take_profit_id)
+            && self.active_order_ids.contains(&stop_loss_id)
+        {
+            self.oco_links
+                .insert(take_profit_id.clone(), stop_loss_id.clone());
+            self.oco_links.insert(stop_loss_id, take_profit_id);
+        }
+
+        Ok(OcoResult {
+            take_profit,
+            stop_loss: Some(stop_loss),
+            trades,
+        })
+    }
+
+    // Cancels the other half of every OCO pair with an order in `trades`
+    fn cancel_oco_siblings(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            for order_id in [&trade.taker_order_id, &trade.maker_order_id] {
+                if let Some(sibling) = self.oco_links.remove(order_id) {
+                    self.oco_links.remove(&sibling);
+                    self.cancel_order(&sibling);
+                }
+            }
+        }
+    }
+
+    fn resting_entry(&self, order_id: &str) -> Option<OrderBookEntry> {
+        let &(_, price) = self.order_index.get(order_id)?;
+        self.get_order_by_id(order_id)
+            .map(|order| book_entry(order, price))
+    }
+
+    fn resting_amount(&self, order_id: &str) -> Option<Decimal> {
+        self.get_order_by_id(order_id)
+            .and_then(|order| Decimal::from_str(&order.amount).ok())
+    }
+
+    // Describes an order after processing. An order still in the book is open; one
+    // that isn't was either filled in full or had its remainder cancelled.
+    fn fill_event(
+        &self,
+        order_id: &str,
+        requested: Decimal,
+        trades: Vec<Trade>,
+        cancelled: bool,
+    ) -> FillEvent {
+        let filled_amount: Decimal = trades
+            .iter()
+            .filter_map(|trade| Decimal::from_str(&trade.amount).ok())
+            .sum();
+
+        let resting = self.get_order_by_id(order_id).and_then(|order| {
+            Decimal::from_str(&order.amount)
+                .ok()
+                .map(|amount| (amount, order.status.clone()))
+        });
+        let (remaining_amount, status) = match resting {
+            Some(resting) => resting,
+            None if cancelled => (requested, OrderStatus::Cancelled),
+            None if self.trailing_stop(order_id).is_some() => (requested, OrderStatus::Open),
+            None => {
+                let remaining = (requested - filled_amount).max(Decimal::ZERO);
+                if remaining.is_zero() {
+                    (remaining, OrderStatus::Filled)
+                } else {
+                    (remaining, OrderStatus::Cancelled)
+                }
+            }
+        };
+
+        FillEvent {
+            order_id: order_id.to_string(),
+            filled_amount,
+            remaining_amount,
+            trades,
+            status,
+        }
+    }
+
+    // Processes orders in sequence and returns every trade they produce. The whole
+    // batch is checked up front and nothing is processed if any order is malformed;
+    // orders the book rejects while processing (unknown ids, post-only crosses and
+    // so on) are skipped.
+    pub fn process_orders_batch(&mut self, orders: Vec<Order>) -> Result<Vec<Trade>, BatchError> {
+        for (index, order) in orders.iter().enumerate() {
+            order.validate().map_err(|error| BatchError {
+                index,
+                order_id: order.order_id.clone(),
+                error,
+            })?;
+        }
+
+        let mut trades = Vec::new();
+        for order in orders {
+            if let Ok((_, new_trades)) = self.process_order(order) {
+                trades.extend(new_trades);
+            }
+        }
+
+        Ok(trades)
+    }
+
+    fn record_fill(
+        &mut self,
+        account_id: &str,
+        pair: &TradingPair,
+        side: Side,
+        price: Decimal,
+        amount: Decimal,
+    ) {
+        self.account_positions
+            .entry(account_id.to_string())
+            .or_default()
+            .entry(pair.to_string())
+            .or_insert_with(|| Position::new(account_id, &pair.to_string()))
+            .apply_fill(side, price, amount);
+        self.activity.record_fill(account_id, amount, self.now());
+    }
+
+    fn record_trades(&mut self, trades: &mut [Trade]) {
+        for trade in trades.iter_mut() {
+            if let (Ok(price), Ok(amount)) = (
+                Decimal::from_str(&trade.price),
+                Decimal::from_str(&trade.amount),
+            ) {
+                // With no earlier trade to compare against, the taker's side stands
+                let tick = self.last_trade_price.map(|last| price.cmp(&last));
+                trade.aggressor = match tick {
+                    Some(Ordering::Greater) => TradeSide::Buyer,
+                    Some(Ordering::Less) => TradeSide::Seller,
+                    _ => self.last_aggressor.unwrap_or(trade.aggressor),
+                };
+                trade.tick_direction = match (tick, trade.aggressor) {
+                    (Some(Ordering::Greater), _) => TickDirection::UpTick,
+                    (Some(Ordering::Less), _) => TickDirection::DownTick,
+                    (_, TradeSide::Buyer) => TickDirection::ZeroUpTick,
+                    (_, TradeSide::Seller) => TickDirection::ZeroDownTick,
+                };
+                self.last_aggressor = Some(trade.aggressor);
+                self.update_session_stats(price, amount);
+            }
+        }
+
+        for trade in trades.iter() {
+            self.events
+                .publish(OrderBookEvent::TradeExecuted(self.format_trade(trade)));
+        }
+        self.index_trades(trades);
+        self.trades.extend_from_slice(trades);
+        self.evict_trade_history();
+    }
+
+    fn update_session_stats(&mut self, price: Decimal, amount: Decimal) {
+        self.last_trade_price = Some(price);
+        self.session_high = Some(self.session_high.map_or(price, |high| high.max(price)));
+        self.session_low = Some(self.session_low.map_or(price, |low| low.min(price)));
+        self.session_volume += amount;
+    }
+
+    // Indexes trades about to be appended to the history
+    fn index_trades(&mut self, trades: &[Trade]) {
+        let next = self.evicted_trades + self.trades.len();
+        for (offset, trade) in trades.iter().enumerate() {
+            self.trade_index
+                .insert(trade.trade_id.clone(), next + offset);
+        }
+    }
+
+    fn evict_trade_history(&mut self) {
+        if let Some(max) = self.max_trade_history
+            && self.trades.len() > max
+        {
+            let excess = self.trades.len() - max;
+            self.evict_trades(excess);
+        }
+    }
+
+    fn evict_trades(&mut self, count: usize) -> Vec<Trade> {
+        let evicted: Vec<Trade> = self.trades.drain(..count).collect();
+        for trade in &evicted {
+            self.trade_index.remove(&trade.trade_id);
+        }
+        self.evicted_trades += count;
+        evicted
+    }
+
+    pub fn filter_trades(&self, filter: TradeFilter) -> Vec<&Trade> {
+        self.trades
+            .iter()
+            .filter(|trade| filter.matches(trade))
+            .collect()
+    }
+
+    pub fn get_trade_by_id(&self, trade_id: &str) -> Option<&Trade> {
+        let position = self.trade_index.get(trade_id)?;
+        self.trades
+            .get(position.checked_sub(self.evicted_trades)?)
+            .filter(|trade| trade.trade_id == trade_id)
+    }
+
+    // Caps the stored trade history, evicting the oldest trades beyond `max`
+    pub fn set_max_trade_history(&mut self, max: Option<usize>) {
+        self.max_trade_history = max;
+        self.evict_trade_history();
+    }
+
+    // Empties the trade history and hands it back, e.g. for archiving
+    pub fn clear_trades(&mut self) -> Vec<Trade> {
+        self.evict_trades(self.trades.len())
+    }
+
+    // Trades at or after `timestamp_ms`. The history is in execution order, so this
+    // is a binary search rather than a scan.
+    pub fn trades_since(&self, timestamp_ms: u64) -> &[Trade] {
+        let since = timestamp_ms.saturating_mul(NANOS_PER_MILLI);
+        let start = self.trades.partition_point(|t| t.timestamp < since);
+        &self.trades[start..]
+    }
+
+    // Prometheus registry holding this book's metrics
+    #[cfg(feature = "metrics")]
+    pub fn registry(&self) -> &prometheus::Registry {
+        self.metrics.registry()
+    }
+
+    // Returns a receiver for every subsequent trade and order event in this book
+    pub fn subscribe(&mut self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    fn create_order(&mut self, mut order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        // Rejected before matching so a reused id can't trade and then fail to rest
+        if self.active_order_ids.contains(&order.order_id)
+            || self.trailing_stop(&order.order_id).is_some()
+        {
+            return Err(OrderBookError::DuplicateOrderId(order.order_id));
+        }
+        let amount = parse_amount(&order.amount)?;
+        if amount <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidAmount(order.amount));
+        }
+        self.check_lot_size(amount)?;
+        self.check_order_size(amount)?;
+        if let Some(price) = order.price_limit()? {
+            if price <= Decimal::ZERO {
+                return Err(OrderBookError::InvalidPrice(order.limit_price));
+            }
+            self.check_tick_size(price)?;
+            self.check_price_band(price)?;
+        }
+        if let Some(visible_amount) = order.visible_amount
+            && visible_amount <= Decimal::ZERO
+        {
+            return Err(OrderBookError::InvalidAmount(visible_amount.to_string()));
+        }
+
+        match (order.trail_amount, order.trail_pct) {
+            (None, None) => {}
+            (Some(trail), None) | (None, Some(trail)) => {
+                if trail <= Decimal::ZERO {
+                    return Err(OrderBookError::InvalidPrice(trail.to_string()));
+                }
+                let mut spec = TrailSpec {
+                    order,
+                    stop_price: None,
+                };
+                if let Some(price) = self.last_trade_price {
+                    spec.stop_price = Some(spec.stop_for(price));
+                }
+                self.trailing_stop_orders
+                    .push((spec.order.order_id.clone(), spec));
+                return Ok(Vec::new());
+            }
+            (Some(_), Some(trail_pct)) => {
+                return Err(OrderBookError::InvalidPrice(trail_pct.to_string()));
+            }
+        }
+
+        // Auction orders wait for the uncross, whatever their time in force
+        if self.auction_mode == AuctionMode::Opening {
+            if order.order_type == OrderType::Market {
+                return Err(OrderBookError::InvalidPrice(order.limit_price));
+            }
+            self.add_order(order)?;
+            return Ok(Vec::new());
+        }
+
+        if order.order_type == OrderType::Market
+            && let Some(limit_price) = self.market_protection_limit(order.side)
+        {
+            order.order_type = OrderType::Limit;
+            order.limit_price = limit_price.to_string();
+            if order.time_in_force != Some(TimeInForce::Fok) {
+                order.time_in_force = Some(TimeInForce::Ioc);
+            }
+            self.events.publish(OrderBookEvent::MarketOrderProtected(
+                order.order_id.clone(),
+                limit_price,
+            ));
+        }
+
+        // A post-only order must land in the book as a maker
+        if order.post_only && self.would_match(&order)? {
+            return Err(OrderBookError::PostOnlyRejected(order.order_id));
+        }
+
+        // A fill-or-kill order is cancelled outright if the book can't fill all of it
+        let time_in_force = order
+            .time_in_force
+            .unwrap_or(self.config.default_time_in_force);
+        if time_in_force == TimeInForce::Fok && self.fillable_amount(&order)? < amount {
+            return Ok(Vec::new());
+        }
+
+        let outcome = self.match_order(&order)?;
+        // If the order is not completely filled, add it to the order book.
+        // Only GTC limit orders rest; any other remainder is cancelled.
+        if let Some(remaining_order) = self.get_remaining_order(&order, outcome.filled)? {
+            match (order.order_type, time_in_force) {
+                _ if outcome.cancel_remainder => {}
+                (OrderType::Limit, TimeInForce::Gtc) => self.add_order(remaining_order)?,
+                _ => {}
+            }
+        }
+
+        Ok(outcome.trades)
+    }
+
+    pub fn auction_mode(&self) -> AuctionMode {
+        self.auction_mode
+    }
+
+    // Switching to AuctionMode::Opening starts collecting orders for run_auction
+    pub fn set_auction_mode(&mut self, auction_mode: AuctionMode) {
+        self.auction_mode = auction_mode;
+    }
+
+    // Uncrosses the book at the single price that executes the most volume, then
+    // switches to continuous trading. Ties go to the price leaving the smallest
+    // imbalance between the two sides, then to the lower price. Orders are filled
+    // best price first and in queue order within a price; in each trade the order
+    // that arrived later is recorded as the taker.
+    pub fn run_auction(&mut self) -> AuctionResult {
+        self.auction_mode = AuctionMode::Continuous;
+
+        let level_amounts = |levels: &BTreeMap<Decimal, Vec<Order>>| -> Vec<(Decimal, Decimal)> {
+            levels
+                .iter()
+                .map(|(price, orders)| (*price, total_amount(orders)))
+                .collect()
+        };
+        let bids = level_amounts(&self.bids);
+        let asks = level_amounts(&self.asks);
+
+        // (volume, imbalance, price) of the best clearing price so far
+        let mut best: Option<(Decimal, Decimal, Decimal)> = None;
+        for &price in bids.iter().chain(asks.iter()).map(|(price, _)| price) {
+            let demand: Decimal = bids
+                .iter()
+                .filter(|(p, _)| *p >= price)
+                .map(|(_, a)| a)
+                .sum();
+            let supply: Decimal = asks
+                .iter()
+                .filter(|(p, _)| *p <= price)
+                .map(|(_, a)| a)
+                .sum();
+            let volume = demand.min(supply);
+            let imbalance = (demand - supply).abs();
+            let better = match best {
+                None => true,
+                Some((best_volume, best_imbalance, best_price)) => {
+                    (volume, -imbalance, -price) > (best_volume, -best_imbalance, -best_price)
+                }
+            };
+            if volume > Decimal::ZERO && better {
+                best = Some((volume, imbalance, price));
+            }
+        }
+
+        let Some((volume, _, clearing_price)) = best else {
+            return AuctionResult {
+                clearing_price: None,
+                volume: Decimal::ZERO,
+                trades: Vec::new(),
+            };
+        };
+
+        let mut trades = Vec::new();
+        let mut remaining = volume;
+        while remaining > Decimal::ZERO {
+            let (Some(bid), Some(ask)) = (
+                self.get_best_bid_orders().and_then(|orders| orders.first()),
+                self.get_best_ask_orders().and_then(|orders| orders.first()),
+            ) else {
+                break;
+            };
+            let (bid, ask) = (bid.clone(), ask.clone());
+            let (Ok(bid_amount), Ok(ask_amount)) =
+                (parse_amount(&bid.amount), parse_amount(&ask.amount))
+            else {
+                break;
+            };
+            let amount = remaining.min(bid_amount).min(ask_amount);
+
+            let (taker, maker) = if bid.sequence_number > ask.sequence_number {
+                (&bid, &ask)
+            } else {
+                (&ask, &bid)
+            };
+            let (maker_fee, taker_fee) = self.fee_schedule.fees(clearing_price, amount);
+            trades.push(Trade {
+                trade_id: generate_trade_id(),
+                taker_order_id: taker.order_id.clone(),
+                maker_order_id: maker.order_id.clone(),
+                taker_account_id: taker.account_id.clone(),
+                maker_account_id: maker.account_id.clone(),
+                pair: taker.pair.clone(),
+                price: clearing_price.to_string(),
+                amount: amount.to_string(),
+                timestamp: self.now(),
+                maker_fee,
+                taker_fee,
+                aggressor: taker.side.into(),
+                tick_direction: TickDirection::default(),
+                taker_side: Some(taker.side),
+            });
+
+            for (order, order_amount) in [(&bid, bid_amount), (&ask, ask_amount)] {
+                self.record_fill(
+                    &order.account_id,
+                    &order.pair,
+                    order.side,
+                    clearing_price,
+                    amount,
+                );
+                if amount == order_amount {
+                    self.take_order(&order.order_id);
+                    self.closed_orders
+                        .insert(order.order_id.clone(), OrderStatus::Filled);
+                } else if let Some(resting) = self.get_order_by_id_mut(&order.order_id) {
+                    let left = order_amount - amount;
+                    resting.amount = left.to_string();
+                    resting.status = resting.status.with_fill(amount);
+                    if let Some(slice) = self.iceberg_slices.get_mut(&order.order_id) {
+                        *slice = (*slice).min(left);
+                    }
+                    self.publish_modified(order.order_id.clone(), left);
+                }
+            }
+            remaining -= amount;
+        }
+
+        self.record_trades(&mut trades);
+        self.cancel_oco_siblings(&trades);
+        self.publish_depth_changes();
+
+        AuctionResult {
+            clearing_price: Some(clearing_price),
+            volume,
+            trades,
+        }
+    }
+
+    // Worst price a market order on `side` may trade at under market order
+    // protection, or None if protection is off or there is nothing to trade against
+    fn market_protection_limit(&self, side: Side) -> Option<Decimal> {
+        let pct = self.config.market_order_protection_pct?;
+        let limit_price = match side {
+            Side::Buy => self.get_best_ask()? * (Decimal::ONE + pct),
+            Side::Sell => self.get_best_bid()? * (Decimal::ONE - pct),
+        };
+        (limit_price > Decimal::ZERO).then_some(limit_price.normalize())
+    }
+
+    // Amends the amount and/or price of a resting order. Reducing the amount at an
+    // unchanged price keeps the order's queue position; anything else loses priority
+    // and the order is re-entered at the tail of its (possibly new) price level.
+    fn modify_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        let Some(&(side, old_price)) = self.order_index.get(&order.order_id) else {
+            return Err(OrderBookError::UnknownOrderId(order.order_id));
+        };
+
+        let new_price = if order.limit_price.is_empty() {
+            old_price
+        } else {
+            parse_price(&order.limit_price)?
+        };
+        if new_price <= Decimal::ZERO {
+            return Err(OrderBookError::InvalidPrice(order.limit_price));
+        }
+        // Checked before the order is pulled from the book so a rejected amend leaves it resting
+        self.check_tick_size(new_price)?;
+        self.check_price_band(new_price)?;
+        if let Some(amount) = &order.new_amount {
+            let amount = parse_amount(amount)?;
+            self.check_lot_size(amount)?;
+            // Zero cancels the order, which is always allowed
+            if amount > Decimal::ZERO {
+                self.check_order_size(amount)?;
+            }
+        }
+
+        let Some(orders) = self.book_mut(side).get_mut(&old_price) else {
+            return Err(OrderBookError::UnknownOrderId(order.order_id));
+        };
+        let Some(index) = orders.iter().position(|o| o.order_id == order.order_id) else {
+            return Err(OrderBookError::UnknownOrderId(order.order_id));
+        };
+        let old_amount = parse_amount(&orders[index].amount)?;
+        let new_amount = match &order.new_amount {
+            Some(amount) => parse_amount(amount)?,
+            None => old_amount,
+        };
+        if new_amount < Decimal::ZERO {
+            return Err(OrderBookError::InvalidAmount(new_amount.to_string()));
+        }
+
+        if new_price == old_price && new_amount > Decimal::ZERO && new_amount <= old_amount {
+            orders[index].amount = new_amount.to_string();
+            if let Some(slice) = self.iceberg_slices.get_mut(&order.order_id) {
+                *slice = (*slice).min(new_amount);
+            }
+            self.publish_modified(order.order_id, new_amount);
+            return Ok(Vec::new());
+        }
+
+        if new_amount > Decimal::ZERO
+            && let Some(resting) = self.get_order_by_id(&order.order_id)
+        {
+            self.check_exposure(&Order {
+                amount: new_amount.to_string(),
+                limit_price: new_price.to_string(),
+                ..resting.clone()
+            })?;
+        }
+
+        if new_amount <= Decimal::ZERO {
+            if let Some(cancelled) = self.cancel_order(&order.order_id) {
+                self.activity
+                    .record_cancel(&cancelled.account_id, self.now());
+            }
+            return Ok(Vec::new());
+        }
+
+        // A new price may cross the spread, so the order goes back through matching.
+        // If it is rejected there, e.g. post-only, it stays as it was.
+        self.replace_resting(&order.order_id, |resting| Order {
+            amount: new_amount.to_string(),
+            limit_price: new_price.to_string(),
+            timestamp: order.timestamp,
+            ..resting.clone()
+        })
+    }
+
+    // Cancels the resting order with the same id and enters this order in its place
+    // as one step. If the replacement is rejected the original is put back at its
+    // old queue position, so the book never holds neither or both.
+    fn cancel_replace_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        let order_id = order.order_id.clone();
+        self.replace_resting(&order_id, |_| Order {
+            type_op: Operation::Create,
+            ..order
+        })
+    }
+
+    // Takes the resting order out of the book and enters the order `replacement`
+    // builds from it. If that is rejected the original goes back where it was.
+    fn replace_resting(
+        &mut self,
+        order_id: &str,
+        replacement: impl FnOnce(&Order) -> Order,
+    ) -> Result<Vec<Trade>, OrderBookError> {
+        let Some(&(_, price)) = self.order_index.get(order_id) else {
+            return Err(OrderBookError::UnknownOrderId(order_id.to_string()));
+        };
+        // Events wait for the outcome, so a rolled back replacement publishes none
+        let changed_levels = self.changed_levels.len();
+        let iceberg_slice = self.iceberg_slices.get(order_id).copied();
+        self.events.hold();
+        let Some((original, position)) = self.take_order_at(order_id) else {
+            self.events.release();
+            return Err(OrderBookError::UnknownOrderId(order_id.to_string()));
+        };
+
+        let result = self.create_order(replacement(&original));
+        if result.is_ok() {
+            self.events.release();
+        } else {
+            self.events.discard();
+            self.restore_order(original, price, position, iceberg_slice);
+            self.changed_levels.truncate(changed_levels);
+        }
+        result
+    }
+
+    // Puts an order taken out of the book back at `position` in its level, keeping
+    // its sequence number and iceberg slice
+    fn restore_order(
+        &mut self,
+        order: Order,
+        price: Decimal,
+        position: usize,
+        iceberg_slice: Option<Decimal>,
+    ) {
+        self.index_order(&order, price);
+        if let Some(slice) = iceberg_slice {
+            self.iceberg_slices.insert(order.order_id.clone(), slice);
+        }
+        let orders = self.book_mut(order.side).entry(price).or_default();
+        orders.insert(position.min(orders.len()), order);
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                order_id = %order.order_id,
+                pair = %order.pair,
+                side = %order.side,
+                amount = %order.amount,
+                price = %order.limit_price,
+            )
+        )
+    )]
+    pub fn match_buy_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        self.match_order(&order).map(|outcome| outcome.trades)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                order_id = %order.order_id,
+                pair = %order.pair,
+                side = %order.side,
+                amount = %order.amount,
+                price = %order.limit_price,
+            )
+        )
+    )]
+    pub fn match_sell_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderBookError> {
+        self.match_order(&order).map(|outcome| outcome.trades)
+    }
+
+    // Walks the opposite side of the book from the best price outwards, filling the
+    // incoming order against resting orders in time priority at each level
+    fn match_order(&mut self, order: &Order) -> Result<MatchOutcome, OrderBookError> {
+        let mut trades = Vec::new();
+        let mut cancel_remainder = false;
+        let self_trade_prevention = self.config.self_trade_prevention;
+        let fee_schedule = self.fee_schedule;
+        let circuit_breaker = self.circuit_breaker;
+        let matching_algorithm = self.config.matching_algorithm;
+        let allocation_unit = self.config.lot_size.unwrap_or(PRO_RATA_UNIT);
+        let now = self.now();
+        // Trades take the time they happen unless a simulation has fixed it
+        let simulated_time = self.simulated_time;
+        let halted = self.is_halted();
+        let mut tripped_at = None;
+        let mut remaining_amount = parse_amount(&order.amount)?;
+        let order_amount = remaining_amount;
+        let limit_price = order.price_limit()?;
+
+        // Price levels the order is allowed to trade at, best first
+        let prices: Vec<Decimal> = match order.side {
+            Side::Buy => self
+                .asks
+                .keys()
+                .copied()
+                .take_while(|price| limit_price.is_none_or(|limit| *price <= limit))
+                .collect(),
+            Side::Sell => self
+                .bids
+                .keys()
+                .rev()
+                .copied()
+                .take_while(|price| limit_price.is_none_or(|limit| *price >= limit))
+                .collect(),
+        };
+
+        for price in prices {
+            if remaining_amount <= Decimal::ZERO || cancel_remainder {
+                break;
+            }
+
+            // Nothing trades while halted or at a price beyond the circuit breaker;
+            // the rest of the order is cancelled rather than left crossing the book
+            if halted {
+                cancel_remainder = true;
+                break;
+            }
+            if circuit_breaker.is_some_and(|breaker| breaker.trips_at(price)) {
+                tripped_at = Some(price);
+                cancel_remainder = true;
+                break;
+            }
+
+            let (book, iceberg_slices, sequence_number) = match order.side.opposite() {
+                Side::Buy => (
+                    &mut self.bids,
+                    &mut self.iceberg_slices,
+                    &mut self.sequence_number,
+                ),
+                Side::Sell => (
+                    &mut self.asks,
+                    &mut self.iceberg_slices,
+                    &mut self.sequence_number,
+                ),
+            };
+            let resting_orders = book.get_mut(&price).unwrap();
+            // Resting orders that are filled or cancelled and must leave the book
+            let mut removed_orders = Vec::new();
+            let mut modified_orders = Vec::new();
+            // Maker account and amount of each fill at this level
+            let mut fills = Vec::new();
+
+            // Icebergs that replenish their displayed slice rejoin the back of the
+            // level, so the level is walked again until nothing more is replenished
+            loop {
+                let mut replenished = Vec::new();
+
+                // Pro-rata shares of the incoming amount, among orders that can trade
+                let allocations = match matching_algorithm {
+                    MatchingAlgorithm::Fifo => None,
+                    MatchingAlgorithm::ProRata => {
+                        let mut sizes = Vec::new();
+                        for resting_order in resting_orders.iter() {
+                            if resting_order.is_expired(now)
+                                || (resting_order.account_id == order.account_id
+                                    && self_trade_prevention != SelfTradePrevention::Allow)
+                            {
+                                continue;
+                            }
+                            let size = match iceberg_slices.get(&resting_order.order_id) {
+                                Some(slice) => *slice,
+                                None => parse_amount(&resting_order.amount)?,
+                            };
+                            sizes.push((resting_order.order_id.clone(), size));
+                        }
+                        Some(pro_rata_allocations(
+                            &sizes,
+                            remaining_amount,
+                            allocation_unit,
+                        ))
+                    }
+                };
+
+                for resting_order in resting_orders.iter_mut() {
+                    if remaining_amount <= Decimal::ZERO {
+                        break;
+                    }
+
+                    // Expired orders are skipped and removed when reached
+                    if resting_order.is_expired(now) {
+                        removed_orders.push((
+                            resting_order.order_id.clone(),
+                            resting_order.account_id.clone(),
+                            OrderStatus::Cancelled,
+                        ));
+                        continue;
+                    }
+
+                    if resting_order.account_id == order.account_id
+                        && self_trade_prevention != SelfTradePrevention::Allow
+                    {
+                        if matches!(
+                            self_trade_prevention,
+                            SelfTradePrevention::CancelMaker | SelfTradePrevention::CancelBoth
+                        ) {
+                            removed_orders.push((
+                                resting_order.order_id.clone(),
+                                resting_order.account_id.clone(),
+                                OrderStatus::Cancelled,
+                            ));
+                        }
+                        if matches!(
+                            self_trade_prevention,
+                            SelfTradePrevention::CancelTaker | SelfTradePrevention::CancelBoth
+                        ) {
+                            cancel_remainder = true;
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let resting_amount = parse_amount(&resting_order.amount)?;
+                    // An iceberg only trades its displayed slice
+                    let available = iceberg_slices
+                        .get(&resting_order.order_id)
+                        .copied()
+                        .unwrap_or(resting_amount);
+
+                    // Calculate the amount that can be matched
+                    let mut trade_amount = remaining_amount.min(available);
+                    if let Some(allocations) = &allocations {
+                        trade_amount = trade_amount.min(
+                            allocations
+                                .get(&resting_order.order_id)
+                                .copied()
+                                .unwrap_or(Decimal::ZERO),
+                        );
+                        if trade_amount <= Decimal::ZERO {
+                            continue;
+                        }
+                    }
+                    let (maker_fee, taker_fee) = fee_schedule.fees(price, trade_amount);
+
+                    let trade = Trade {
+                        trade_id: generate_trade_id(),
+                        taker_order_id: order.order_id.clone(),
+                        maker_order_id: resting_order.order_id.clone(),
+                        taker_account_id: order.account_id.clone(),
+                        maker_account_id: resting_order.account_id.clone(),
+                        pair: order.pair.clone(),
+                        price: price.to_string(),
+                        amount: trade_amount.to_string(),
+                        timestamp: simulated_time.unwrap_or_else(get_current_timestamp),
+                        maker_fee,
+                        taker_fee,
+                        // Provisional: record_trades applies the tick rule
+                        aggressor: order.side.into(),
+                        tick_direction: TickDirection::default(),
+                        taker_side: Some(order.side),
+                    };
+
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        taker_order_id = %trade.taker_order_id,
+                        maker_order_id = %trade.maker_order_id,
+                        amount = %trade.amount,
+                        price = %trade.price,
+                        "trade executed"
+                    );
+
+                    trades.push(trade);
+                    fills.push((resting_order.account_id.clone(), trade_amount));
+
+                    // Update the remaining amount
+                    remaining_amount -= trade_amount;
+
+                    if trade_amount < resting_amount {
+                        // Partial fill
+                        let left = resting_amount - trade_amount;
+                        resting_order.amount = left.to_string();
+                        resting_order.status = resting_order.status.with_fill(trade_amount);
+                        modified_orders.push((resting_order.order_id.clone(), left));
+
+                        if let Some(slice) = iceberg_slices.get_mut(&resting_order.order_id) {
+                            *slice -= trade_amount;
+                            if *slice <= Decimal::ZERO {
+                                // Top up from the reserve, losing time priority
+                                *slice = resting_order.visible_amount.unwrap_or(left).min(left);
+                                resting_order.timestamp = now;
+                                replenished.push(resting_order.order_id.clone());
+                            }
+                        }
+                    } else {
+                        // Complete fill
+                        removed_orders.push((
+                            resting_order.order_id.clone(),
+                            resting_order.account_id.clone(),
+                            OrderStatus::Filled,
+                        ));
+                    }
+                }
+
+                // Remove filled orders
+                resting_orders
+                    .retain(|o| !removed_orders.iter().any(|(id, _, _)| *id == o.order_id));
+
+                for order_id in &replenished {
+                    if let Some(index) = resting_orders.iter().position(|o| o.order_id == *order_id)
+                    {
+                        let mut requeued = resting_orders.remove(index);
+                        *sequence_number += 1;
+                        requeued.sequence_number = *sequence_number;
+                        resting_orders.push(requeued);
+                    }
+                }
+
+                if replenished.is_empty() || remaining_amount <= Decimal::ZERO || cancel_remainder {
+                    break;
+                }
+            }
+
+            // Drop the price level if nothing is left at it
+            if resting_orders.is_empty() {
+                book.remove(&price);
+            }
+            for (order_id, account_id, status) in removed_orders {
+                self.unindex_order(&order_id, &account_id);
+                if status == OrderStatus::Cancelled {
+                    self.events
+                        .publish(OrderBookEvent::OrderCancelled(order_id.clone()));
+                }
+                self.closed_orders.insert(order_id, status);
+            }
+            for (order_id, amount) in modified_orders {
+                self.publish_modified(order_id, amount);
+            }
+            for (maker_account_id, amount) in fills {
+                self.record_fill(&order.account_id, &order.pair, order.side, price, amount);
+                self.record_fill(
+                    &maker_account_id,
+                    &order.pair,
+                    order.side.opposite(),
+                    price,
+                    amount,
+                );
+            }
+        }
+
+        if let (Some(price), Some(breaker)) = (tripped_at, circuit_breaker) {
+            let resume_at = now.saturating_add(breaker.cooldown_ms.saturating_mul(NANOS_PER_MILLI));
+            self.halted_until = Some(resume_at);
+            self.events
+                .publish(OrderBookEvent::CircuitBreakerTripped(price, resume_at));
+        }
+
+        Ok(MatchOutcome {
+            trades,
+            filled: order_amount - remaining_amount,
+            cancel_remainder,
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                order_id = %order.order_id,
+                pair = %order.pair,
+                side = %order.side,
+                amount = %order.amount,
+                price = %order.limit_price,
+            )
+        )
+    )]
+    pub fn add_order(&mut self, mut order: Order) -> Result<(), OrderBookError> {
+        let price = parse_price(&order.limit_price)?;
+        if self.active_order_ids.contains(&order.order_id) {
+            return Err(OrderBookError::DuplicateOrderId(order.order_id));
+        }
+        self.sequence_number += 1;
+        order.sequence_number = self.sequence_number;
+
+        if let Some(visible_amount) = order.visible_amount {
+            let amount = parse_amount(&order.amount)?;
+            self.iceberg_slices
+                .insert(order.order_id.clone(), visible_amount.min(amount));
+        }
+
+        self.index_order(&order, price);
+        self.events.publish(OrderBookEvent::OrderAdded(
+            self.displayed_entry(&order, price),
+        ));
+        self.book_mut(order.side)
+            .entry(price)
+            .or_default()
+            .push(order);
+
+        if let Some(max_depth) = self.config.max_depth {
+            self.trim_to_depth(max_depth);
+        }
+
+        Ok(())
+    }
+
+    // Moves every resting order from `other` into this book, behind the orders already
+    // resting at the same price, and appends its trade history. Fails without changing
+    // anything if an order id is present in both books. Trades already in this book's
+    // history (e.g. from a shared replica) are not appended twice.
+    pub fn merge(&mut self, other: OrderBook) -> Result<(), OrderBookError> {
+        if let Some(order_id) = other
+            .order_index
+            .keys()
+            .find(|order_id| self.order_index.contains_key(*order_id))
+        {
+            return Err(OrderBookError::DuplicateOrderId(order_id.clone()));
+        }
+
+        for order in other
+            .bids
+            .into_values()
+            .chain(other.asks.into_values())
+            .flatten()
+        {
+            self.add_order(order)?;
+        }
+
+        let new_trades: Vec<Trade> = other
+            .trades
+            .into_iter()
+            .filter(|trade| !self.trade_index.contains_key(&trade.trade_id))
+            .collect();
+        if !new_trades.is_empty() {
+            // The history stays in time order, with the statistics rebuilt from it
+            let mut trades = std::mem::take(&mut self.trades);
+            trades.extend(new_trades);
+            trades.sort_by_key(|trade| trade.timestamp);
+            self.set_trade_history(trades);
+        }
+
+        Ok(())
+    }
+
+    // Checks the book's internal consistency and reports every violation found
+    pub fn validate_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = Vec::new();
+
+        if let (Some(bid), Some(ask)) = (self.get_best_bid(), self.get_best_ask())
+            && bid >= ask
+        {
+            violations.push(InvariantViolation::CrossedBook(bid, ask));
+        }
+
+        let mut resting_ids = HashSet::new();
+        for (side, book) in [(Side::Buy, &self.bids), (Side::Sell, &self.asks)] {
+            for order in book.values().flatten() {
+                if order.side != side {
+                    violations.push(InvariantViolation::WrongSide(order.order_id.clone(), side));
+                }
+                if !parse_amount(&order.amount).is_ok_and(|amount| amount > Decimal::ZERO) {
+                    violations.push(InvariantViolation::NonPositiveAmount(
+                        order.order_id.clone(),
+                        order.amount.clone(),
+                    ));
+                }
+                if !self.active_order_ids.contains(&order.order_id) {
+                    violations.push(InvariantViolation::UntrackedOrder(order.order_id.clone()));
+                }
+                resting_ids.insert(order.order_id.as_str());
+            }
+        }
+        for order_id in &self.active_order_ids {
+            if !resting_ids.contains(order_id.as_str()) {
+                violations.push(InvariantViolation::MissingOrder(order_id.clone()));
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    // Drops the worst-priced levels beyond `levels` on each side and returns the
+    // orders that were resting at them
+    pub fn trim_to_depth(&mut self, levels: usize) -> Vec<Order> {
+        let evicted_ids: Vec<String> = self
+            .bids
+            .values()
+            .rev()
+            .skip(levels)
+            .chain(self.asks.values().skip(levels))
+            .flatten()
+            .map(|o| o.order_id.clone())
+            .collect();
+
+        let evicted = evicted_ids
+            .iter()
+            .filter_map(|order_id| self.cancel_order(order_id))
+            .collect();
+        self.publish_depth_changes();
+        evicted
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                order_id = %order.order_id,
+                pair = %order.pair,
+                side = %order.side,
+                amount = %order.amount,
+                price = %order.limit_price,
+            )
+        )
+    )]
+    pub fn remove_order(&mut self, order: &Order) -> Result<(), OrderBookError> {
+        if let Some(index) = self
+            .trailing_stop_orders
+            .iter()
+            .position(|(order_id, _)| *order_id == order.order_id)
+        {
+            let (_, spec) = self.trailing_stop_orders.remove(index);
+            self.closed_orders
+                .insert(order.order_id.clone(), OrderStatus::Cancelled);
+            self.activity
+                .record_cancel(&spec.order.account_id, self.now());
+            self.events
+                .publish(OrderBookEvent::OrderCancelled(order.order_id.clone()));
+            return Ok(());
+        }
+        let removed = self
+            .cancel_order(&order.order_id)
+            .ok_or_else(|| OrderBookError::UnknownOrderId(order.order_id.clone()))?;
+        self.activity.record_cancel(&removed.account_id, self.now());
+        Ok(())
+    }
+
+    pub fn get_order_by_id(&self, order_id: &str) -> Option<&Order> {
+        let (side, price) = self.order_index.get(order_id)?;
+        let book = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+
+        book.get(price)?.iter().find(|o| o.order_id == order_id)
+    }
+
+    // Status of a resting order, or the final status of one that has left the book
+    // or was rejected. None for ids the book has never seen.
+    pub fn get_order_status(&self, order_id: &str) -> Option<OrderStatus> {
+        match self.get_order_by_id(order_id) {
+            Some(order) => Some(order.status.clone()),
+            None if self.trailing_stop(order_id).is_some() => Some(OrderStatus::Open),
+            None => self.closed_orders.get(order_id).cloned(),
+        }
+    }
+
+    pub fn get_orders_by_account(&self, account_id: &str) -> Vec<&Order> {
+        self.account_orders
+            .get(account_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| self.get_order_by_id(order_id))
+            .collect()
+    }
+
+    // Cancels every resting order for the account and returns how many were removed
+    pub fn cancel_all_orders_for_account(&mut self, account_id: &str) -> usize {
+        let order_ids: Vec<String> = self
+            .account_orders
+            .get(account_id)
+            .map(|order_ids| order_ids.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let now = self.now();
+        let mut cancelled = 0;
+        for order_id in &order_ids {
+            if self.cancel_order(order_id).is_some() {
+                self.activity.record_cancel(account_id, now);
+                cancelled += 1;
+            }
+        }
+        self.publish_depth_changes();
+        cancelled
+    }
+
+    // Removes every resting order whose expiry has passed and returns them
+    pub fn remove_expired_orders(&mut self) -> Vec<Order> {
+        let now = self.now();
+        let expired: Vec<String> = self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flatten()
+            .filter(|o| o.is_expired(now))
+            .map(|o| o.order_id.clone())
+            .collect();
+
+        let expired = expired
+            .iter()
+            .filter_map(|order_id| self.cancel_order(order_id))
+            .collect();
+        self.publish_depth_changes();
+        expired
+    }
+
+    fn get_order_by_id_mut(&mut self, order_id: &str) -> Option<&mut Order> {
+        let &(side, price) = self.order_index.get(order_id)?;
+
+        self.book_mut(side)
+            .get_mut(&price)?
+            .iter_mut()
+            .find(|o| o.order_id == order_id)
+    }
+
+    // Takes a resting order out of the book because it was cancelled, rather than
+    // amended, replaced or moved
+    fn cancel_order(&mut self, order_id: &str) -> Option<Order> {
+        let order = self.take_order(order_id)?;
+        self.events
+            .publish(OrderBookEvent::OrderCancelled(order.order_id.clone()));
+        Some(order)
+    }
+
+    fn publish_modified(&mut self, order_id: String, amount: Decimal) {
+        if let Some(&(side, price)) = self.order_index.get(&order_id) {
+            self.mark_level_changed(side, price);
+        }
+        // An iceberg only shows its displayed slice
+        let amount = self
+            .iceberg_slices
+            .get(&order_id)
+            .copied()
+            .unwrap_or(amount);
+        self.events
+            .publish(OrderBookEvent::OrderModified(order_id, amount));
+    }
+
+    fn mark_level_changed(&mut self, side: Side, price: Decimal) {
+        if !self.changed_levels.contains(&(side, price)) {
+            self.changed_levels.push((side, price));
+        }
+    }
+
+    // Publishes the new total of every level changed since the last call
+    fn publish_depth_changes(&mut self) {
+        for (side, price) in std::mem::take(&mut self.changed_levels) {
+            let amount = self.get_volume_at_price(side, price);
+            self.events
+                .publish(OrderBookEvent::DepthChanged(side, price, amount));
+        }
+    }
+
+    // Removes a resting order from the book by id, dropping its price level if empty
+    fn take_order(&mut self, order_id: &str) -> Option<Order> {
+        self.take_order_at(order_id).map(|(order, _)| order)
+    }
+
+    // Same as take_order, also returning the order's position in its level
+    fn take_order_at(&mut self, order_id: &str) -> Option<(Order, usize)> {
+        let &(side, price) = self.order_index.get(order_id)?;
+        let book = self.book_mut(side);
+        let orders = book.get_mut(&price)?;
+        let index = orders.iter().position(|o| o.order_id == order_id)?;
+        let order = orders.remove(index);
+        if orders.is_empty() {
+            book.remove(&price);
+        }
+        self.unindex_order(&order.order_id, &order.account_id);
+        self.closed_orders
+            .insert(order.order_id.clone(), OrderStatus::Cancelled);
+
+        Some((order, index))
+    }
+
+    // Adds a resting order to the secondary indexes
+    fn index_order(&mut self, order: &Order, price: Decimal) {
+        self.active_order_ids.insert(order.order_id.clone());
+        self.closed_orders.remove(&order.order_id);
+        self.order_index
+            .insert(order.order_id.clone(), (order.side, price));
+        match order.side {
+            Side::Buy => self.bid_count += 1,
+            Side::Sell => self.ask_count += 1,
+        }
+        self.account_orders
+            .entry(order.account_id.clone())
+            .or_default()
+            .insert(order.order_id.clone());
+        self.mark_level_changed(order.side, price);
+    }
+
+    // Drops an order that has left the book from the secondary indexes
+    fn unindex_order(&mut self, order_id: &str, account_id: &str) {
+        self.active_order_ids.remove(order_id);
+        if let Some((side, price)) = self.order_index.remove(order_id) {
+            match side {
+                Side::Buy => self.bid_count -= 1,
+                Side::Sell => self.ask_count -= 1,
+            }
+            self.mark_level_changed(side, price);
+        }
+        self.iceberg_slices.remove(order_id);
+        self.events
+            .publish(OrderBookEvent::OrderRemoved(order_id.to_string()));
+        if let Some(order_ids) = self.account_orders.get_mut(account_id) {
+            order_ids.remove(order_id);
+            if order_ids.is_empty() {
+                self.account_orders.remove(account_id);
+            }
+        }
+    }
+
+    fn book_mut(&mut self, side: Side) -> &mut BTreeMap<Decimal, Vec<Order>> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn check_tick_size(&self, price: Decimal) -> Result<(), OrderBookError> {
+        match self.config.tick_size {
+            Some(tick_size) if !(price % tick_size).is_zero() => {
+                Err(OrderBookError::InvalidTickSize(price.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_lot_size(&self, amount: Decimal) -> Result<(), OrderBookError> {
+        match self.config.lot_size {
+            Some(lot_size) if !(amount % lot_size).is_zero() => {
+                Err(OrderBookError::InvalidLotSize(amount.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // A limit order is counted at its full amount and price, as if all of it came to
+    // rest. Market orders never rest, so they aren't limited.
+    fn check_exposure(&self, order: &Order) -> Result<(), OrderBookError> {
+        let (Some(&limit), Some(price)) = (
+            self.account_limits.get(&order.account_id),
+            order.price_limit()?,
+        ) else {
+            return Ok(());
+        };
+        let notional = parse_amount(&order.amount)? * price;
+        // An amend or replacement gives up the exposure of the order it replaces
+        let replaced = self
+            .get_order_by_id(&order.order_id)
+            .filter(|resting| resting.account_id == order.account_id)
+            .and_then(|resting| resting.notional_value().ok())
+            .unwrap_or(Decimal::ZERO);
+        if self.account_open_notional(&order.account_id) - replaced + notional > limit {
+            return Err(OrderBookError::ExposureLimitExceeded(
+                order.order_id.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_order_size(&self, amount: Decimal) -> Result<(), OrderBookError> {
+        if let Some(minimum) = self.config.min_order_size
+            && amount < minimum
+        {
+            return Err(OrderBookError::OrderTooSmall {
+                minimum,
+                submitted: amount,
+            });
+        }
+        if let Some(maximum) = self.config.max_order_size
+            && amount > maximum
+        {
+            return Err(OrderBookError::OrderTooLarge {
+                maximum,
+                submitted: amount,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_price_band(&self, price: Decimal) -> Result<(), OrderBookError> {
+        match self.price_band {
+            Some(band) if price < band.min_price || price > band.max_price => {
+                Err(OrderBookError::PriceOutOfBand(price.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Whether the order would trade against the opposite side on arrival
+    fn would_match(&self, order: &Order) -> Result<bool, OrderBookError> {
+        let limit_price = order.price_limit()?;
+
+        Ok(match order.side {
+            Side::Buy => self
+                .get_best_ask()
+                .is_some_and(|ask| limit_price.is_none_or(|limit| ask <= limit)),
+            Side::Sell => self
+                .get_best_bid()
+                .is_some_and(|bid| limit_price.is_none_or(|limit| bid >= limit)),
+        })
+    }
+
+    // Amount the opposite side of the book could fill at the order's limit price,
+    // walked the way match_order walks it: expired orders and the taker's own orders
+    // under self-trade prevention don't count, and nothing does while halted, from a
+    // price that trips the circuit breaker on, or after a self-trade that cancels the
+    // taker
+    fn fillable_amount(&self, order: &Order) -> Result<Decimal, OrderBookError> {
+        if self.is_halted() {
+            return Ok(Decimal::ZERO);
+        }
+        let limit_price = order.price_limit()?;
+        let levels: Vec<(&Decimal, &Vec<Order>)> = match order.side {
+            Side::Buy => self
+                .asks
+                .iter()
+                .take_while(|(price, _)| limit_price.is_none_or(|limit| **price <= limit))
+                .collect(),
+            Side::Sell => self
+                .bids
+                .iter()
+                .rev()
+                .take_while(|(price, _)| limit_price.is_none_or(|limit| **price >= limit))
+                .collect(),
+        };
+
+        let now = self.now();
+        let self_trade_prevention = self.config.self_trade_prevention;
+        let mut fillable = Decimal::ZERO;
+        for (price, orders) in levels {
+            if self
+                .circuit_breaker
+                .is_some_and(|breaker| breaker.trips_at(*price))
+            {
+                break;
+            }
+            for resting_order in orders {
+                if resting_order.is_expired(now) {
+                    continue;
+                }
+                if resting_order.account_id == order.account_id
+                    && self_trade_prevention != SelfTradePrevention::Allow
+                {
+                    if matches!(
+                        self_trade_prevention,
+                        SelfTradePrevention::CancelTaker | SelfTradePrevention::CancelBoth
+                    ) {
+                        return Ok(fillable);
+                    }
+                    continue;
+                }
+                fillable += parse_amount(&resting_order.amount)?;
+            }
+        }
+        Ok(fillable)
+    }
+
+    fn get_remaining_order(
+        &self,
+        original_order: &Order,
+        traded_amount: Decimal,
+    ) -> Result<Option<Order>, OrderBookError> {
+        let original_amount = parse_amount(&original_order.amount)?;
+
+        // Calculate remaining amount
+        let remaining_amount = original_amount - traded_amount;
+
+        if remaining_amount > Decimal::ZERO {
+            // Create a new order with the remaining amount
+            let mut remaining_order = original_order.clone();
+            remaining_order.amount = remaining_amount.to_string();
+            remaining_order.status = original_order.status.with_fill(traded_amount);
+            Ok(Some(remaining_order))
+        } else {
+            Ok(None)
+        }
+    }
+    pub fn generate_order_book_output(&self) -> Vec<OrderBookEntry> {
+        let mut entries = Vec::new();
+
+        for (price, orders) in self.bids.iter().chain(&self.asks) {
+            for order in orders {
+                entries.push(self.displayed_entry(order, *price));
+            }
+        }
+
+        entries
+    }
+
+    // Copy of `trade` with its price and amount written with the configured
+    // precision, for output. The stored trade keeps the exact values.
+    pub fn format_trade(&self, trade: &Trade) -> Trade {
+        let mut trade = trade.clone();
+        if let Ok(price) = parse_price(&trade.price) {
+            trade.price = format_decimal(price, self.config.price_precision);
+        }
+        if let Ok(amount) = parse_amount(&trade.amount) {
+            trade.amount = format_decimal(amount, self.config.amount_precision);
+        }
+        trade
+    }
+
+    // Book entry as the market sees it, with only the displayed slice of an iceberg
+    fn displayed_entry(&self, order: &Order, price: Decimal) -> OrderBookEntry {
+        let mut entry = book_entry(order, price);
+        if let Some(slice) = self.iceberg_slices.get(&order.order_id) {
+            entry.amount = slice.to_string();
+        }
+        self.format_entry(entry)
+    }
+
+    // Total of a level's orders as the market sees it, with icebergs counting only
+    // their displayed slice
+    fn displayed_amount(&self, orders: &[Order]) -> Decimal {
+        orders
+            .iter()
+            .map(|order| match self.iceberg_slices.get(&order.order_id) {
+                Some(slice) => *slice,
+                None => Decimal::from_str(&order.amount).unwrap_or_default(),
+            })
+            .sum()
+    }
+
+    // Writes an entry's price and amount with the configured precision
+    fn format_entry(&self, mut entry: OrderBookEntry) -> OrderBookEntry {
+        if let Ok(price) = parse_price(&entry.price) {
+            entry.price = format_decimal(price, self.config.price_precision);
+        }
+        if let Ok(amount) = parse_amount(&entry.amount) {
+            entry.amount = format_decimal(amount, self.config.amount_precision);
+        }
+        entry
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_order_count() == 0
+    }
+
+    pub fn bid_count(&self) -> usize {
+        self.bid_count
+    }
+
+    pub fn ask_count(&self) -> usize {
+        self.ask_count
+    }
+
+    pub fn total_order_count(&self) -> usize {
+        self.bid_count + self.ask_count
+    }
+
+    // Highest resting bid price
+    pub fn get_best_bid(&self) -> Option<Decimal> {
+        self.bids.keys().next_back().copied()
+    }
+
+    // Lowest resting ask price
+    pub fn get_best_ask(&self) -> Option<Decimal> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn get_best_bid_orders(&self) -> Option<&[Order]> {
+        self.bids
+            .values()
+            .next_back()
+            .map(|orders| orders.as_slice())
+    }
+
+    pub fn get_best_ask_orders(&self) -> Option<&[Order]> {
+        self.asks.values().next().map(|orders| orders.as_slice())
+    }
+
+    pub fn get_spread(&self) -> Option<Decimal> {
+        Some(self.get_best_ask()? - self.get_best_bid()?)
+    }
+
+    pub fn get_mid_price(&self) -> Option<Decimal> {
+        Some((self.get_best_ask()? + self.get_best_bid()?) / Decimal::TWO)
+    }
+
+    // Micro-price: the mid price weighted by the size at the top of each side, so
+    // it leans toward the best ask when bids are deeper and vice versa
+    pub fn weighted_mid_price(&self) -> Option<Decimal> {
+        let best_bid = self.get_best_bid()?;
+        let best_ask = self.get_best_ask()?;
+        let bid_size = self.displayed_amount(self.get_best_bid_orders()?);
+        let ask_size = self.displayed_amount(self.get_best_ask_orders()?);
+        let total_size = bid_size + ask_size;
+        if total_size.is_zero() {
+            return None;
+        }
+
+        Some((best_ask * bid_size + best_bid * ask_size) / total_size)
+    }
+
+    // Spread in basis points of the mid price
+    pub fn get_spread_bps(&self) -> Option<Decimal> {
+        let mid_price = self.get_mid_price()?;
+        if mid_price.is_zero() {
+            return None;
+        }
+
+        Some(self.get_spread()? / mid_price * Decimal::from(10_000))
+    }
+
+    // Top `levels` price levels on each side of the book, as the market sees them:
+    // icebergs count only their displayed slice
+    pub fn get_depth(&self, levels: usize) -> OrderBookDepth {
+        OrderBookDepth {
+            bids: price_levels(self.bids.iter().rev().take(levels), |orders| {
+                self.displayed_amount(orders)
+            }),
+            asks: price_levels(self.asks.iter().take(levels), |orders| {
+                self.displayed_amount(orders)
+            }),
+        }
+    }
+
+    // Text ladder of the top `levels` price levels: asks above the mid price line
+    // (best ask nearest to it), bids below, each with a bar scaled to its amount
+    pub fn format_order_book_ascii(&self, levels: usize) -> String {
+        let depth = self.get_depth(levels);
+        let largest = depth
+            .bids
+            .iter()
+            .chain(&depth.asks)
+            .map(|level| level.total_amount)
+            .max()
+            .unwrap_or_default();
+
+        let row = |level: &PriceLevel| {
+            let width = if largest.is_zero() {
+                0
+            } else {
+                (level.total_amount / largest * Decimal::from(ASCII_BAR_WIDTH))
+                    .round()
+                    .to_usize()
+                    .unwrap_or(0)
+                    .max(1)
+            };
+            format!(
+                "{:>14} {:>14} {}\n",
+                level.price,
+                level.total_amount,
+                "#".repeat(width)
+            )
+        };
+
+        let mut output = format!("{:>14} {:>14}\n", "PRICE", "AMOUNT");
+        for level in depth.asks.iter().rev() {
+            output.push_str(&row(level));
+        }
+        let mid = match self.get_mid_price() {
+            Some(mid_price) => format!(" mid {} ", mid_price),
+            None => " mid - ".to_string(),
+        };
+        output.push_str(&format!("{:-^1$}\n", mid, 30 + ASCII_BAR_WIDTH));
+        for level in &depth.bids {
+            output.push_str(&row(level));
+        }
+
+        output
+    }
+
+    // Runs the order through the matching logic on a scratch copy of the book
+    pub fn simulate_order(&self, order: &Order) -> SimulationResult {
+        let amount = parse_amount(&order.amount).unwrap_or_default();
+        let estimated_trades = self
+            .scratch_copy()
+            .create_order(order.clone())
+            .unwrap_or_default();
+
+        let filled_amount: Decimal = estimated_trades
+            .iter()
+            .map(|t| Decimal::from_str(&t.amount).unwrap_or_default())
+            .sum();
+
+        SimulationResult {
+            unfilled_amount: amount - filled_amount,
+            average_fill_price: average_price(&estimated_trades),
+            estimated_trades,
+        }
+    }
+
+    // Average price an order for `amount` on `side` would fill at if it swept the
+    // opposite side of the book now, or None if the book can't fill all of it.
+    // Walks the price levels directly rather than matching a copy of the book like
+    // simulate_order, so it ignores self-trade prevention and iceberg slices.
+    pub fn expected_fill_price(&self, side: Side, amount: Decimal) -> Option<Decimal> {
+        if amount <= Decimal::ZERO {
+            return None;
+        }
+        let levels: Vec<(&Decimal, &Vec<Order>)> = match side {
+            Side::Buy => self.asks.iter().collect(),
+            Side::Sell => self.bids.iter().rev().collect(),
+        };
+
+        let mut remaining = amount;
+        let mut notional = Decimal::ZERO;
+        for (price, orders) in levels {
+            let filled = remaining.min(total_amount(orders));
+            notional += filled * price;
+            remaining -= filled;
+            if remaining.is_zero() {
+                return Some(notional / amount);
+            }
+        }
+        None
+    }
+
+    // Copy of the resting orders, without trade history, for dry runs
+    fn scratch_copy(&self) -> OrderBook {
+        OrderBook {
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+            trades: Vec::new(),
+            order_index: self.order_index.clone(),
+            account_orders: self.account_orders.clone(),
+            last_trade_price: self.last_trade_price,
+            last_aggressor: self.last_aggressor,
+            session_high: self.session_high,
+            session_low: self.session_low,
+            session_volume: self.session_volume,
+            config: self.config.clone(),
+            events: EventBus::default(),
+            order_log: Vec::new(),
+            max_order_log: self.max_order_log,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::new(),
+            fee_schedule: self.fee_schedule,
+            account_positions: self.account_positions.clone(),
+            iceberg_slices: self.iceberg_slices.clone(),
+            circuit_breaker: self.circuit_breaker,
+            halted_until: self.halted_until,
+            price_band: self.price_band,
+            sequence_number: self.sequence_number,
+            max_trade_history: self.max_trade_history,
+            trade_index: HashMap::new(),
+            evicted_trades: 0,
+            bid_count: self.bid_count,
+            ask_count: self.ask_count,
+            active_order_ids: self.active_order_ids.clone(),
+            seen_order_ids: None,
+            closed_orders: HashMap::new(),
+            audit_log: None,
+            session_number: self.session_number,
+            oco_links: self.oco_links.clone(),
+            halted_since: self.halted_since,
+            auction_mode: self.auction_mode,
+            trailing_stop_orders: self.trailing_stop_orders.clone(),
+            account_limits: self.account_limits.clone(),
+            rate_limits: self.rate_limits.clone(),
+            activity: ActivityLog::default(),
+            changed_levels: Vec::new(),
+            mark_prices: self.mark_prices.clone(),
+            simulated_time: self.simulated_time,
+        }
+    }
+
+    // Difference between the best price and the average fill price of a market order
+    // for `amount`, or None if the book can't fill it
+    pub fn expected_slippage(&self, side: Side, amount: Decimal) -> Option<Decimal> {
+        let book = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let pair = book.values().flatten().next()?.pair.clone();
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: String::new(),
+            amount: amount.to_string(),
+            order_id: String::new(),
+            pair,
+            limit_price: String::new(),
+            side,
+            time_in_force: Some(TimeInForce::Ioc),
+            order_type: OrderType::Market,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: self.now(),
+        };
+
+        let result = self.simulate_order(&order);
+        if result.unfilled_amount > Decimal::ZERO {
+            return None;
+        }
+
+        let average_fill_price = result.average_fill_price?;
+        match side {
+            Side::Buy => Some(average_fill_price - self.get_best_ask()?),
+            Side::Sell => Some(self.get_best_bid()? - average_fill_price),
+        }
+    }
+
+    pub fn get_total_bid_volume(&self) -> Decimal {
+        self.bids.values().map(|orders| total_amount(orders)).sum()
+    }
+
+    pub fn get_total_ask_volume(&self) -> Decimal {
+        self.asks.values().map(|orders| total_amount(orders)).sum()
+    }
+
+    // Displayed amount at `price`, like get_depth
+    pub fn get_volume_at_price(&self, side: Side, price: Decimal) -> Decimal {
+        let book = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+
+        book.get(&price)
+            .map(|orders| self.displayed_amount(orders))
+            .unwrap_or_default()
+    }
+
+    // Amount an order on `side` could take without moving the price more than
+    // `max_impact_pct` from the best opposite price. The impact is a fraction, so
+    // 0.005 counts the asks up to 0.5% above the best ask for a buy.
+    pub fn liquidity_at_price_impact(&self, side: Side, max_impact_pct: Decimal) -> Decimal {
+        match side {
+            Side::Buy => self.get_best_ask().map(|best_ask| {
+                let limit = best_ask * (Decimal::ONE + max_impact_pct);
+                self.asks
+                    .range(..=limit)
+                    .map(|(_, orders)| total_amount(orders))
+                    .sum()
+            }),
+            Side::Sell => self.get_best_bid().map(|best_bid| {
+                let limit = best_bid * (Decimal::ONE - max_impact_pct);
+                self.bids
+                    .range(limit..)
+                    .map(|(_, orders)| total_amount(orders))
+                    .sum()
+            }),
+        }
+        .unwrap_or_default()
+    }
+
+    // (bid volume - ask volume) / (bid volume + ask volume) over the top `levels`
+    // price levels, in [-1.0, 1.0]. Positive values indicate buy pressure.
+    pub fn order_book_imbalance(&self, levels: usize) -> Option<f64> {
+        let depth = self.get_depth(levels);
+        let bid_volume: Decimal = depth.bids.iter().map(|l| l.total_amount).sum();
+        let ask_volume: Decimal = depth.asks.iter().map(|l| l.total_amount).sum();
+
+        let total_volume = bid_volume + ask_volume;
+        if total_volume.is_zero() {
+            return None;
+        }
+
+        ((bid_volume - ask_volume) / total_volume).to_f64()
+    }
+
+    // Like order_book_imbalance, but each level's volume is weighted by the inverse of
+    // its distance from the mid price so the levels nearest the touch count most.
+    // In [-1.0, 1.0], and 0.0 when either side is empty and there is no mid price.
+    pub fn order_book_pressure(&self, depth_levels: usize) -> f64 {
+        let Some(mid_price) = self.get_mid_price() else {
+            return 0.0;
+        };
+        let depth = self.get_depth(depth_levels);
+        // A book crossed during an auction can have a level at the mid, which has no
+        // distance to weigh by and is left out
+        let weighted = |levels: &[PriceLevel]| -> Decimal {
+            levels
+                .iter()
+                .filter(|level| level.price != mid_price)
+                .map(|level| level.total_amount / (level.price - mid_price).abs())
+                .sum()
+        };
+        let bid_volume = weighted(&depth.bids);
+        let ask_volume = weighted(&depth.asks);
+
+        let total_volume = bid_volume + ask_volume;
+        if total_volume.is_zero() {
+            return 0.0;
+        }
+        ((bid_volume - ask_volume) / total_volume)
+            .to_f64()
+            .unwrap_or_default()
+    }
+
+    // Volume-weighted average price of all trades at or after `since_ms`
+    pub fn vwap(&self, since_ms: u64) -> Option<Decimal> {
+        let since = since_ms.saturating_mul(NANOS_PER_MILLI);
+        average_price(self.trades.iter().filter(|t| t.timestamp >= since))
+    }
+
+    // Current time (ns): the system clock, or the simulated time under FillSimulator
+    fn now(&self) -> u64 {
+        self.simulated_time.unwrap_or_else(get_current_timestamp)
+    }
+
+    pub(crate) fn set_simulated_time(&mut self, now: u64) {
+        self.simulated_time = Some(now);
+    }
+
+    // Start (ns) of the window covering the last `window_ms` milliseconds
+    fn window_start(&self, window_ms: u64) -> u64 {
+        self.now()
+            .saturating_sub(window_ms.saturating_mul(NANOS_PER_MILLI))
+    }
+
+    // New orders submitted per second over the last `window_ms` milliseconds, counting
+    // rejected ones. Submissions are kept for five minutes, so longer windows only
+    // see that much.
+    pub fn order_arrival_rate(&self, window_ms: u64) -> f64 {
+        per_second(
+            self.activity.arrival_count(self.window_start(window_ms)),
+            window_ms,
+        )
+    }
+
+    pub fn account_order_arrival_rate(&self, account_id: &str, window_ms: u64) -> f64 {
+        let arrivals = self
+            .activity
+            .account_arrival_count(account_id, self.window_start(window_ms));
+        per_second(arrivals, window_ms)
+    }
+
+    // Orders the account cancelled per new order it submitted over the last
+    // `window_ms` milliseconds. Rates near 1 can point to spoofing. It can go above
+    // 1 when the account cancels orders placed before the window.
+    pub fn cancel_rate(&self, account_id: &str, window_ms: u64) -> f64 {
+        let since = self.window_start(window_ms);
+        cancel_ratio(
+            self.activity.account_cancel_count(account_id, since),
+            self.activity.account_arrival_count(account_id, since),
+        )
+    }
+
+    // cancel_rate for every account that submitted or cancelled in the window
+    pub fn all_account_cancel_rates(&self, window_ms: u64) -> HashMap<String, f64> {
+        self.activity
+            .cancel_counts(self.window_start(window_ms))
+            .map(|(account_id, cancelled, submitted)| {
+                (account_id.to_string(), cancel_ratio(cancelled, submitted))
+            })
+            .collect()
+    }
+
+    // Share of the amount the account submitted over the last `window_ms`
+    // milliseconds that was filled, as maker or taker, in the same window; the rest
+    // is still resting or was cancelled. Fills of orders placed before the window
+    // count too, so it can go above 1.
+    pub fn fill_rate(&self, account_id: &str, window_ms: u64) -> f64 {
+        let (submitted, filled) = self
+            .activity
+            .account_volumes(account_id, self.window_start(window_ms));
+        if submitted.is_zero() {
+            return 0.0;
+        }
+        (filled / submitted).to_f64().unwrap_or_default()
+    }
+
+    // Statistics over the trades of the last `window_ms` milliseconds
+    pub fn rolling_window_stats(&self, window_ms: u64) -> RollingStats {
+        let since = self.window_start(window_ms);
+        let start = self.trades.partition_point(|t| t.timestamp < since);
+        let fills: Vec<(Decimal, Decimal, TradeSide)> = self.trades[start..]
+            .iter()
+            .filter_map(|trade| {
+                Some((
+                    Decimal::from_str(&trade.price).ok()?,
+                    Decimal::from_str(&trade.amount).ok()?,
+                    trade.aggressor,
+                ))
+            })
+            .collect();
+        if fills.is_empty() {
+            return RollingStats::default();
+        }
+
+        let mut stats = RollingStats {
+            trade_count: fills.len(),
+            ..RollingStats::default()
+        };
+        let mut notional = Decimal::ZERO;
+        for &(price, amount, aggressor) in &fills {
+            stats.total_volume += amount;
+            notional += price * amount;
+            match aggressor {
+                TradeSide::Buyer => stats.buy_volume += amount,
+                TradeSide::Seller => stats.sell_volume += amount,
+            }
+        }
+        if !stats.total_volume.is_zero() {
+            stats.avg_price = notional / stats.total_volume;
+        }
+
+        let count = Decimal::from(fills.len());
+        let mean = fills.iter().map(|(price, _, _)| price).sum::<Decimal>() / count;
+        let variance = fills
+            .iter()
+            .map(|(price, _, _)| (price - mean) * (price - mean))
+            .sum::<Decimal>()
+            / count;
+        stats.price_stddev = variance.sqrt().unwrap_or_default();
+        stats
+    }
+
+    // Traded volume at each price over the trade history, split by aggressor
+    pub fn volume_profile(&self) -> BTreeMap<Decimal, VolumeProfileLevel> {
+        self.volume_profile_by(|price| price)
+    }
+
+    // Same as volume_profile with prices grouped into buckets `tick_size` wide, each
+    // keyed by its lowest price. A tick size that isn't positive groups nothing.
+    pub fn volume_profile_bucketed(
+        &self,
+        tick_size: Decimal,
+    ) -> BTreeMap<Decimal, VolumeProfileLevel> {
+        if tick_size <= Decimal::ZERO {
+            return self.volume_profile();
+        }
+        self.volume_profile_by(|price| (price / tick_size).floor() * tick_size)
+    }
+
+    fn volume_profile_by(
+        &self,
+        bucket: impl Fn(Decimal) -> Decimal,
+    ) -> BTreeMap<Decimal, VolumeProfileLevel> {
+        let mut profile: BTreeMap<Decimal, VolumeProfileLevel> = BTreeMap::new();
+        for trade in &self.trades {
+            let (Ok(price), Ok(amount)) = (
+                Decimal::from_str(&trade.price),
+                Decimal::from_str(&trade.amount),
+            ) else {
+                continue;
+            };
+            let level = profile.entry(bucket(price).normalize()).or_default();
+            match trade.aggressor {
+                TradeSide::Buyer => level.buy_volume += amount,
+                TradeSide::Seller => level.sell_volume += amount,
+            }
+            level.trade_count += 1;
+        }
+        profile
+    }
+
+    pub fn vwap_for_pair(&self, pair: &str, since_ms: u64) -> Option<Decimal> {
+        let since = since_ms.saturating_mul(NANOS_PER_MILLI);
+        average_price(
+            self.trades
+                .iter()
+                .filter(|t| t.pair.to_string() == pair && t.timestamp >= since),
+        )
+    }
+
+    // Time-weighted average price over [from_ms, to_ms): the range is split into
+    // `interval_ms` buckets, each bucket is priced at the mean of its trade prices
+    // (or the last trade price if it has none) and the buckets are averaged. None
+    // if no trade falls inside the range.
+    pub fn twap(&self, from_ms: u64, to_ms: u64, interval_ms: u64) -> Option<Decimal> {
+        if interval_ms == 0 || from_ms >= to_ms {
+            return None;
+        }
+        let from = from_ms.saturating_mul(NANOS_PER_MILLI);
+        let to = to_ms.saturating_mul(NANOS_PER_MILLI);
+        let interval = interval_ms.saturating_mul(NANOS_PER_MILLI);
+
+        let start = self.trades.partition_point(|t| t.timestamp < from);
+        let end = self.trades.partition_point(|t| t.timestamp < to);
+        if start == end {
+            return None;
         }
-    }
 
-    pub fn process_order(&mut self, order: Order) -> Vec<Trade> {
-        let mut new_trades = Vec::new();
+        // Buckets ahead of the first trade in range carry the last price before it
+        let mut last_price = self.trades[..start]
+            .last()
+            .and_then(|trade| Decimal::from_str(&trade.price).ok());
+        let mut trades = self.trades[start..end].iter().peekable();
+        let mut total = Decimal::ZERO;
+        let mut buckets = 0u64;
 
-        match order.type_op.as_str() {
-            "CREATE" => {
-                if order.side == "BUY" {
-                    new_trades = self.match_buy_order(order.clone());
-                    // If the order is not completely filled, add it to the order book
-                    if let Some(remaining_order) = self.get_remaining_order(&order, &new_trades) {
-                        self.add_order(remaining_order);
-                    }
-                } else if order.side == "SELL" {
-                    new_trades = self.match_sell_order(order.clone());
-                    // If the order is not completely filled, add it to the order book
-                    if let Some(remaining_order) = self.get_remaining_order(&order, &new_trades) {
-                        self.add_order(remaining_order);
-                    }
+        let mut bucket_start = from;
+        while bucket_start < to {
+            let bucket_end = bucket_start.saturating_add(interval).min(to);
+            let mut price_sum = Decimal::ZERO;
+            let mut trade_count = 0u64;
+            while let Some(trade) = trades.next_if(|t| t.timestamp < bucket_end) {
+                if let Ok(price) = Decimal::from_str(&trade.price) {
+                    price_sum += price;
+                    trade_count += 1;
+                    last_price = Some(price);
                 }
             }
-            "DELETE" => {
-                self.remove_order(&order);
-            }
-            _ => {
-                eprintln!("Unknown order type: {}", order.type_op);
+
+            let bucket_price = if trade_count > 0 {
+                Some(price_sum / Decimal::from(trade_count))
+            } else {
+                last_price
+            };
+            if let Some(price) = bucket_price {
+                total += price;
+                buckets += 1;
             }
+            bucket_start = bucket_end;
         }
 
-        // Add new trades to the trade history
-        self.trades.extend(new_trades.clone());
+        if buckets == 0 {
+            None
+        } else {
+            Some(total / Decimal::from(buckets))
+        }
+    }
+
+    // Buckets the trade history into candles of `interval_ms`, oldest first. Candle
+    // open times are in nanoseconds, like trade timestamps.
+    pub fn generate_candles(&self, interval_ms: u64) -> Vec<Candle> {
+        if interval_ms == 0 {
+            return Vec::new();
+        }
+        let interval = interval_ms.saturating_mul(NANOS_PER_MILLI);
+
+        let mut candles: BTreeMap<u64, Candle> = BTreeMap::new();
+
+        for trade in &self.trades {
+            let (Ok(price), Ok(amount)) = (
+                Decimal::from_str(&trade.price),
+                Decimal::from_str(&trade.amount),
+            ) else {
+                continue;
+            };
+            let open_time = trade.timestamp / interval * interval;
+
+            let candle = candles.entry(open_time).or_insert(Candle {
+                open_time,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: Decimal::ZERO,
+                trade_count: 0,
+            });
+            candle.high = candle.high.max(price);
+            candle.low = candle.low.min(price);
+            candle.close = price;
+            candle.volume += amount;
+            candle.trade_count += 1;
+        }
 
-        new_trades
+        candles.into_values().collect()
     }
 
-    pub fn match_buy_order(&mut self, order: Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
-        let mut remaining_amount = Decimal::from_str(&order.amount).unwrap();
-        let buy_price = Decimal::from_str(&order.limit_price).unwrap();
+    // Turns on the audit log: every order action the book accepts from then on is
+    // recorded with the order's state before and after it
+    pub fn with_audit_log(mut self) -> Self {
+        self.audit_log = Some(AuditLog::default());
+        self
+    }
 
-        // Look for matching sell orders
-        let mut asks_to_remove = Vec::new();
-        let mut orders_to_update = Vec::new();
+    // Empty unless the book was built with_audit_log
+    pub fn audit_log(&self) -> &AuditLog {
+        self.audit_log.as_ref().unwrap_or(&EMPTY_AUDIT_LOG)
+    }
 
-        for (ask_price, ask_orders) in self.asks.iter_mut() {
-            if *ask_price > buy_price {
-                break;
-            }
+    // Remembers the ids of the last `capacity` created orders and ignores repeated
+    // CREATEs for them. A capacity of 0 turns deduplication off.
+    pub fn set_dedup_cache_size(&mut self, capacity: usize) {
+        self.seen_order_ids = NonZeroUsize::new(capacity).map(LruCache::new);
+    }
 
-            for ask_order in ask_orders.iter_mut() {
-                if remaining_amount <= Decimal::ZERO {
-                    break;
-                }
+    pub fn config(&self) -> &OrderBookConfig {
+        &self.config
+    }
+
+    pub fn set_matching_algorithm(&mut self, matching_algorithm: MatchingAlgorithm) {
+        self.config.matching_algorithm = matching_algorithm;
+    }
 
-                let ask_amount = Decimal::from_str(&ask_order.amount).unwrap();
+    pub fn set_tick_size(&mut self, tick_size: Option<Decimal>) -> Result<(), OrderBookError> {
+        check_increment("tick size", tick_size)?;
+        self.config.tick_size = tick_size;
+        Ok(())
+    }
 
-                let trade_amount = remaining_amount.min(ask_amount);
+    pub fn set_lot_size(&mut self, lot_size: Option<Decimal>) -> Result<(), OrderBookError> {
+        check_increment("lot size", lot_size)?;
+        self.config.lot_size = lot_size;
+        Ok(())
+    }
 
-                let trade = Trade {
-                    trade_id: Uuid::new_v4().to_string(),
-                    taker_order_id: order.order_id.clone(),
-                    maker_order_id: ask_order.order_id.clone(),
-                    pair: order.pair.clone(),
-                    price: ask_price.to_string(),
-                    amount: trade_amount.to_string(),
-                    timestamp: get_current_timestamp(),
-                };
+    pub fn set_price_precision(&mut self, decimals: u32) {
+        self.config.price_precision = Some(decimals);
+    }
 
-                trades.push(trade);
+    pub fn set_amount_precision(&mut self, decimals: u32) {
+        self.config.amount_precision = Some(decimals);
+    }
 
-                // Update the remaining amount
-                remaining_amount -= trade_amount;
+    // Caps the notional (amount * price) an account may have resting in the book.
+    // New orders that would go over it are rejected; orders already resting stay.
+    pub fn set_account_limit(&mut self, account_id: &str, max_open_notional: Decimal) {
+        self.account_limits
+            .insert(account_id.to_string(), max_open_notional);
+    }
 
-                if trade_amount < ask_amount {
-                    // Partial fill
-                    let new_amount = (ask_amount - trade_amount).to_string();
-                    ask_order.amount = new_amount;
-                } else {
-                    // Complete fill
-                    // Mark this order to be removed
-                    orders_to_update.push((ask_price.clone(), ask_order.order_id.clone()));
-                }
+    // Limits the account to `max_orders_per_second` order operations, cancellations
+    // aside. Bursts of up to that many orders are allowed after a quiet second.
+    pub fn set_account_rate_limit(&mut self, account_id: &str, max_orders_per_second: u32) {
+        self.rate_limits.insert(
+            account_id.to_string(),
+            TokenBucket::new(max_orders_per_second, self.now()),
+        );
+    }
+
+    fn take_rate_token(&mut self, account_id: &str) -> bool {
+        let now = self.now();
+        self.rate_limits
+            .get_mut(account_id)
+            .is_none_or(|bucket| bucket.try_take(now))
+    }
+
+    // Notional of the account's resting orders, counting hidden iceberg amounts
+    pub fn account_open_notional(&self, account_id: &str) -> Decimal {
+        self.get_orders_by_account(account_id)
+            .into_iter()
+            .filter_map(|order| order.notional_value().ok())
+            .sum()
+    }
+
+    pub fn trailing_stop_orders(&self) -> &[(String, TrailSpec)] {
+        &self.trailing_stop_orders
+    }
+
+    fn trailing_stop(&self, order_id: &str) -> Option<&TrailSpec> {
+        self.trailing_stop_orders
+            .iter()
+            .find(|(id, _)| id == order_id)
+            .map(|(_, spec)| spec)
+    }
+
+    // Moves each trailing stop after `current_price` where that is in its favour,
+    // then sends the stops the price has reached to the book as market orders, in
+    // the order they were placed. Returns the trades they made. Triggered stops
+    // wait while trading is halted.
+    pub fn update_trailing_stops(&mut self, current_price: Decimal) -> Vec<Trade> {
+        let mut triggered = Vec::new();
+        for (_, spec) in &mut self.trailing_stop_orders {
+            let stop_price = spec.stop_for(current_price);
+            if spec.trails(stop_price) {
+                spec.stop_price = Some(stop_price);
+            }
+            if spec.is_triggered(current_price) && self.halted_since.is_none() {
+                triggered.push(spec.order.order_id.clone());
             }
+        }
+
+        let mut trades = Vec::new();
+        for order_id in triggered {
+            let Some(index) = self
+                .trailing_stop_orders
+                .iter()
+                .position(|(id, _)| *id == order_id)
+            else {
+                continue;
+            };
+            let (_, TrailSpec { mut order, .. }) = self.trailing_stop_orders.remove(index);
+            order.order_type = OrderType::Market;
+            order.trail_amount = None;
+            order.trail_pct = None;
+            let amount = parse_amount(&order.amount).unwrap_or(Decimal::ZERO);
+
+            let mut new_trades = match self.create_order(order) {
+                Ok(new_trades) => new_trades,
+                Err(err) => {
+                    self.closed_orders
+                        .insert(order_id, OrderStatus::Rejected(err));
+                    continue;
+                }
+            };
+            self.record_trades(&mut new_trades);
+            self.cancel_oco_siblings(&new_trades);
+            let fill = self.fill_event(&order_id, amount, new_trades.clone(), false);
+            self.closed_orders.insert(order_id, fill.status);
+            trades.extend(new_trades);
+        }
+        self.publish_depth_changes();
+        trades
+    }
 
-            // Check if all orders at this price level are filled
-            if ask_orders.is_empty()
-                || ask_orders
+    // Moves every pegged order to its reference price, taken from the best unpegged
+    // bid and ask, and returns the orders that moved with their new prices. A moved
+    // order goes to the back of its new level. Orders are left where they are if
+    // their reference is missing or the new price would cross the book or fall
+    // outside the price band.
+    pub fn repeg_all(&mut self) -> Vec<(String, Decimal)> {
+        let best_bid = unpegged_best(self.bids.iter().rev());
+        let best_ask = unpegged_best(self.asks.iter());
+        let mid = best_bid
+            .zip(best_ask)
+            .map(|(bid, ask)| (bid + ask) / Decimal::TWO);
+
+        let pegged: Vec<(String, Side, Decimal, PegType)> = self
+            .bids
+            .iter()
+            .chain(self.asks.iter())
+            .flat_map(|(price, orders)| {
+                orders
                     .iter()
-                    .all(|o| Decimal::from_str(&o.amount).unwrap() <= Decimal::ZERO)
+                    .filter_map(move |o| o.peg.map(|peg| (o.order_id.clone(), o.side, *price, peg)))
+            })
+            .collect();
+
+        let mut moved = Vec::new();
+        for (order_id, side, price, peg) in pegged {
+            let reference = match peg {
+                PegType::MidPeg => mid,
+                PegType::BidPeg => best_bid,
+                PegType::AskPeg => best_ask,
+            };
+            let Some(new_price) = reference.map(|reference| self.round_to_tick(reference)) else {
+                continue;
+            };
+            let crosses = match side {
+                Side::Buy => self.get_best_ask().is_some_and(|ask| new_price >= ask),
+                Side::Sell => self.get_best_bid().is_some_and(|bid| new_price <= bid),
+            };
+            if new_price == price
+                || new_price <= Decimal::ZERO
+                || crosses
+                || self.check_price_band(new_price).is_err()
             {
-                asks_to_remove.push(*ask_price);
+                continue;
+            }
+
+            let Some(mut order) = self.take_order(&order_id) else {
+                continue;
+            };
+            order.limit_price = new_price.to_string();
+            if self.add_order(order).is_ok() {
+                moved.push((order_id, new_price));
             }
         }
+        self.publish_depth_changes();
+
+        moved
+    }
+
+    // Nearest valid price under the book's tick size
+    pub fn round_to_tick(&self, price: Decimal) -> Decimal {
+        match self.config.tick_size {
+            Some(tick_size) => (price / tick_size).round() * tick_size,
+            None => price,
+        }
+    }
+
+    pub fn set_price_band(&mut self, band: PriceBand) {
+        self.price_band = Some(band);
+    }
+
+    pub fn clear_price_band(&mut self) {
+        self.price_band = None;
+    }
+
+    // Replaces the circuit breaker and lifts any halt it caused
+    pub fn set_circuit_breaker(&mut self, circuit_breaker: Option<CircuitBreaker>) {
+        self.circuit_breaker = circuit_breaker;
+        self.halted_until = None;
+    }
+
+    // Whether trading is halted or a tripped circuit breaker is still suspending matching
+    pub fn is_halted(&self) -> bool {
+        self.halted_since.is_some()
+            || self
+                .halted_until
+                .is_some_and(|resume_at| self.now() < resume_at)
+    }
+
+    // Suspends trading until resume_trading: every order except a DELETE is rejected
+    // with TradingHalted. Halting an already halted book keeps the original time and
+    // reason.
+    pub fn halt_trading(&mut self, reason: HaltReason) {
+        self.halted_since.get_or_insert((self.now(), reason));
+    }
+
+    pub fn resume_trading(&mut self) {
+        self.halted_since = None;
+    }
+
+    // When (in nanoseconds) and why trading was halted, if it is
+    pub fn halted_since(&self) -> Option<(u64, HaltReason)> {
+        self.halted_since
+    }
+
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        self.fee_schedule
+    }
+
+    // Applies to trades executed from now on; past trades keep the fees they were charged
+    pub fn set_fee_schedule(&mut self, fee_schedule: FeeSchedule) {
+        self.fee_schedule = fee_schedule;
+    }
+
+    // Maker and taker fees charged across the stored trade history
+    pub fn total_fees_collected(&self) -> Decimal {
+        self.trades
+            .iter()
+            .map(|trade| trade.maker_fee + trade.taker_fee)
+            .sum()
+    }
+
+    // Net holding of an account in a pair, built up from every fill it has had.
+    // None until the account has traded the pair.
+    pub fn get_position(&self, account_id: &str, pair: &str) -> Option<&Position> {
+        self.account_positions.get(account_id)?.get(pair)
+    }
+
+    pub fn unrealized_pnl(
+        &self,
+        account_id: &str,
+        pair: &str,
+        mark_price: Decimal,
+    ) -> Option<Decimal> {
+        self.get_position(account_id, pair)
+            .map(|position| position.unrealized_pnl(mark_price))
+    }
+
+    // Sets the price positions in `pair` are marked to, independent of where it last
+    // traded, as a derivatives venue would from an index or funding model
+    pub fn set_mark_price(&mut self, pair: &str, price: Decimal) {
+        self.mark_prices.insert(pair.to_string(), price);
+    }
+
+    pub fn get_mark_price(&self, pair: &str) -> Option<Decimal> {
+        self.mark_prices.get(pair).copied()
+    }
+
+    // unrealized_pnl at the pair's mark price. None without a position or a mark;
+    // the last trade price is never used in place of the mark.
+    pub fn unrealized_pnl_at_mark(&self, account_id: &str, pair: &str) -> Option<Decimal> {
+        self.unrealized_pnl(account_id, pair, self.get_mark_price(pair)?)
+    }
+
+    // all_account_pnl at the mark prices set so far
+    pub fn all_account_pnl_at_mark(&self) -> HashMap<String, Decimal> {
+        self.all_account_pnl(&self.mark_prices)
+    }
 
-        // Remove filled orders
-        for (price, order_id) in orders_to_update {
-            if let Some(orders) = self.asks.get_mut(&price) {
-                orders.retain(|o| o.order_id != order_id);
-                if orders.is_empty() {
-                    asks_to_remove.push(price);
+    // Unrealized P&L per account, summed over its positions in the pairs `prices`
+    // has a mark price for. Accounts with no position in those pairs are left out.
+    pub fn all_account_pnl(&self, prices: &HashMap<String, Decimal>) -> HashMap<String, Decimal> {
+        let mut pnl = HashMap::new();
+        for (account_id, positions) in &self.account_positions {
+            for (pair, position) in positions {
+                if let Some(&mark_price) = prices.get(pair) {
+                    *pnl.entry(account_id.clone()).or_insert(Decimal::ZERO) +=
+                        position.unrealized_pnl(mark_price);
                 }
             }
         }
+        pnl
+    }
 
-        // Remove empty price levels
-        for price in asks_to_remove {
-            self.asks.remove(&price);
-        }
+    // Realized P&L per pair for an account, with FIFO cost basis
+    pub fn realized_pnl_for_account(&self, account_id: &str) -> HashMap<String, Decimal> {
+        self.account_positions
+            .get(account_id)
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|(pair, position)| (pair.clone(), position.realized_pnl))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 
-        trades
+    pub fn last_trade_price(&self) -> Option<Decimal> {
+        self.last_trade_price
     }
 
-    fn match_sell_order(&mut self, order: Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
-        let mut remaining_amount = Decimal::from_str(&order.amount).unwrap();
-        let sell_price = Decimal::from_str(&order.limit_price).unwrap();
+    pub fn session_high(&self) -> Option<Decimal> {
+        self.session_high
+    }
+
+    pub fn session_low(&self) -> Option<Decimal> {
+        self.session_low
+    }
+
+    pub fn session_volume(&self) -> Decimal {
+        self.session_volume
+    }
 
-        // We need to iterate through bids in reverse order (highest price first)
-        let mut bids_to_process: Vec<(Decimal, Vec<Order>)> = self
+    // Clears the session statistics at the start of a new trading day. The last
+    // trade price carries over as it isn't tied to a session.
+    pub fn reset_session(&mut self) {
+        self.session_high = None;
+        self.session_low = None;
+        self.session_volume = Decimal::ZERO;
+    }
+
+    // End-of-day reset: cancels every resting order, clears the session statistics
+    // and, unless `preserve_trades` is set, the trade history, then starts the next
+    // session. Returns the cancelled orders, bids then asks.
+    pub fn reset(&mut self, preserve_trades: bool) -> Vec<Order> {
+        let order_ids: Vec<String> = self
             .bids
+            .values()
+            .chain(self.asks.values())
+            .flatten()
+            .map(|o| o.order_id.clone())
+            .collect();
+        let cancelled = order_ids
             .iter()
-            .filter(|(bid_price, _)| **bid_price >= sell_price)
-            .map(|(price, orders)| (*price, orders.clone()))
+            .filter_map(|order_id| self.cancel_order(order_id))
             .collect();
+        self.publish_depth_changes();
 
-        // Sort by price (highest first)
-        bids_to_process.sort_by(|(price_a, _), (price_b, _)| price_b.cmp(price_a));
+        self.reset_session();
+        if !preserve_trades {
+            self.clear_trades();
+        }
+        self.session_number += 1;
 
-        // Process each bid
-        for (bid_price, mut bid_orders) in bids_to_process {
-            if remaining_amount <= Decimal::ZERO {
-                break;
-            }
+        cancelled
+    }
 
-            let mut orders_to_update = Vec::new();
+    // Number of resets the book has been through, starting at 0
+    pub fn session_number(&self) -> u32 {
+        self.session_number
+    }
 
-            for bid_order in bid_orders.iter_mut() {
-                if remaining_amount <= Decimal::ZERO {
-                    break;
-                }
+    // Hash of every resting order's side, price, id and amount, for checking that
+    // replicas of a book agree. Orders are hashed in price then id order, so the
+    // result doesn't depend on queue order or how the amounts were written.
+    pub fn compute_checksum(&self) -> u64 {
+        orders_checksum(self.bids.values().chain(self.asks.values()).flatten())
+    }
 
-                let bid_amount = Decimal::from_str(&bid_order.amount).unwrap();
+    pub fn snapshot(&self) -> OrderBookSnapshot {
+        OrderBookSnapshot {
+            orders: self
+                .bids
+                .values()
+                .chain(self.asks.values())
+                .flatten()
+                .cloned()
+                .collect(),
+            trades: self.trades.clone(),
+            config: self.config.clone(),
+            fee_schedule: self.fee_schedule,
+            circuit_breaker: self.circuit_breaker,
+        }
+    }
 
-                // Calculate the amount that can be matched
-                let trade_amount = remaining_amount.min(bid_amount);
+    // Rebuilds a book from a snapshot, with the same trading rules. Fails if the
+    // snapshot's config is invalid.
+    pub fn restore(snapshot: OrderBookSnapshot) -> Result<OrderBook, OrderBookError> {
+        let mut order_book = OrderBook::new(snapshot.config)?;
+        order_book.fee_schedule = snapshot.fee_schedule;
+        order_book.circuit_breaker = snapshot.circuit_breaker;
+        Ok(order_book
+            .with_orders(snapshot.orders)
+            .with_trades_from_history(snapshot.trades))
+    }
 
-                // Create a new trade
-                let trade = Trade {
-                    trade_id: Uuid::new_v4().to_string(),
-                    taker_order_id: order.order_id.clone(),
-                    maker_order_id: bid_order.order_id.clone(),
-                    pair: order.pair.clone(),
-                    price: bid_price.to_string(),
-                    amount: trade_amount.to_string(),
-                    timestamp: get_current_timestamp(),
-                };
+    // Re-adds orders in the given order so each price level keeps the queue it had
+    // when the orders were taken
+    fn with_orders(mut self, orders: Vec<Order>) -> Self {
+        for order in orders {
+            // Orders in a snapshot were validated when they first entered a book
+            let _ = self.add_order(order);
+        }
+        self
+    }
 
-                trades.push(trade);
-
-                // Update the remaining amount
-                remaining_amount -= trade_amount;
-
-                // Update the bid order in the actual orderbook
-                if let Some(orders) = self.bids.get_mut(&bid_price) {
-                    for o in orders.iter_mut() {
-                        if o.order_id == bid_order.order_id {
-                            if trade_amount < bid_amount {
-                                // Partial fill
-                                o.amount = (bid_amount - trade_amount).to_string();
-                            } else {
-                                // Complete fill
-                                orders_to_update.push(o.order_id.clone());
-                            }
-                            break;
-                        }
-                    }
-                }
-            }
+    // Replaces the trade history with `trades`, given oldest first, and rebuilds the
+    // last price and session statistics from it. Resting orders are left alone.
+    pub fn with_trades_from_history(mut self, trades: Vec<Trade>) -> Self {
+        self.set_trade_history(trades);
+        self
+    }
 
-            // Remove filled orders
-            if let Some(orders) = self.bids.get_mut(&bid_price) {
-                orders.retain(|o| !orders_to_update.contains(&o.order_id));
-                if orders.is_empty() {
-                    self.bids.remove(&bid_price);
-                }
+    fn set_trade_history(&mut self, trades: Vec<Trade>) {
+        self.trade_index.clear();
+        self.evicted_trades = 0;
+        self.last_trade_price = None;
+        self.last_aggressor = trades.last().map(|trade| trade.aggressor);
+        self.reset_session();
+
+        for trade in &trades {
+            if let (Ok(price), Ok(amount)) = (
+                Decimal::from_str(&trade.price),
+                Decimal::from_str(&trade.amount),
+            ) {
+                self.update_session_stats(price, amount);
             }
         }
+        self.index_trades(&trades);
+        self.trades = trades;
+        self.evict_trade_history();
+    }
 
-        trades
+    // Compact binary form of snapshot(), for books too large to snapshot as JSON
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(&self.snapshot())
     }
 
-    pub fn add_order(&mut self, order: Order) {
-        let price = Decimal::from_str(&order.limit_price).unwrap();
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> Result<OrderBook, bincode::Error> {
+        let snapshot = bincode::deserialize(bytes)?;
+        OrderBook::restore(snapshot)
+            .map_err(|err| Box::new(bincode::ErrorKind::Custom(err.to_string())))
+    }
 
-        if order.side == "BUY" {
-            self.bids.entry(price).or_insert_with(Vec::new).push(order)
-        } else {
-            self.asks.entry(price).or_insert_with(Vec::new).push(order)
+    // FIX 4.4 execution report for the taker's fill in `trade`. The order status is
+    // the taker's status now, which is later than the trade for older trades. None
+    // if the trade doesn't record the taker's side and neither order can be found.
+    pub fn trade_to_fix44(&self, trade: &Trade) -> Option<String> {
+        let side = trade
+            .taker_side
+            .or_else(|| self.order_side(&trade.taker_order_id))
+            .or_else(|| {
+                self.order_side(&trade.maker_order_id)
+                    .map(|side| side.opposite())
+            })?;
+        let status = self.get_order_status(&trade.taker_order_id);
+        Some(fix::execution_report(
+            trade,
+            &trade.taker_order_id,
+            side,
+            status.as_ref(),
+        ))
+    }
+
+    // Side of a resting order, or of a logged one that has left the book
+    fn order_side(&self, order_id: &str) -> Option<Side> {
+        self.get_order_by_id(order_id)
+            .or_else(|| self.order_log.iter().rev().find(|o| o.order_id == order_id))
+            .map(|order| order.side)
+    }
+
+    // Appends an order to the event log. process_order logs every order it accepts,
+    // so replaying the log with the book's config reproduces the book.
+    pub fn log_order(&mut self, order: &Order) {
+        self.push_order_log(order.clone());
+    }
+
+    fn push_order_log(&mut self, order: Order) {
+        self.order_log.push(order);
+        self.evict_order_log();
+    }
+
+    fn evict_order_log(&mut self) {
+        if let Some(max) = self.max_order_log
+            && self.order_log.len() > max
+        {
+            let excess = self.order_log.len() - max;
+            self.order_log.drain(..excess);
         }
     }
 
-    pub fn remove_order(&mut self, order: &Order) {
-        let price = Decimal::from_str(&order.limit_price).unwrap();
+    pub fn order_log(&self) -> &[Order] {
+        &self.order_log
+    }
 
-        if order.side == "BUY" {
-            if let Some(orders) = self.bids.get_mut(&price) {
-                orders.retain(|o| o.order_id != order.order_id);
-                if orders.is_empty() {
-                    self.bids.remove(&price);
-                }
+    // Caps the order log, evicting the oldest orders beyond `max`. A capped log
+    // only replays to the same book if nothing was evicted.
+    pub fn set_max_order_log(&mut self, max: Option<usize>) {
+        self.max_order_log = max;
+        self.evict_order_log();
+    }
+
+    // Rebuilds a book with `config` by processing logged orders in sequence. Only
+    // accepted orders are logged, so with the original config each one is accepted
+    // again; any the book still rejects are skipped.
+    pub fn replay_from_log(
+        config: OrderBookConfig,
+        events: impl IntoIterator<Item = Order>,
+    ) -> Result<(OrderBook, Vec<Trade>), OrderBookError> {
+        let mut order_book = OrderBook::new(config)?;
+        let mut trades = Vec::new();
+
+        for order in events {
+            if let Ok((_, new_trades)) = order_book.process_order(order) {
+                trades.extend(new_trades);
             }
-        } else {
-            if let Some(orders) = self.asks.get_mut(&price) {
-                orders.retain(|o| o.order_id != order.order_id);
-                if orders.is_empty() {
-                    self.asks.remove(&price);
+        }
+
+        Ok((order_book, trades))
+    }
+
+    // Deltas that turn `before` into `after`. Orders that moved price or grew lose
+    // their queue position, so they are sent as a remove followed by an add.
+    pub fn diff(before: &OrderBookSnapshot, after: &OrderBookSnapshot) -> Vec<OrderBookDelta> {
+        let before_orders: HashMap<&str, &Order> = before
+            .orders
+            .iter()
+            .map(|o| (o.order_id.as_str(), o))
+            .collect();
+        let after_ids: HashSet<&str> = after.orders.iter().map(|o| o.order_id.as_str()).collect();
+
+        let mut deltas: Vec<OrderBookDelta> = before
+            .orders
+            .iter()
+            .filter(|o| !after_ids.contains(o.order_id.as_str()))
+            .map(|o| OrderBookDelta::Remove(o.order_id.clone()))
+            .collect();
+
+        for order in &after.orders {
+            let price = Decimal::from_str(&order.limit_price).unwrap_or_default();
+            let amount = Decimal::from_str(&order.amount).unwrap_or_default();
+
+            match before_orders.get(order.order_id.as_str()) {
+                None => deltas.push(OrderBookDelta::Add(book_entry(order, price))),
+                Some(previous) => {
+                    let previous_price =
+                        Decimal::from_str(&previous.limit_price).unwrap_or_default();
+                    let previous_amount = Decimal::from_str(&previous.amount).unwrap_or_default();
+
+                    if previous.side != order.side
+                        || previous_price != price
+                        || amount > previous_amount
+                    {
+                        deltas.push(OrderBookDelta::Remove(order.order_id.clone()));
+                        deltas.push(OrderBookDelta::Add(book_entry(order, price)));
+                    } else if amount < previous_amount {
+                        deltas.push(OrderBookDelta::Modify(order.order_id.clone(), amount));
+                    }
                 }
             }
         }
+
+        deltas
     }
+}
 
-    fn get_remaining_order(&self, original_order: &Order, trades: &[Trade]) -> Option<Order> {
-        let original_amount = Decimal::from_str(&original_order.amount).unwrap();
+// Smallest share handed out by pro-rata matching when the book has no lot size
+const PRO_RATA_UNIT: Decimal = Decimal::from_parts(1, 0, 0, false, 8);
 
-        // Calculate traded amount
-        let traded_amount: Decimal = trades
-            .iter()
-            .filter(|t| t.taker_order_id == original_order.order_id)
-            .map(|t| Decimal::from_str(&t.amount).unwrap())
-            .sum();
+// Splits `amount` between orders in proportion to their sizes. Shares are rounded
+// down to a multiple of `unit` and what rounding leaves over is handed out a unit at
+// a time, round-robin from the largest order (the earliest one on a tie), so the
+// whole amount is allocated at this level if it can be. Orders are never allocated
+// more than their size.
+fn pro_rata_allocations(
+    sizes: &[(String, Decimal)],
+    amount: Decimal,
+    unit: Decimal,
+) -> HashMap<String, Decimal> {
+    let total: Decimal = sizes.iter().map(|(_, size)| *size).sum();
+    if amount >= total {
+        return sizes.iter().cloned().collect();
+    }
 
-        // Calculate remaining amount
-        let remaining_amount = original_amount - traded_amount;
+    let mut allocations: HashMap<String, Decimal> = sizes
+        .iter()
+        .map(|(order_id, size)| {
+            let share = (amount * *size / total / unit).floor() * unit;
+            (order_id.clone(), share.normalize())
+        })
+        .collect();
 
-        if remaining_amount > Decimal::ZERO {
-            // Create a new order with the remaining amount
-            let mut remaining_order = original_order.clone();
-            remaining_order.amount = remaining_amount.to_string();
-            Some(remaining_order)
-        } else {
-            None
+    // Stable, so equal sizes keep their queue order
+    let mut by_size: Vec<&(String, Decimal)> = sizes.iter().collect();
+    by_size.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    let mut leftover = amount - allocations.values().copied().sum::<Decimal>();
+    while leftover > Decimal::ZERO {
+        let mut allocated_any = false;
+        for (order_id, size) in &by_size {
+            let Some(share) = allocations.get_mut(order_id) else {
+                continue;
+            };
+            let extra = unit.min(leftover).min(*size - *share);
+            if extra > Decimal::ZERO {
+                *share += extra;
+                leftover -= extra;
+                allocated_any = true;
+            }
+            if leftover <= Decimal::ZERO {
+                break;
+            }
+        }
+        if !allocated_any {
+            break;
         }
     }
-    pub fn generate_order_book_output(&self) -> Vec<OrderBookEntry> {
-        let mut entries = Vec::new();
 
-        for (price, orders) in &self.bids {
-            for order in orders {
-                entries.push(OrderBookEntry {
-                    order_id: order.order_id.clone(),
-                    account_id: order.account_id.clone(),
-                    pair: order.pair.clone(),
-                    side: order.side.clone(),
-                    amount: order.amount.clone(),
-                    price: price.to_string(),
-                    timestamp: order.timestamp,
-                });
+    allocations
+}
+
+// Fixed number of decimal places when `precision` is set, so 50000 and 50000.00
+// come out the same
+fn format_decimal(value: Decimal, precision: Option<u32>) -> String {
+    match precision {
+        Some(decimals) => format!("{:.*}", decimals as usize, value.round_dp(decimals)),
+        None => value.to_string(),
+    }
+}
+
+fn book_entry(order: &Order, price: Decimal) -> OrderBookEntry {
+    OrderBookEntry {
+        order_id: order.order_id.clone(),
+        account_id: order.account_id.clone(),
+        pair: order.pair.clone(),
+        side: order.side,
+        amount: order.amount.clone(),
+        price: price.to_string(),
+        timestamp: order.timestamp,
+        sequence_number: order.sequence_number,
+    }
+}
+
+// Resting limit order described by a book entry
+fn order_from_entry(entry: &OrderBookEntry) -> Order {
+    Order {
+        type_op: Operation::Create,
+        account_id: entry.account_id.clone(),
+        amount: entry.amount.clone(),
+        order_id: entry.order_id.clone(),
+        pair: entry.pair.clone(),
+        limit_price: entry.price.clone(),
+        side: entry.side,
+        time_in_force: Some(TimeInForce::Gtc),
+        order_type: OrderType::Limit,
+        new_amount: None,
+        post_only: false,
+        expires_at_ms: None,
+        visible_amount: None,
+        peg: None,
+        sequence_number: entry.sequence_number,
+        status: OrderStatus::Open,
+        trail_amount: None,
+        trail_pct: None,
+        timestamp: entry.timestamp,
+    }
+}
+
+fn per_second(count: usize, window_ms: u64) -> f64 {
+    if window_ms == 0 {
+        return 0.0;
+    }
+    count as f64 * 1_000.0 / window_ms as f64
+}
+
+fn cancel_ratio(cancelled: usize, submitted: usize) -> f64 {
+    if submitted == 0 {
+        return 0.0;
+    }
+    cancelled as f64 / submitted as f64
+}
+
+fn orders_checksum<'a>(orders: impl IntoIterator<Item = &'a Order>) -> u64 {
+    let mut entries: Vec<(Decimal, &str, Side, Decimal)> = orders
+        .into_iter()
+        .map(|order| {
+            (
+                parse_price(&order.limit_price).unwrap_or_default(),
+                order.order_id.as_str(),
+                order.side,
+                parse_amount(&order.amount).unwrap_or_default(),
+            )
+        })
+        .collect();
+    entries.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    let mut hasher = Xxh3::new();
+    for (price, order_id, side, amount) in entries {
+        let side = match side {
+            Side::Buy => "B",
+            Side::Sell => "S",
+        };
+        let entry = format!(
+            "{}|{}|{}|{}\n",
+            side,
+            price.normalize(),
+            order_id,
+            amount.normalize()
+        );
+        hasher.update(entry.as_bytes());
+    }
+    hasher.digest()
+}
+
+// Volume-weighted average price of a set of trades
+fn average_price<'a>(trades: impl IntoIterator<Item = &'a Trade>) -> Option<Decimal> {
+    let mut volume = Decimal::ZERO;
+    let mut notional = Decimal::ZERO;
+
+    for trade in trades {
+        let price = Decimal::from_str(&trade.price).ok()?;
+        let amount = Decimal::from_str(&trade.amount).ok()?;
+        volume += amount;
+        notional += price * amount;
+    }
+
+    if volume.is_zero() {
+        None
+    } else {
+        Some(notional / volume)
+    }
+}
+
+// Best price, given levels best-first, with at least one order that isn't pegged
+fn unpegged_best<'a>(
+    mut levels: impl Iterator<Item = (&'a Decimal, &'a Vec<Order>)>,
+) -> Option<Decimal> {
+    levels
+        .find(|(_, orders)| orders.iter().any(|o| o.peg.is_none()))
+        .map(|(price, _)| *price)
+}
+
+// Aggregates price levels, given best-first, accumulating amounts as it goes
+fn price_levels<'a>(
+    levels: impl Iterator<Item = (&'a Decimal, &'a Vec<Order>)>,
+    level_amount: impl Fn(&[Order]) -> Decimal,
+) -> Vec<PriceLevel> {
+    let mut cumulative_amount = Decimal::ZERO;
+
+    levels
+        .map(|(price, orders)| {
+            let total_amount = level_amount(orders);
+            cumulative_amount += total_amount;
+            PriceLevel {
+                price: *price,
+                total_amount,
+                order_count: orders.len(),
+                cumulative_amount,
             }
+        })
+        .collect()
+}
+
+// Holds one order book per trading pair and routes orders by `pair`
+#[derive(Debug, Default)]
+pub struct TradingEngine {
+    books: HashMap<String, OrderBook>,
+}
+
+impl TradingEngine {
+    pub fn new() -> Self {
+        TradingEngine {
+            books: HashMap::new(),
         }
+    }
 
-        for (price, orders) in &self.asks {
-            for order in orders {
-                entries.push(OrderBookEntry {
-                    order_id: order.order_id.clone(),
-                    account_id: order.account_id.clone(),
-                    pair: order.pair.clone(),
-                    side: order.side.clone(),
-                    amount: order.amount.clone(),
-                    price: price.to_string(),
-                    timestamp: order.timestamp,
-                });
+    pub fn get_or_create_book(&mut self, pair: &str) -> &mut OrderBook {
+        self.books.entry(pair.to_string()).or_default()
+    }
+
+    pub fn get_book(&self, pair: &str) -> Option<&OrderBook> {
+        self.books.get(pair)
+    }
+
+    pub fn process_order(
+        &mut self,
+        order: Order,
+    ) -> Result<(FillEvent, Vec<Trade>), OrderBookError> {
+        // Order ids are unique across the engine, not just within a pair, and an
+        // order can only be amended or cancelled through the pair it was placed on
+        let owning_pair = self
+            .books
+            .iter()
+            .find(|(_, book)| book.get_order_by_id(&order.order_id).is_some())
+            .map(|(pair, _)| pair.clone());
+        let pair = order.pair.to_string();
+
+        match (order.type_op, owning_pair) {
+            (Operation::Create, Some(_)) => {
+                return Err(OrderBookError::DuplicateOrderId(order.order_id));
+            }
+            (
+                Operation::Delete | Operation::Modify | Operation::CancelReplace,
+                Some(owning_pair),
+            ) if owning_pair != pair => {
+                return Err(OrderBookError::UnknownOrderId(order.order_id));
             }
+            _ => {}
         }
 
-        entries
+        if order.type_op == Operation::Create {
+            self.get_or_create_book(&pair).process_order(order)
+        } else {
+            self.books
+                .get_mut(&pair)
+                .ok_or_else(|| OrderBookError::UnknownOrderId(order.order_id.clone()))?
+                .process_order(order)
+        }
+    }
+
+    pub fn all_books(&self) -> impl Iterator<Item = (&str, &OrderBook)> {
+        self.books.iter().map(|(pair, book)| (pair.as_str(), book))
+    }
+}
+
+// Sum of the amounts of resting orders, which were validated when they entered the book
+fn total_amount(orders: &[Order]) -> Decimal {
+    orders
+        .iter()
+        .map(|o| Decimal::from_str(&o.amount).unwrap_or_default())
+        .sum()
+}
+
+fn parse_amount(amount: &str) -> Result<Decimal, OrderBookError> {
+    Decimal::from_str(amount).map_err(|_| OrderBookError::InvalidAmount(amount.to_string()))
+}
+
+fn parse_price(price: &str) -> Result<Decimal, OrderBookError> {
+    Decimal::from_str(price).map_err(|_| OrderBookError::InvalidPrice(price.to_string()))
+}
+
+// Time-ordered (UUID v7) id for a new order
+pub fn generate_order_id() -> String {
+    Uuid::now_v7().to_string()
+}
+
+// Time-ordered (UUID v7) id for a new trade
+pub fn generate_trade_id() -> String {
+    Uuid::now_v7().to_string()
+}
+
+// Millisecond creation time encoded in a UUID v7 trade id
+pub fn timestamp_from_trade_id(id: &str) -> Option<u64> {
+    let uuid = Uuid::parse_str(id).ok()?;
+    if uuid.get_version_num() != 7 {
+        return None;
     }
+    let (seconds, nanos) = uuid.get_timestamp()?.to_unix();
+    Some(seconds * 1_000 + u64::from(nanos) / 1_000_000)
 }
 
-// Get current timestamp in milliseconds
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+// Length of the bar drawn for the largest level by format_order_book_ascii
+const ASCII_BAR_WIDTH: usize = 20;
+
+// Get current timestamp in nanoseconds since the Unix epoch. A u64 holds nanosecond
+// timestamps until the year 2554, after which this wraps.
 pub fn get_current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis() as u64
+        .as_nanos() as u64
 }
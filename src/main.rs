@@ -12,19 +12,26 @@ fn main() -> io::Result<()> {
     let orders: Vec<Order> = serde_json::from_str(&contents)?;
 
     // Create order book
-    let mut order_book = OrderBook::new();
+    let mut order_book = OrderBook::default();
 
     // Process orders
     for mut order in orders {
         // Set timestamp
         order.timestamp = get_current_timestamp();
 
-        // Process order
-        order_book.process_order(order);
+        // Process order, skipping any that the order book rejects
+        let order_id = order.order_id.clone();
+        if let Err(e) = order_book.process_order(order) {
+            eprintln!("Skipping order {}: {}", order_id, e);
+        }
     }
 
     let order_book_output = order_book.generate_order_book_output();
-    let trades_output = order_book.trades;
+    let trades_output: Vec<_> = order_book
+        .trades
+        .iter()
+        .map(|trade| order_book.format_trade(trade))
+        .collect();
 
     let order_book_json = serde_json::to_string_pretty(&order_book_output)?;
     let mut order_book_file = File::create("orderbook.json")?;
@@ -0,0 +1,97 @@
+use crate::{Order, Trade};
+use prometheus::{Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::fmt;
+use std::time::Duration;
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    orders_processed: IntCounterVec,
+    trades_executed: IntCounterVec,
+    order_book_depth: IntGaugeVec,
+    matching_duration: Histogram,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let registry = Registry::new();
+
+        let orders_processed = IntCounterVec::new(
+            Opts::new("orders_processed_total", "Orders received by the book"),
+            &["side", "type"],
+        )
+        .unwrap();
+        let trades_executed = IntCounterVec::new(
+            Opts::new("trades_executed_total", "Trades executed by the book"),
+            &["pair"],
+        )
+        .unwrap();
+        let order_book_depth = IntGaugeVec::new(
+            Opts::new("order_book_depth", "Number of price levels on each side"),
+            &["pair", "side"],
+        )
+        .unwrap();
+        let matching_duration = Histogram::with_opts(HistogramOpts::new(
+            "order_matching_duration_seconds",
+            "Time spent processing an order",
+        ))
+        .unwrap();
+
+        // Registration only fails on duplicate names, which a fresh registry can't have
+        registry
+            .register(Box::new(orders_processed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(trades_executed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(order_book_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(matching_duration.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            orders_processed,
+            trades_executed,
+            order_book_depth,
+            matching_duration,
+        }
+    }
+
+    pub(crate) fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub(crate) fn order_received(&self, order: &Order) {
+        self.orders_processed
+            .with_label_values(&[&order.side.to_string(), &order.type_op.to_string()])
+            .inc();
+    }
+
+    pub(crate) fn order_processed(
+        &self,
+        pair: &str,
+        trades: &[Trade],
+        elapsed: Duration,
+        bid_levels: usize,
+        ask_levels: usize,
+    ) {
+        self.matching_duration.observe(elapsed.as_secs_f64());
+        self.trades_executed
+            .with_label_values(&[pair])
+            .inc_by(trades.len() as u64);
+        self.order_book_depth
+            .with_label_values(&[pair, "BUY"])
+            .set(bid_levels as i64);
+        self.order_book_depth
+            .with_label_values(&[pair, "SELL"])
+            .set(ask_levels as i64);
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{Side, Trade, parse_amount, parse_price};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+
+// An account's holding in one pair. Quantity is positive when long and negative when
+// short; fills that reduce it realize P&L against the oldest open lots first (FIFO).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub account_id: String,
+    pub pair: String,
+    pub quantity: Decimal,
+    pub avg_entry_price: Decimal,
+    pub realized_pnl: Decimal,
+    lots: VecDeque<Lot>,
+}
+
+// Open quantity acquired at a single price, signed like Position::quantity
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Lot {
+    quantity: Decimal,
+    price: Decimal,
+}
+
+impl Position {
+    pub(crate) fn new(account_id: &str, pair: &str) -> Self {
+        Position {
+            account_id: account_id.to_string(),
+            pair: pair.to_string(),
+            quantity: Decimal::ZERO,
+            avg_entry_price: Decimal::ZERO,
+            realized_pnl: Decimal::ZERO,
+            lots: VecDeque::new(),
+        }
+    }
+
+    // P&L of the open quantity if it were closed at `mark_price`. The signed quantity
+    // makes a short gain when the price falls below its entry.
+    pub fn unrealized_pnl(&self, mark_price: Decimal) -> Decimal {
+        (mark_price - self.avg_entry_price) * self.quantity
+    }
+
+    pub(crate) fn apply_fill(&mut self, side: Side, price: Decimal, amount: Decimal) {
+        let mut remaining = match side {
+            Side::Buy => amount,
+            Side::Sell => -amount,
+        };
+
+        // Close lots on the other side of the fill, oldest first
+        while remaining != Decimal::ZERO {
+            let Some(lot) = self.lots.front_mut() else {
+                break;
+            };
+            if lot.quantity.is_sign_positive() == remaining.is_sign_positive() {
+                break;
+            }
+
+            let closed = remaining.abs().min(lot.quantity.abs());
+            if lot.quantity.is_sign_positive() {
+                self.realized_pnl += (price - lot.price) * closed;
+                lot.quantity -= closed;
+                remaining += closed;
+            } else {
+                self.realized_pnl += (lot.price - price) * closed;
+                lot.quantity += closed;
+                remaining -= closed;
+            }
+
+            if lot.quantity == Decimal::ZERO {
+                self.lots.pop_front();
+            }
+        }
+
+        // Whatever is left opens a new lot
+        if remaining != Decimal::ZERO {
+            self.lots.push_back(Lot {
+                quantity: remaining,
+                price,
+            });
+        }
+
+        self.quantity = self.lots.iter().map(|lot| lot.quantity).sum();
+        self.avg_entry_price = if self.quantity == Decimal::ZERO {
+            Decimal::ZERO
+        } else {
+            self.lots
+                .iter()
+                .map(|lot| lot.quantity * lot.price)
+                .sum::<Decimal>()
+                / self.quantity
+        };
+    }
+}
+
+// Rebuilds an account's positions, keyed by pair, from a trade history alone, for
+// reconciling against a book that didn't track them from the start. Trades are
+// applied in timestamp order, the maker on the opposite side to the taker. Trades
+// recorded without a taker side or with an unparseable price or amount are skipped.
+pub fn positions_from_trades(trades: &[Trade], account_id: &str) -> HashMap<String, Position> {
+    let mut trades: Vec<&Trade> = trades.iter().collect();
+    trades.sort_by_key(|trade| trade.timestamp);
+
+    let mut positions = HashMap::new();
+    for trade in trades {
+        let (Some(taker_side), Ok(price), Ok(amount)) = (
+            trade.taker_side,
+            parse_price(&trade.price),
+            parse_amount(&trade.amount),
+        ) else {
+            continue;
+        };
+        for (trade_account_id, side) in [
+            (&trade.taker_account_id, taker_side),
+            (&trade.maker_account_id, taker_side.opposite()),
+        ] {
+            if trade_account_id == account_id {
+                let pair = trade.pair.to_string();
+                positions
+                    .entry(pair.clone())
+                    .or_insert_with(|| Position::new(account_id, &pair))
+                    .apply_fill(side, price, amount);
+            }
+        }
+    }
+    positions
+}
@@ -0,0 +1,39 @@
+use crate::NANOS_PER_MILLI;
+use rust_decimal::Decimal;
+
+const NANOS_PER_SECOND: u64 = 1_000 * NANOS_PER_MILLI;
+
+// Token bucket allowing `rate` orders per second. It holds at most one second's
+// worth of tokens and refills continuously, so an idle account can burst up to
+// `rate` orders at once.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    rate: u32,
+    tokens: Decimal,
+    last_refill_ns: u64,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(rate: u32, now_ns: u64) -> Self {
+        TokenBucket {
+            rate,
+            tokens: Decimal::from(rate),
+            last_refill_ns: now_ns,
+        }
+    }
+
+    // Takes a token if one is available
+    pub(crate) fn try_take(&mut self, now_ns: u64) -> bool {
+        let elapsed = now_ns.saturating_sub(self.last_refill_ns);
+        let refill =
+            Decimal::from(elapsed) * Decimal::from(self.rate) / Decimal::from(NANOS_PER_SECOND);
+        self.tokens = (self.tokens + refill).min(Decimal::from(self.rate));
+        self.last_refill_ns = self.last_refill_ns.max(now_ns);
+
+        if self.tokens < Decimal::ONE {
+            return false;
+        }
+        self.tokens -= Decimal::ONE;
+        true
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{NANOS_PER_MILLI, Order, OrderBook, Trade};
+
+// Time source for a simulation. It only moves when told to, and never backwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulatedClock {
+    now_ms: u64,
+}
+
+impl SimulatedClock {
+    pub fn new(start_ms: u64) -> Self {
+        SimulatedClock { now_ms: start_ms }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    // Moves the clock to `timestamp_ms`, or leaves it where it is if that's earlier
+    pub fn advance_to(&mut self, timestamp_ms: u64) {
+        self.now_ms = self.now_ms.max(timestamp_ms);
+    }
+}
+
+// Replays orders through a book at the times given instead of the system clock, for
+// backtesting. Matching, expiry, halts and rate limits all see the simulated time.
+#[derive(Debug)]
+pub struct FillSimulator {
+    book: OrderBook,
+    clock: SimulatedClock,
+}
+
+impl FillSimulator {
+    pub fn new(book: OrderBook) -> Self {
+        let mut simulator = FillSimulator {
+            book,
+            clock: SimulatedClock::default(),
+        };
+        simulator.sync_clock();
+        simulator
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn clock(&self) -> &SimulatedClock {
+        &self.clock
+    }
+
+    // Processes the order at `timestamp_ms` and returns the trades it made. A
+    // rejected order makes no trades.
+    pub fn step(&mut self, mut order: Order, timestamp_ms: u64) -> Vec<Trade> {
+        self.clock.advance_to(timestamp_ms);
+        self.sync_clock();
+        order.timestamp = self.clock.now_ms().saturating_mul(NANOS_PER_MILLI);
+        self.book
+            .process_order(order)
+            .map(|(_, trades)| trades)
+            .unwrap_or_default()
+    }
+
+    // Steps through the orders in timestamp order, keeping the given order for equal
+    // timestamps, and returns every trade made
+    pub fn fast_forward(&mut self, mut orders: Vec<(u64, Order)>) -> Vec<Trade> {
+        orders.sort_by_key(|(timestamp_ms, _)| *timestamp_ms);
+        orders
+            .into_iter()
+            .flat_map(|(timestamp_ms, order)| self.step(order, timestamp_ms))
+            .collect()
+    }
+
+    fn sync_clock(&mut self) {
+        self.book
+            .set_simulated_time(self.clock.now_ms().saturating_mul(NANOS_PER_MILLI));
+    }
+}
+
+impl Default for FillSimulator {
+    fn default() -> Self {
+        FillSimulator::new(OrderBook::default())
+    }
+}
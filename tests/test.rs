@@ -1,41 +1,77 @@
 #[cfg(test)]
 mod tests {
-    use trading_engine::{Order, OrderBook, get_current_timestamp};
+    use proptest::prelude::*;
+    use rust_decimal::Decimal;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use trading_engine::{
+        AuctionMode, BatchError, CircuitBreaker, FeeSchedule, FillSimulator, FixParseError,
+        HaltReason, InvariantViolation, MatchingAlgorithm, OcoOrderPair, Operation, Order,
+        OrderBook, OrderBookConfig, OrderBookDelta, OrderBookError, OrderBookEvent,
+        OrderBookSnapshot, OrderBuilder, OrderBuilderError, OrderStatus, OrderType, PegType,
+        PriceBand, RollingStats, SelfTradePrevention, Side, SimulatedClock, TickDirection,
+        TimeInForce, Trade, TradeFilter, TradeSide, TradingEngine, TradingPair, VolumeProfileLevel,
+        generate_order_id, generate_trade_id, get_current_timestamp, orders_from_csv,
+        parse_fix44_new_order, parse_fix44_new_order_single, positions_from_trades,
+        timestamp_from_trade_id, trades_to_csv,
+    };
 
     #[test]
     fn test_simple_trade_match() {
-        let mut order_book = OrderBook::new();
+        let mut order_book = OrderBook::default();
 
         // Create a sell order
         let sell_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "1".to_string(),
             amount: "1.0".to_string(),
             order_id: "1".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "SELL".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the sell order to the order book
-        let trades = order_book.process_order(sell_order);
+        let (_, trades) = order_book.process_order(sell_order).unwrap();
         assert_eq!(trades.len(), 0); // No trades yet
 
         // Create a matching buy order
         let buy_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "2".to_string(),
             amount: "0.5".to_string(),
             order_id: "2".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "BUY".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the buy order to the order book
-        let trades = order_book.process_order(buy_order);
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
 
         // Check that a trade was created
         assert_eq!(trades.len(), 1);
@@ -53,37 +89,59 @@ mod tests {
 
     #[test]
     fn test_complete_fill() {
-        let mut order_book = OrderBook::new();
+        let mut order_book = OrderBook::default();
 
         // Create a sell order
         let sell_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "1".to_string(),
             amount: "1.0".to_string(),
             order_id: "1".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "SELL".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the sell order to the order book
-        order_book.process_order(sell_order);
+        order_book.process_order(sell_order).unwrap();
 
         // Create a matching buy order that completely fills the sell order
         let buy_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "2".to_string(),
             amount: "1.0".to_string(),
             order_id: "2".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "BUY".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the buy order to the order book
-        let trades = order_book.process_order(buy_order);
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
 
         // Check that a trade was created
         assert_eq!(trades.len(), 1);
@@ -96,49 +154,82 @@ mod tests {
 
     #[test]
     fn test_price_priority() {
-        let mut order_book = OrderBook::new();
+        let mut order_book = OrderBook::default();
 
         // Create sell orders at different prices
         let sell_order_1 = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "1".to_string(),
             amount: "1.0".to_string(),
             order_id: "1".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "51000.0".to_string(),
-            side: "SELL".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         let sell_order_2 = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "1".to_string(),
             amount: "1.0".to_string(),
             order_id: "2".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "SELL".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the sell orders to the order book
-        order_book.process_order(sell_order_1);
-        order_book.process_order(sell_order_2);
+        order_book.process_order(sell_order_1).unwrap();
+        order_book.process_order(sell_order_2).unwrap();
 
         // Create a matching buy order
         let buy_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "2".to_string(),
             amount: "1.0".to_string(),
             order_id: "3".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "51000.0".to_string(),
-            side: "BUY".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the buy order to the order book
-        let trades = order_book.process_order(buy_order);
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
 
         // Check that the buy order matched with the lowest-priced sell order
         assert_eq!(trades.len(), 1);
@@ -148,22 +239,33 @@ mod tests {
 
     #[test]
     fn test_delete_order() {
-        let mut order_book = OrderBook::new();
+        let mut order_book = OrderBook::default();
 
         // Create a sell order
         let sell_order = Order {
-            type_op: "CREATE".to_string(),
+            type_op: Operation::Create,
             account_id: "1".to_string(),
             amount: "1.0".to_string(),
             order_id: "1".to_string(),
-            pair: "BTC/USDC".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
             limit_price: "50000.0".to_string(),
-            side: "SELL".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
             timestamp: get_current_timestamp(),
         };
 
         // Add the sell order to the order book
-        order_book.process_order(sell_order.clone());
+        order_book.process_order(sell_order.clone()).unwrap();
 
         // Check that the order is in the order book
         let order_book_entries = order_book.generate_order_book_output();
@@ -171,11 +273,5448 @@ mod tests {
 
         // Delete the order
         let mut delete_order = sell_order.clone();
-        delete_order.type_op = "DELETE".to_string();
-        order_book.process_order(delete_order);
+        delete_order.type_op = Operation::Delete;
+        order_book.process_order(delete_order).unwrap();
 
         // Check that the order is removed from the order book
         let order_book_entries = order_book.generate_order_book_output();
         assert_eq!(order_book_entries.len(), 0);
     }
+
+    #[test]
+    fn test_side_parsing() {
+        assert_eq!(Side::from_str("BUY").unwrap(), Side::Buy);
+        assert_eq!(Side::from_str("SELL").unwrap(), Side::Sell);
+        assert!(Side::from_str("buy").is_err());
+        assert!(Side::from_str("LONG").is_err());
+
+        // Display must round-trip with FromStr
+        assert_eq!(Side::Buy.to_string(), "BUY");
+        assert_eq!(Side::from_str(&Side::Sell.to_string()).unwrap(), Side::Sell);
+
+        // JSON uses the same upper-case representation
+        assert_eq!(serde_json::to_string(&Side::Buy).unwrap(), "\"BUY\"");
+        assert_eq!(
+            serde_json::from_str::<Side>("\"SELL\"").unwrap(),
+            Side::Sell
+        );
+        assert!(serde_json::from_str::<Side>("\"Buy\"").is_err());
+    }
+
+    #[test]
+    fn test_operation_parsing() {
+        assert_eq!(Operation::from_str("CREATE").unwrap(), Operation::Create);
+        assert_eq!(Operation::from_str("DELETE").unwrap(), Operation::Delete);
+        assert!(Operation::from_str("UPDATE").is_err());
+        assert_eq!(Operation::Delete.to_string(), "DELETE");
+
+        // Unknown operations are rejected when the order is deserialized
+        let json = r#"{
+            "type_op": "UPDATE",
+            "account_id": "1",
+            "amount": "1.0",
+            "order_id": "1",
+            "pair": "BTC/USDC",
+            "limit_price": "50000.0",
+            "side": "BUY"
+        }"#;
+        assert!(serde_json::from_str::<Order>(json).is_err());
+
+        let json = json.replace("UPDATE", "CREATE");
+        let order: Order = serde_json::from_str(&json).unwrap();
+        assert_eq!(order.type_op, Operation::Create);
+    }
+
+    #[test]
+    fn test_ioc_remainder_is_cancelled() {
+        let mut order_book = OrderBook::default();
+
+        // Create a resting sell order
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order).unwrap();
+
+        // IOC buy for more than is available
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "2".to_string(),
+            amount: "3.0".to_string(),
+            order_id: "2".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Ioc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
+
+        // The available amount is filled and the remainder does not rest
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].amount, "1.0");
+        assert!(order_book.generate_order_book_output().is_empty());
+    }
+
+    #[test]
+    fn test_fok_cancelled_when_not_fully_fillable() {
+        let mut order_book = OrderBook::default();
+
+        // Create two resting sell orders, one outside the buyer's limit
+        let sell_order_1 = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let sell_order_2 = Order {
+            order_id: "2".to_string(),
+            limit_price: "51000.0".to_string(),
+            ..sell_order_1.clone()
+        };
+        order_book.process_order(sell_order_1).unwrap();
+        order_book.process_order(sell_order_2).unwrap();
+
+        // FOK buy that can only be partially filled at its limit
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "2".to_string(),
+            amount: "1.5".to_string(),
+            order_id: "3".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Fok),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let (_, trades) = order_book.process_order(buy_order.clone()).unwrap();
+
+        // Nothing traded and the book is untouched
+        assert!(trades.is_empty());
+        assert_eq!(order_book.generate_order_book_output().len(), 2);
+
+        // Raising the limit makes the order fully fillable
+        let buy_order = Order {
+            order_id: "4".to_string(),
+            limit_price: "51000.0".to_string(),
+            ..buy_order
+        };
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
+
+        assert_eq!(trades.len(), 2);
+        let order_book_entries = order_book.generate_order_book_output();
+        assert_eq!(order_book_entries.len(), 1);
+        assert_eq!(order_book_entries[0].order_id, "2");
+        assert_eq!(order_book_entries[0].amount, "0.5");
+    }
+
+    #[test]
+    fn test_market_order_sweeps_book() {
+        let mut order_book = OrderBook::default();
+
+        // Create resting sell orders at two price levels
+        let sell_order_1 = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let sell_order_2 = Order {
+            order_id: "2".to_string(),
+            limit_price: "60000.0".to_string(),
+            ..sell_order_1.clone()
+        };
+        order_book.process_order(sell_order_1).unwrap();
+        order_book.process_order(sell_order_2).unwrap();
+
+        // Market buy for more than the whole ask side, with no limit price
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "2".to_string(),
+            amount: "3.0".to_string(),
+            order_id: "3".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: String::new(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Market,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
+
+        // Both levels are swept and the unfilled remainder is not rested
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, "50000.0");
+        assert_eq!(trades[1].price, "60000.0");
+        assert!(order_book.generate_order_book_output().is_empty());
+    }
+
+    #[test]
+    fn test_modify_order_queue_priority() {
+        let mut order_book = OrderBook::default();
+
+        // Create two resting buy orders at the same price
+        let buy_order_1 = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let buy_order_2 = Order {
+            order_id: "2".to_string(),
+            ..buy_order_1.clone()
+        };
+        order_book.process_order(buy_order_1.clone()).unwrap();
+        order_book.process_order(buy_order_2).unwrap();
+
+        // Reducing the amount at the same price keeps the order at the head of the queue
+        let decrease = Order {
+            type_op: Operation::Modify,
+            new_amount: Some("0.4".to_string()),
+            ..buy_order_1.clone()
+        };
+        order_book.process_order(decrease).unwrap();
+
+        let order_book_entries = order_book.generate_order_book_output();
+        assert_eq!(order_book_entries[0].order_id, "1");
+        assert_eq!(order_book_entries[0].amount, "0.4");
+        assert_eq!(order_book_entries[1].order_id, "2");
+
+        // Increasing the amount sends the order to the tail of the queue
+        let increase = Order {
+            type_op: Operation::Modify,
+            new_amount: Some("2.0".to_string()),
+            ..buy_order_1.clone()
+        };
+        order_book.process_order(increase).unwrap();
+
+        let order_book_entries = order_book.generate_order_book_output();
+        assert_eq!(order_book_entries[0].order_id, "2");
+        assert_eq!(order_book_entries[1].order_id, "1");
+        assert_eq!(order_book_entries[1].amount, "2.0");
+
+        // Changing the price moves the order to the new level
+        let reprice = Order {
+            type_op: Operation::Modify,
+            limit_price: "49000.0".to_string(),
+            ..buy_order_1
+        };
+        order_book.process_order(reprice).unwrap();
+
+        assert_eq!(order_book.bids.len(), 2);
+        let order_book_entries = order_book.generate_order_book_output();
+        assert_eq!(order_book_entries[0].order_id, "1");
+        assert_eq!(order_book_entries[0].price, "49000.0");
+        assert_eq!(order_book_entries[0].amount, "2.0");
+    }
+
+    #[test]
+    fn test_rejected_reprice_keeps_order() {
+        let mut order_book = OrderBook::default();
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(ask).unwrap();
+        let bid = Order {
+            post_only: true,
+            ..OrderBuilder::buy("BTC/USDC", "1", "99")
+                .order_id("bid")
+                .build()
+                .unwrap()
+        };
+        order_book.process_order(bid.clone()).unwrap();
+        let sequence_number = order_book.get_order_by_id("bid").unwrap().sequence_number;
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+
+        // Repricing onto the ask would take liquidity, so the amend is refused and the
+        // bid keeps its price and place without any events
+        let err = order_book
+            .process_order(Order {
+                type_op: Operation::Modify,
+                limit_price: "100".to_string(),
+                ..bid
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::PostOnlyRejected("bid".to_string()));
+        let resting = order_book.get_order_by_id("bid").unwrap();
+        assert_eq!(resting.limit_price, "99");
+        assert_eq!(resting.sequence_number, sequence_number);
+        assert_eq!(order_book.get_order_status("bid"), Some(OrderStatus::Open));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_invalid_orders_are_rejected() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+
+        // Non-numeric amount
+        let bad_amount = Order {
+            amount: "abc".to_string(),
+            ..order.clone()
+        };
+        assert_eq!(
+            order_book.process_order(bad_amount).unwrap_err(),
+            OrderBookError::InvalidAmount("abc".to_string())
+        );
+
+        // Negative price
+        let bad_price = Order {
+            limit_price: "-1".to_string(),
+            ..order.clone()
+        };
+        assert_eq!(
+            order_book.process_order(bad_price).unwrap_err(),
+            OrderBookError::InvalidPrice("-1".to_string())
+        );
+
+        // Missing order id
+        let bad_id = Order {
+            order_id: "".to_string(),
+            ..order.clone()
+        };
+        assert_eq!(
+            order_book.process_order(bad_id).unwrap_err(),
+            OrderBookError::InvalidOrderId("".to_string())
+        );
+
+        // Deleting an order that is not in the book
+        let unknown = Order {
+            type_op: Operation::Delete,
+            ..order
+        };
+        assert_eq!(
+            order_book.process_order(unknown).unwrap_err(),
+            OrderBookError::UnknownOrderId("1".to_string())
+        );
+
+        assert!(order_book.generate_order_book_output().is_empty());
+    }
+
+    #[test]
+    fn test_get_order_by_id() {
+        let mut order_book = OrderBook::default();
+
+        // Create a resting buy order and a sell order that partially fills it
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "2.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let sell_order = Order {
+            account_id: "2".to_string(),
+            amount: "0.5".to_string(),
+            order_id: "2".to_string(),
+            side: Side::Sell,
+            ..buy_order.clone()
+        };
+        order_book.process_order(buy_order).unwrap();
+        order_book.process_order(sell_order).unwrap();
+
+        // The lookup reflects the partial fill; the filled taker is not in the book
+        let resting = order_book.get_order_by_id("1").unwrap();
+        assert_eq!(resting.amount, "1.5");
+        assert!(order_book.get_order_by_id("2").is_none());
+
+        // A DELETE only needs the order id
+        let delete_order = Order {
+            type_op: Operation::Delete,
+            account_id: "1".to_string(),
+            amount: String::new(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: String::new(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(delete_order).unwrap();
+
+        assert!(order_book.get_order_by_id("1").is_none());
+        assert!(order_book.generate_order_book_output().is_empty());
+    }
+
+    #[test]
+    fn test_get_orders_by_account() {
+        let mut order_book = OrderBook::default();
+
+        // Account 1 has a bid and an ask, account 2 has a single bid
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "49000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "51000.0".to_string(),
+                side: Side::Sell,
+                ..order.clone()
+            })
+            .unwrap();
+        order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "3".to_string(),
+                ..order.clone()
+            })
+            .unwrap();
+
+        let mut order_ids: Vec<&str> = order_book
+            .get_orders_by_account("1")
+            .iter()
+            .map(|o| o.order_id.as_str())
+            .collect();
+        order_ids.sort();
+        assert_eq!(order_ids, vec!["1", "2"]);
+
+        let orders = order_book.get_orders_by_account("2");
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].order_id, "3");
+
+        assert!(order_book.get_orders_by_account("3").is_empty());
+    }
+
+    #[test]
+    fn test_cancel_all_orders_for_account() {
+        let mut order_book = OrderBook::default();
+
+        // Account 1 rests orders on both sides, account 2 shares a price level with it
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "49000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "51000.0".to_string(),
+                side: Side::Sell,
+                ..order.clone()
+            })
+            .unwrap();
+        order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "3".to_string(),
+                ..order.clone()
+            })
+            .unwrap();
+
+        assert_eq!(order_book.cancel_all_orders_for_account("1"), 2);
+
+        // Only account 2's order is left and the empty ask level is gone
+        let order_book_entries = order_book.generate_order_book_output();
+        assert_eq!(order_book_entries.len(), 1);
+        assert_eq!(order_book_entries[0].order_id, "3");
+        assert!(order_book.asks.is_empty());
+        assert!(order_book.get_orders_by_account("1").is_empty());
+
+        // Nothing left to cancel
+        assert_eq!(order_book.cancel_all_orders_for_account("1"), 0);
+    }
+
+    #[test]
+    fn test_best_bid_and_ask() {
+        let mut order_book = OrderBook::default();
+
+        // An empty book has no top of book
+        assert_eq!(order_book.get_best_bid(), None);
+        assert_eq!(order_book.get_best_ask(), None);
+        assert!(order_book.get_best_bid_orders().is_none());
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "49000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Buy, "49000.0"),
+            ("2", Side::Buy, "49500.0"),
+            ("3", Side::Buy, "49500.0"),
+            ("4", Side::Sell, "51000.0"),
+            ("5", Side::Sell, "50500.0"),
+        ];
+        for (order_id, side, price) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            order_book.get_best_bid(),
+            Some(Decimal::from_str("49500.0").unwrap())
+        );
+        assert_eq!(
+            order_book.get_best_ask(),
+            Some(Decimal::from_str("50500.0").unwrap())
+        );
+
+        let best_bids = order_book.get_best_bid_orders().unwrap();
+        assert_eq!(best_bids.len(), 2);
+        assert_eq!(best_bids[0].order_id, "2");
+        assert_eq!(best_bids[1].order_id, "3");
+
+        let best_asks = order_book.get_best_ask_orders().unwrap();
+        assert_eq!(best_asks.len(), 1);
+        assert_eq!(best_asks[0].order_id, "5");
+    }
+
+    #[test]
+    fn test_spread_and_mid_price() {
+        let mut order_book = OrderBook::default();
+
+        let bid = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "99.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(bid.clone()).unwrap();
+
+        // One-sided book
+        assert_eq!(order_book.get_spread(), None);
+        assert_eq!(order_book.get_mid_price(), None);
+        assert_eq!(order_book.get_spread_bps(), None);
+
+        let ask = Order {
+            order_id: "2".to_string(),
+            limit_price: "101.0".to_string(),
+            side: Side::Sell,
+            ..bid
+        };
+        order_book.process_order(ask).unwrap();
+
+        assert_eq!(order_book.get_spread(), Some(Decimal::from(2)));
+        assert_eq!(order_book.get_mid_price(), Some(Decimal::from(100)));
+        assert_eq!(order_book.get_spread_bps(), Some(Decimal::from(200)));
+    }
+
+    #[test]
+    fn test_get_depth() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "49000.0".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Buy, "49000", "1.0"),
+            ("2", Side::Buy, "49500", "0.5"),
+            ("3", Side::Buy, "49500", "0.25"),
+            ("4", Side::Buy, "48000", "2.0"),
+            ("5", Side::Sell, "51000", "1.5"),
+            ("6", Side::Sell, "50500", "0.75"),
+        ];
+        for (order_id, side, price, amount) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    amount: amount.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        let depth = order_book.get_depth(2);
+
+        // Bids are best (highest) first and truncated to two levels
+        assert_eq!(depth.bids.len(), 2);
+        assert_eq!(depth.bids[0].price, Decimal::from(49500));
+        assert_eq!(
+            depth.bids[0].total_amount,
+            Decimal::from_str("0.75").unwrap()
+        );
+        assert_eq!(depth.bids[0].order_count, 2);
+        assert_eq!(depth.bids[1].price, Decimal::from(49000));
+
+        // Asks are best (lowest) first
+        assert_eq!(depth.asks.len(), 2);
+        assert_eq!(depth.asks[0].price, Decimal::from(50500));
+        assert_eq!(depth.asks[1].price, Decimal::from(51000));
+        assert_eq!(depth.asks[1].order_count, 1);
+    }
+
+    #[test]
+    fn test_depth_cumulative_amount() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "50000".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", "50000", "1.0"),
+            ("2", "50000", "0.5"),
+            ("3", "50100", "2.0"),
+            ("4", "50200", "0.25"),
+        ];
+        for (order_id, price, amount) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    limit_price: price.to_string(),
+                    amount: amount.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        let depth = order_book.get_depth(10);
+        let cumulative: Vec<Decimal> = depth.asks.iter().map(|l| l.cumulative_amount).collect();
+        assert_eq!(
+            cumulative,
+            vec![
+                Decimal::from_str("1.5").unwrap(),
+                Decimal::from_str("3.5").unwrap(),
+                Decimal::from_str("3.75").unwrap(),
+            ]
+        );
+
+        // The deepest level's cumulative amount is the whole side
+        let total: Decimal = depth.asks.iter().map(|l| l.total_amount).sum();
+        assert_eq!(depth.asks.last().unwrap().cumulative_amount, total);
+        assert!(depth.bids.is_empty());
+    }
+
+    #[test]
+    fn test_simulate_order() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "110".to_string(),
+                ..sell_order
+            })
+            .unwrap();
+
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "2".to_string(),
+            amount: "3.0".to_string(),
+            order_id: "3".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "120".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let result = order_book.simulate_order(&buy_order);
+
+        assert_eq!(result.estimated_trades.len(), 2);
+        assert_eq!(result.unfilled_amount, Decimal::from(1));
+        assert_eq!(result.average_fill_price, Some(Decimal::from(105)));
+
+        // The book and trade history are untouched
+        assert_eq!(order_book.generate_order_book_output().len(), 2);
+        assert!(order_book.trades.is_empty());
+    }
+
+    #[test]
+    fn test_expected_slippage() {
+        let mut order_book = OrderBook::default();
+
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(buy_order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "90".to_string(),
+                ..buy_order
+            })
+            .unwrap();
+
+        // Selling within the best level has no slippage
+        assert_eq!(
+            order_book.expected_slippage(Side::Sell, Decimal::from_str("0.5").unwrap()),
+            Some(Decimal::ZERO)
+        );
+
+        // Selling 2.0 averages 95 against a best bid of 100
+        assert_eq!(
+            order_book.expected_slippage(Side::Sell, Decimal::from(2)),
+            Some(Decimal::from(5))
+        );
+
+        // Not enough liquidity
+        assert_eq!(
+            order_book.expected_slippage(Side::Sell, Decimal::from(3)),
+            None
+        );
+        assert_eq!(order_book.expected_slippage(Side::Buy, Decimal::ONE), None);
+    }
+
+    #[test]
+    fn test_volume_queries() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Buy, "100", "1.0"),
+            ("2", Side::Buy, "100", "0.5"),
+            ("3", Side::Buy, "99", "2.0"),
+            ("4", Side::Sell, "101", "0.25"),
+        ];
+        for (order_id, side, price, amount) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    amount: amount.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            order_book.get_total_bid_volume(),
+            Decimal::from_str("3.5").unwrap()
+        );
+        assert_eq!(
+            order_book.get_total_ask_volume(),
+            Decimal::from_str("0.25").unwrap()
+        );
+        assert_eq!(
+            order_book.get_volume_at_price(Side::Buy, Decimal::from(100)),
+            Decimal::from_str("1.5").unwrap()
+        );
+        assert_eq!(
+            order_book.get_volume_at_price(Side::Sell, Decimal::from(100)),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_order_book_imbalance() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(order_book.order_book_imbalance(5), None);
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Buy, "100", "3.0"),
+            ("2", Side::Buy, "99", "4.0"),
+            ("3", Side::Sell, "101", "1.0"),
+        ];
+        for (order_id, side, price, amount) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    amount: amount.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        // Top level only: (3 - 1) / (3 + 1)
+        assert_eq!(order_book.order_book_imbalance(1), Some(0.5));
+        // Both bid levels: (7 - 1) / (7 + 1)
+        assert_eq!(order_book.order_book_imbalance(2), Some(0.75));
+    }
+
+    #[test]
+    fn test_vwap() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(order_book.vwap(0), None);
+
+        let trade = |pair: &str, price: &str, amount: &str, timestamp_ms: u64| Trade {
+            trade_id: format!("{}-{}", pair, timestamp_ms),
+            taker_order_id: "2".to_string(),
+            maker_order_id: "1".to_string(),
+            taker_account_id: "2".to_string(),
+            maker_account_id: "1".to_string(),
+            pair: pair.parse().unwrap(),
+            price: price.to_string(),
+            amount: amount.to_string(),
+            timestamp: timestamp_ms * 1_000_000,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+            aggressor: TradeSide::Buyer,
+            tick_direction: TickDirection::default(),
+            taker_side: Some(Side::Buy),
+        };
+        order_book.trades = vec![
+            trade("BTC/USDC", "100", "1", 1_000),
+            trade("BTC/USDC", "110", "1", 2_000),
+            trade("BTC/USDC", "120", "3", 3_000),
+            trade("ETH/USDC", "10", "5", 3_000),
+        ];
+
+        // (110 * 1 + 120 * 3) / 4
+        assert_eq!(
+            order_book.vwap_for_pair("BTC/USDC", 2_000),
+            Some(Decimal::from_str("117.5").unwrap())
+        );
+        assert_eq!(
+            order_book.vwap_for_pair("ETH/USDC", 0),
+            Some(Decimal::from(10))
+        );
+        assert_eq!(order_book.vwap_for_pair("BTC/USDC", 4_000), None);
+
+        // All pairs: (120 * 3 + 10 * 5) / 8
+        assert_eq!(
+            order_book.vwap(3_000),
+            Some(Decimal::from_str("51.25").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_generate_candles() {
+        let mut order_book = OrderBook::default();
+
+        let trade = |price: &str, amount: &str, timestamp_ms: u64| Trade {
+            trade_id: timestamp_ms.to_string(),
+            taker_order_id: "2".to_string(),
+            maker_order_id: "1".to_string(),
+            taker_account_id: "2".to_string(),
+            maker_account_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            price: price.to_string(),
+            amount: amount.to_string(),
+            timestamp: timestamp_ms * 1_000_000,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+            aggressor: TradeSide::Buyer,
+            tick_direction: TickDirection::default(),
+            taker_side: Some(Side::Buy),
+        };
+        order_book.trades = vec![
+            trade("100", "1", 60_500),
+            trade("105", "2", 61_000),
+            trade("95", "1", 62_000),
+            trade("98", "0.5", 119_999),
+            trade("110", "3", 180_000),
+        ];
+
+        let candles = order_book.generate_candles(60_000);
+        assert_eq!(candles.len(), 2);
+
+        let first = &candles[0];
+        assert_eq!(first.open_time, 60_000_000_000);
+        assert_eq!(first.open, Decimal::from(100));
+        assert_eq!(first.high, Decimal::from(105));
+        assert_eq!(first.low, Decimal::from(95));
+        assert_eq!(first.close, Decimal::from(98));
+        assert_eq!(first.volume, Decimal::from_str("4.5").unwrap());
+        assert_eq!(first.trade_count, 4);
+
+        // Empty intervals produce no candle
+        assert_eq!(candles[1].open_time, 180_000_000_000);
+        assert_eq!(candles[1].trade_count, 1);
+    }
+
+    #[test]
+    fn test_session_statistics() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [("1", "105"), ("2", "95"), ("3", "100")];
+        for (order_id, price) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    limit_price: price.to_string(),
+                    ..sell_order.clone()
+                })
+                .unwrap();
+        }
+
+        // Sweep the asks in price order: 95, 100, then half of 105
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            amount: "2.5".to_string(),
+            order_id: "4".to_string(),
+            limit_price: "105".to_string(),
+            side: Side::Buy,
+            ..sell_order
+        };
+        order_book.process_order(buy_order).unwrap();
+
+        assert_eq!(order_book.last_trade_price(), Some(Decimal::from(105)));
+        assert_eq!(order_book.session_high(), Some(Decimal::from(105)));
+        assert_eq!(order_book.session_low(), Some(Decimal::from(95)));
+        assert_eq!(
+            order_book.session_volume(),
+            Decimal::from_str("2.5").unwrap()
+        );
+
+        order_book.reset_session();
+        assert_eq!(order_book.session_high(), None);
+        assert_eq!(order_book.session_low(), None);
+        assert_eq!(order_book.session_volume(), Decimal::ZERO);
+        assert_eq!(order_book.last_trade_price(), Some(Decimal::from(105)));
+    }
+
+    #[test]
+    fn test_post_only_rejected_when_crossing() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+
+        // A post-only bid at the ask would take liquidity
+        let crossing = Order {
+            account_id: "2".to_string(),
+            order_id: "2".to_string(),
+            side: Side::Buy,
+            post_only: true,
+            ..sell_order.clone()
+        };
+        assert_eq!(
+            order_book.process_order(crossing).unwrap_err(),
+            OrderBookError::PostOnlyRejected("2".to_string())
+        );
+        assert!(order_book.trades.is_empty());
+        assert!(order_book.get_order_by_id("2").is_none());
+
+        // Below the ask it rests as a maker
+        let resting = Order {
+            account_id: "2".to_string(),
+            order_id: "3".to_string(),
+            limit_price: "99".to_string(),
+            side: Side::Buy,
+            post_only: true,
+            ..sell_order
+        };
+        assert!(order_book.process_order(resting).unwrap().1.is_empty());
+        assert!(order_book.get_order_by_id("3").is_some());
+    }
+
+    #[test]
+    fn test_self_trade_prevention() {
+        // Account 1 rests an ask at 100 ahead of account 2's ask at the same price,
+        // then account 1 sends a bid for 2.0 at 100
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let other_sell_order = Order {
+            account_id: "2".to_string(),
+            order_id: "2".to_string(),
+            ..sell_order.clone()
+        };
+        let buy_order = Order {
+            amount: "2.0".to_string(),
+            order_id: "3".to_string(),
+            side: Side::Buy,
+            ..sell_order.clone()
+        };
+
+        let run = |mode: SelfTradePrevention| {
+            let mut order_book = OrderBook::new(OrderBookConfig {
+                self_trade_prevention: mode,
+                ..OrderBookConfig::default()
+            })
+            .unwrap();
+            order_book.process_order(sell_order.clone()).unwrap();
+            order_book.process_order(other_sell_order.clone()).unwrap();
+            let (_, trades) = order_book.process_order(buy_order.clone()).unwrap();
+            let mut resting: Vec<(String, String)> = order_book
+                .generate_order_book_output()
+                .into_iter()
+                .map(|e| (e.order_id, e.amount))
+                .collect();
+            resting.sort();
+            (trades, resting)
+        };
+
+        // Allow: the account trades with itself and the other account
+        let (trades, resting) = run(SelfTradePrevention::Allow);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, "1");
+        assert!(resting.is_empty());
+
+        // CancelTaker: the bid is cancelled before trading, both asks remain
+        let (trades, resting) = run(SelfTradePrevention::CancelTaker);
+        assert!(trades.is_empty());
+        assert_eq!(
+            resting,
+            vec![
+                ("1".to_string(), "1.0".to_string()),
+                ("2".to_string(), "1.0".to_string())
+            ]
+        );
+
+        // CancelMaker: the own ask is cancelled, the bid fills against account 2
+        // and its remainder rests
+        let (trades, resting) = run(SelfTradePrevention::CancelMaker);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, "2");
+        assert_eq!(resting, vec![("3".to_string(), "1.0".to_string())]);
+
+        // CancelBoth: the own ask and the bid are cancelled
+        let (trades, resting) = run(SelfTradePrevention::CancelBoth);
+        assert!(trades.is_empty());
+        assert_eq!(resting, vec![("2".to_string(), "1.0".to_string())]);
+    }
+
+    #[test]
+    fn test_expired_orders() {
+        let mut order_book = OrderBook::default();
+        let now = get_current_timestamp() / 1_000_000;
+
+        // An expired ask at the best price and a live ask behind it
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: Some(now - 1_000),
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "101".to_string(),
+                expires_at_ms: Some(now + 60_000),
+                ..sell_order.clone()
+            })
+            .unwrap();
+
+        // The buy skips the expired ask, which is removed from the book
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            amount: "0.5".to_string(),
+            order_id: "3".to_string(),
+            limit_price: "101".to_string(),
+            side: Side::Buy,
+            expires_at_ms: None,
+            ..sell_order.clone()
+        };
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, "2");
+        assert!(order_book.get_order_by_id("1").is_none());
+
+        // Eager cleanup
+        order_book
+            .process_order(Order {
+                order_id: "4".to_string(),
+                limit_price: "105".to_string(),
+                ..sell_order
+            })
+            .unwrap();
+        let expired = order_book.remove_expired_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order_id, "4");
+        assert_eq!(order_book.generate_order_book_output().len(), 1);
+    }
+
+    #[test]
+    fn test_trading_engine_routes_by_pair() {
+        let mut engine = TradingEngine::new();
+
+        let btc_sell = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        engine.process_order(btc_sell.clone()).unwrap();
+
+        // A bid on another pair at a crossing price does not match
+        let eth_buy = Order {
+            account_id: "2".to_string(),
+            order_id: "2".to_string(),
+            pair: "ETH/USDC".parse().unwrap(),
+            side: Side::Buy,
+            ..btc_sell.clone()
+        };
+        assert!(engine.process_order(eth_buy.clone()).unwrap().1.is_empty());
+
+        let mut pairs: Vec<&str> = engine.all_books().map(|(pair, _)| pair).collect();
+        pairs.sort();
+        assert_eq!(pairs, vec!["BTC/USDC", "ETH/USDC"]);
+
+        // Order ids are unique across pairs
+        let duplicate = Order {
+            order_id: "1".to_string(),
+            ..eth_buy.clone()
+        };
+        assert_eq!(
+            engine.process_order(duplicate).unwrap_err(),
+            OrderBookError::DuplicateOrderId("1".to_string())
+        );
+
+        // Cancelling through the wrong pair is rejected
+        let wrong_pair_delete = Order {
+            type_op: Operation::Delete,
+            pair: "ETH/USDC".parse().unwrap(),
+            ..btc_sell.clone()
+        };
+        assert_eq!(
+            engine.process_order(wrong_pair_delete).unwrap_err(),
+            OrderBookError::UnknownOrderId("1".to_string())
+        );
+
+        // A BTC bid matches the BTC ask
+        let btc_buy = Order {
+            order_id: "3".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            ..eth_buy
+        };
+        let (_, trades) = engine.process_order(btc_buy).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(engine.get_book("BTC/USDC").unwrap().trades.len(), 1);
+        assert!(engine.get_book("ETH/USDC").unwrap().trades.is_empty());
+    }
+
+    #[test]
+    fn test_subscribe_to_events() {
+        let mut order_book = OrderBook::default();
+        // Broadcast receivers need `&mut self` to receive
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            amount: "0.4".to_string(),
+            order_id: "2".to_string(),
+            side: Side::Buy,
+            ..sell_order.clone()
+        };
+        order_book.process_order(buy_order).unwrap();
+
+        let delete_order = Order {
+            type_op: Operation::Delete,
+            ..sell_order
+        };
+        order_book.process_order(delete_order).unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+
+        assert_eq!(events.len(), 8);
+        assert!(matches!(&events[0], OrderBookEvent::OrderAdded(entry) if entry.order_id == "1"));
+        assert!(matches!(
+            &events[1],
+            OrderBookEvent::DepthChanged(Side::Sell, price, amount) if *price == Decimal::from(100) && *amount == Decimal::ONE
+        ));
+        assert!(matches!(
+            &events[2],
+            OrderBookEvent::OrderModified(id, amount) if id == "1" && *amount == Decimal::from_str("0.6").unwrap()
+        ));
+        assert!(
+            matches!(&events[3], OrderBookEvent::TradeExecuted(trade) if trade.taker_order_id == "2")
+        );
+        assert!(matches!(
+            &events[4],
+            OrderBookEvent::DepthChanged(Side::Sell, _, amount) if *amount == Decimal::from_str("0.6").unwrap()
+        ));
+        assert!(matches!(&events[5], OrderBookEvent::OrderRemoved(id) if id == "1"));
+        assert!(matches!(&events[6], OrderBookEvent::OrderCancelled(id) if id == "1"));
+        assert!(matches!(
+            &events[7],
+            OrderBookEvent::DepthChanged(Side::Sell, _, amount) if amount.is_zero()
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Sell, "100", "1.0"),
+            ("2", Side::Sell, "100", "2.0"),
+            ("3", Side::Sell, "101", "1.0"),
+            ("4", Side::Buy, "99", "1.5"),
+            ("5", Side::Buy, "100", "0.5"),
+        ];
+        for (order_id, side, price, amount) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    amount: amount.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+
+        // Round-trip through JSON
+        let json = serde_json::to_string(&order_book.snapshot()).unwrap();
+        let snapshot: OrderBookSnapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = OrderBook::restore(snapshot).unwrap();
+
+        assert_eq!(restored.trades.len(), order_book.trades.len());
+        let entries = |book: &OrderBook| -> Vec<(String, String, String, u64)> {
+            book.generate_order_book_output()
+                .into_iter()
+                .map(|e| (e.order_id, e.price, e.amount, e.timestamp))
+                .collect()
+        };
+        assert_eq!(entries(&restored), entries(&order_book));
+
+        // Matching after the restore behaves exactly like the original book
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            amount: "2.0".to_string(),
+            order_id: "6".to_string(),
+            limit_price: "101".to_string(),
+            side: Side::Buy,
+            ..order
+        };
+        let fills = |trades: Vec<Trade>| -> Vec<(String, String, String)> {
+            trades
+                .into_iter()
+                .map(|t| (t.maker_order_id, t.price, t.amount))
+                .collect()
+        };
+        assert_eq!(
+            fills(restored.process_order(buy_order.clone()).unwrap().1),
+            fills(order_book.process_order(buy_order).unwrap().1)
+        );
+        assert_eq!(entries(&restored), entries(&order_book));
+    }
+
+    #[test]
+    fn test_replay_from_log() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            tick_size: Some(Decimal::ONE),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = vec![
+            order.clone(),
+            Order {
+                order_id: "2".to_string(),
+                limit_price: "101".to_string(),
+                ..order.clone()
+            },
+            Order {
+                account_id: "2".to_string(),
+                amount: "1.5".to_string(),
+                order_id: "3".to_string(),
+                limit_price: "101".to_string(),
+                side: Side::Buy,
+                ..order.clone()
+            },
+            // Rejected: the order is already filled
+            Order {
+                type_op: Operation::Delete,
+                ..order.clone()
+            },
+        ];
+        for order in orders {
+            let _ = order_book.process_order(order);
+        }
+        // Rejected during a halt, so it isn't logged and doesn't rest after replay
+        order_book.halt_trading(HaltReason::Regulatory);
+        let halted = Order {
+            order_id: "4".to_string(),
+            side: Side::Buy,
+            limit_price: "99".to_string(),
+            ..order
+        };
+        order_book.process_order(halted).unwrap_err();
+        order_book.resume_trading();
+        assert_eq!(order_book.order_log().len(), 3);
+
+        let (replayed, trades) = OrderBook::replay_from_log(
+            order_book.config().clone(),
+            order_book.order_log().to_vec(),
+        )
+        .unwrap();
+
+        assert_eq!(trades.len(), order_book.trades.len());
+        assert_eq!(replayed.trades.len(), order_book.trades.len());
+        let entries = |book: &OrderBook| -> Vec<(String, String)> {
+            book.generate_order_book_output()
+                .into_iter()
+                .map(|e| (e.order_id, e.amount))
+                .collect()
+        };
+        assert_eq!(entries(&replayed), entries(&order_book));
+        assert_eq!(
+            entries(&replayed),
+            vec![("2".to_string(), "0.5".to_string())]
+        );
+        assert_eq!(replayed.config(), order_book.config());
+
+        order_book.set_max_order_log(Some(2));
+        let ids: Vec<&str> = order_book
+            .order_log()
+            .iter()
+            .map(|o| o.order_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_diff_and_apply_deltas() {
+        let mut order_book = OrderBook::default();
+
+        let order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        let orders = [
+            ("1", Side::Sell, "100"),
+            ("2", Side::Sell, "100"),
+            ("3", Side::Sell, "101"),
+            ("4", Side::Buy, "99"),
+            ("5", Side::Buy, "98"),
+        ];
+        for (order_id, side, price) in orders {
+            order_book
+                .process_order(Order {
+                    order_id: order_id.to_string(),
+                    side,
+                    limit_price: price.to_string(),
+                    ..order.clone()
+                })
+                .unwrap();
+        }
+        let before = order_book.snapshot();
+
+        // Fill order 1 and half of order 2, cancel 5, grow 4, add a new ask
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            amount: "1.5".to_string(),
+            order_id: "6".to_string(),
+            side: Side::Buy,
+            ..order.clone()
+        };
+        order_book.process_order(buy_order).unwrap();
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                order_id: "5".to_string(),
+                ..order.clone()
+            })
+            .unwrap();
+        order_book
+            .process_order(Order {
+                type_op: Operation::Modify,
+                order_id: "4".to_string(),
+                side: Side::Buy,
+                limit_price: "99".to_string(),
+                new_amount: Some("3.0".to_string()),
+                ..order.clone()
+            })
+            .unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "7".to_string(),
+                limit_price: "101".to_string(),
+                ..order
+            })
+            .unwrap();
+        let after = order_book.snapshot();
+
+        let deltas = OrderBook::diff(&before, &after);
+        assert!(
+            deltas
+                .iter()
+                .any(|d| matches!(d, OrderBookDelta::Remove(id) if id == "1"))
+        );
+        assert!(deltas.iter().any(|d| matches!(
+            d,
+            OrderBookDelta::Modify(id, amount) if id == "2" && *amount == Decimal::from_str("0.5").unwrap()
+        )));
+
+        let mut replica = before.clone();
+        replica.apply(&deltas);
+
+        let entries = |snapshot: OrderBookSnapshot| -> Vec<(String, String, String)> {
+            OrderBook::restore(snapshot)
+                .unwrap()
+                .generate_order_book_output()
+                .into_iter()
+                .map(|e| (e.order_id, e.price, e.amount))
+                .collect()
+        };
+        assert_eq!(entries(replica), entries(after.clone()));
+
+        // No changes, no deltas
+        assert!(OrderBook::diff(&after, &after).is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_registry() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "2".to_string(),
+                side: Side::Buy,
+                ..sell_order
+            })
+            .unwrap();
+
+        let families = order_book.registry().gather();
+        let value = |name: &str| -> f64 {
+            families
+                .iter()
+                .find(|f| f.name() == name)
+                .map(|f| {
+                    f.get_metric()
+                        .iter()
+                        .map(|m| m.get_counter().get_value())
+                        .sum()
+                })
+                .unwrap_or(0.0)
+        };
+        assert_eq!(value("orders_processed_total"), 2.0);
+        assert_eq!(value("trades_executed_total"), 1.0);
+        assert!(
+            families
+                .iter()
+                .any(|f| f.name() == "order_matching_duration_seconds")
+        );
+    }
+
+    #[test]
+    fn test_trade_fees() {
+        let mut order_book = OrderBook::default();
+        order_book.set_fee_schedule(FeeSchedule {
+            fee_rate_maker: Some(Decimal::from_str("0.001").unwrap()),
+            fee_rate_taker: Some(Decimal::from_str("0.002").unwrap()),
+        });
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "2.0".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(sell_order.clone()).unwrap();
+
+        let (_, trades) = order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "2".to_string(),
+                amount: "1.5".to_string(),
+                side: Side::Buy,
+                ..sell_order.clone()
+            })
+            .unwrap();
+
+        // Notional is 1.5 * 100 = 150
+        assert_eq!(trades[0].maker_fee, Decimal::from_str("0.15").unwrap());
+        assert_eq!(trades[0].taker_fee, Decimal::from_str("0.3").unwrap());
+
+        // Without a taker rate only the maker pays
+        order_book.set_fee_schedule(FeeSchedule {
+            fee_rate_maker: Some(Decimal::from_str("0.001").unwrap()),
+            fee_rate_taker: None,
+        });
+        let (_, trades) = order_book
+            .process_order(Order {
+                account_id: "3".to_string(),
+                order_id: "3".to_string(),
+                amount: "0.5".to_string(),
+                side: Side::Buy,
+                ..sell_order
+            })
+            .unwrap();
+        assert_eq!(trades[0].taker_fee, Decimal::ZERO);
+
+        // 0.15 + 0.3 + 0.05
+        assert_eq!(
+            order_book.total_fees_collected(),
+            Decimal::from_str("0.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_realized_pnl_fifo() {
+        let mut order_book = OrderBook::default();
+
+        let order =
+            |account_id: &str, order_id: &str, side: Side, amount: &str, price: &str| Order {
+                type_op: Operation::Create,
+                account_id: account_id.to_string(),
+                amount: amount.to_string(),
+                order_id: order_id.to_string(),
+                pair: "BTC/USDC".parse().unwrap(),
+                limit_price: price.to_string(),
+                side,
+                time_in_force: Some(TimeInForce::Gtc),
+                order_type: OrderType::Limit,
+                new_amount: None,
+                post_only: false,
+                expires_at_ms: None,
+                visible_amount: None,
+                peg: None,
+                sequence_number: 0,
+                status: OrderStatus::Open,
+                trail_amount: None,
+                trail_pct: None,
+                timestamp: get_current_timestamp(),
+            };
+
+        // Account 1 buys 1 @ 100 then 1 @ 110
+        for (id, price) in [("1", "100"), ("3", "110")] {
+            order_book
+                .process_order(order("2", id, Side::Sell, "1", price))
+                .unwrap();
+            order_book
+                .process_order(order("1", &format!("{}b", id), Side::Buy, "1", price))
+                .unwrap();
+        }
+
+        // Sells 1.5 @ 120: FIFO closes the lot at 100 then half the lot at 110
+        order_book
+            .process_order(order("3", "5", Side::Buy, "1.5", "120"))
+            .unwrap();
+        order_book
+            .process_order(order("1", "6", Side::Sell, "1.5", "120"))
+            .unwrap();
+
+        // (120 - 100) * 1 + (120 - 110) * 0.5
+        let pnl = order_book.realized_pnl_for_account("1");
+        assert_eq!(pnl["BTC/USDC"], Decimal::from(25));
+
+        // Account 2 sold short at 100 and 110, still open
+        assert_eq!(
+            order_book.realized_pnl_for_account("2")["BTC/USDC"],
+            Decimal::ZERO
+        );
+        assert!(order_book.realized_pnl_for_account("4").is_empty());
+    }
+
+    #[test]
+    fn test_iceberg_order() {
+        let mut order_book = OrderBook::default();
+
+        // Iceberg selling 10 with 3 displayed, then a regular ask at the same price
+        let iceberg = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "10".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: Some(Decimal::from(3)),
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(iceberg.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "2".to_string(),
+                amount: "2".to_string(),
+                visible_amount: None,
+                ..iceberg.clone()
+            })
+            .unwrap();
+
+        let output = order_book.generate_order_book_output();
+        assert_eq!(output[0].order_id, "1");
+        assert_eq!(output[0].amount, "3");
+
+        // Filling the displayed slice replenishes it behind the regular ask
+        let buy = |order_id: &str, amount: &str| Order {
+            account_id: "3".to_string(),
+            order_id: order_id.to_string(),
+            amount: amount.to_string(),
+            side: Side::Buy,
+            visible_amount: None,
+            ..iceberg.clone()
+        };
+        order_book.process_order(buy("3", "3")).unwrap();
+        let output = order_book.generate_order_book_output();
+        assert_eq!(output[0].order_id, "2");
+        assert_eq!(
+            (output[1].order_id.as_str(), output[1].amount.as_str()),
+            ("1", "3")
+        );
+        assert_eq!(order_book.get_order_by_id("1").unwrap().amount, "7");
+
+        // A larger buy takes the regular ask, then keeps hitting the replenished slices
+        let (_, trades) = order_book.process_order(buy("4", "8")).unwrap();
+        let fills: Vec<(&str, &str)> = trades
+            .iter()
+            .map(|t| (t.maker_order_id.as_str(), t.amount.as_str()))
+            .collect();
+        assert_eq!(fills, vec![("2", "2"), ("1", "3"), ("1", "3")]);
+
+        let output = order_book.generate_order_book_output();
+        assert_eq!(
+            (output[0].order_id.as_str(), output[0].amount.as_str()),
+            ("1", "1")
+        );
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut order_book = OrderBook::default();
+        order_book.set_circuit_breaker(Some(CircuitBreaker {
+            reference_price: Decimal::from(100),
+            max_move_pct: Decimal::from(10),
+            cooldown_ms: 60_000,
+        }));
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        for (id, price) in [("1", "100"), ("2", "105"), ("3", "120")] {
+            order_book
+                .process_order(Order {
+                    order_id: id.to_string(),
+                    limit_price: price.to_string(),
+                    ..sell_order.clone()
+                })
+                .unwrap();
+        }
+
+        // The sweep stops before trading 20% away from the reference price
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            order_id: "4".to_string(),
+            amount: "3".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            ..sell_order.clone()
+        };
+        let (_, trades) = order_book.process_order(buy_order.clone()).unwrap();
+        assert_eq!(trades.len(), 2);
+        assert!(order_book.is_halted());
+        let mut tripped = false;
+        while let Ok(event) = receiver.try_recv() {
+            tripped |= matches!(
+                event,
+                OrderBookEvent::CircuitBreakerTripped(price, _) if price == Decimal::from(120)
+            );
+        }
+        assert!(tripped);
+
+        // Nothing trades during the cooldown, but passive orders still rest
+        let (_, trades) = order_book
+            .process_order(Order {
+                order_id: "5".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap();
+        assert!(trades.is_empty());
+        order_book
+            .process_order(Order {
+                order_id: "6".to_string(),
+                order_type: OrderType::Limit,
+                limit_price: "90".to_string(),
+                amount: "1".to_string(),
+                ..buy_order
+            })
+            .unwrap();
+        assert!(order_book.get_order_by_id("6").is_some());
+
+        order_book.set_circuit_breaker(None);
+        assert!(!order_book.is_halted());
+    }
+
+    #[test]
+    fn test_price_band() {
+        let mut order_book = OrderBook::default();
+        order_book.set_price_band(PriceBand {
+            min_price: Decimal::from(90),
+            max_price: Decimal::from(110),
+        });
+
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "89".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        assert_eq!(
+            order_book.process_order(buy_order.clone()).unwrap_err(),
+            OrderBookError::PriceOutOfBand("89".to_string())
+        );
+
+        // Band edges are inclusive
+        order_book
+            .process_order(Order {
+                limit_price: "90".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap();
+
+        // Amending outside the band is rejected and the order keeps resting
+        let err = order_book
+            .process_order(Order {
+                type_op: Operation::Modify,
+                limit_price: "111".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::PriceOutOfBand("111".to_string()));
+        assert!(order_book.get_order_by_id("1").is_some());
+
+        order_book.clear_price_band();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                ..buy_order
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tick_and_lot_size() {
+        let mut order_book = OrderBook::default();
+        order_book
+            .set_tick_size(Some(Decimal::from_str("0.5").unwrap()))
+            .unwrap();
+        order_book
+            .set_lot_size(Some(Decimal::from_str("0.1").unwrap()))
+            .unwrap();
+
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1.2".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100.5".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(buy_order.clone()).unwrap();
+
+        let err = order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                limit_price: "100.2".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::InvalidTickSize("100.2".to_string()));
+
+        let err = order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                amount: "1.25".to_string(),
+                ..buy_order
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::InvalidLotSize("1.25".to_string()));
+
+        assert_eq!(
+            order_book.round_to_tick(Decimal::from_str("100.2").unwrap()),
+            Decimal::from(100)
+        );
+        assert_eq!(
+            order_book.round_to_tick(Decimal::from_str("100.3").unwrap()),
+            Decimal::from_str("100.5").unwrap()
+        );
+
+        // Sizes that aren't positive are refused and leave the book's setting alone
+        assert!(matches!(
+            order_book.set_tick_size(Some(Decimal::ZERO)),
+            Err(OrderBookError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            order_book.set_lot_size(Some(Decimal::from(-1))),
+            Err(OrderBookError::InvalidConfig(_))
+        ));
+        assert_eq!(
+            order_book.config().tick_size,
+            Some(Decimal::from_str("0.5").unwrap())
+        );
+        let err = OrderBook::new(OrderBookConfig {
+            lot_size: Some(Decimal::ZERO),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config: lot size must be positive, got 0"
+        );
+    }
+
+    #[test]
+    fn test_cancel_replace() {
+        let mut order_book = OrderBook::default();
+
+        let buy_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "99".to_string(),
+            side: Side::Buy,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        order_book.process_order(buy_order.clone()).unwrap();
+        order_book
+            .process_order(Order {
+                order_id: "2".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap();
+        order_book
+            .process_order(Order {
+                account_id: "2".to_string(),
+                order_id: "3".to_string(),
+                limit_price: "100".to_string(),
+                side: Side::Sell,
+                ..buy_order.clone()
+            })
+            .unwrap();
+
+        // Unknown ids fail without entering the new order
+        let err = order_book
+            .process_order(Order {
+                type_op: Operation::CancelReplace,
+                order_id: "4".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::UnknownOrderId("4".to_string()));
+        assert!(order_book.get_order_by_id("4").is_none());
+
+        // A rejected replacement leaves the original at the front of its level, and
+        // publishes nothing
+        let sequence_number = order_book.get_order_by_id("1").unwrap().sequence_number;
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+        let err = order_book
+            .process_order(Order {
+                type_op: Operation::CancelReplace,
+                limit_price: "100".to_string(),
+                post_only: true,
+                ..buy_order.clone()
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::PostOnlyRejected("1".to_string()));
+        let best_bids = order_book.get_best_bid_orders().unwrap();
+        assert_eq!(best_bids[0].order_id, "1");
+        assert_eq!(best_bids[0].limit_price, "99");
+        assert_eq!(best_bids[0].sequence_number, sequence_number);
+        assert_eq!(order_book.get_order_status("1"), Some(OrderStatus::Open));
+        assert!(receiver.try_recv().is_err());
+
+        // The replacement can change price and amount, and trades if it crosses
+        let (_, trades) = order_book
+            .process_order(Order {
+                type_op: Operation::CancelReplace,
+                amount: "1.5".to_string(),
+                limit_price: "100".to_string(),
+                ..buy_order
+            })
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        let replaced = order_book.get_order_by_id("1").unwrap();
+        assert_eq!(
+            (replaced.amount.as_str(), replaced.limit_price.as_str()),
+            ("0.5", "100")
+        );
+        assert_eq!(
+            "CANCEL_REPLACE".parse::<Operation>().unwrap(),
+            Operation::CancelReplace
+        );
+    }
+
+    #[test]
+    fn test_pro_rata_matching() {
+        let mut order_book = OrderBook::default();
+        order_book.set_matching_algorithm(MatchingAlgorithm::ProRata);
+
+        // 1 + 2 + 3 resting at the same price
+        let sell_order = Order {
+            type_op: Operation::Create,
+            account_id: "1".to_string(),
+            amount: "1".to_string(),
+            order_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            limit_price: "100".to_string(),
+            side: Side::Sell,
+            time_in_force: Some(TimeInForce::Gtc),
+            order_type: OrderType::Limit,
+            new_amount: None,
+            post_only: false,
+            expires_at_ms: None,
+            visible_amount: None,
+            peg: None,
+            sequence_number: 0,
+            status: OrderStatus::Open,
+            trail_amount: None,
+            trail_pct: None,
+            timestamp: get_current_timestamp(),
+        };
+        for (id, amount) in [("1", "1"), ("2", "2"), ("3", "3")] {
+            order_book
+                .process_order(Order {
+                    order_id: id.to_string(),
+                    amount: amount.to_string(),
+                    ..sell_order.clone()
+                })
+                .unwrap();
+        }
+
+        let buy_order = Order {
+            account_id: "2".to_string(),
+            order_id: "4".to_string(),
+            amount: "3".to_string(),
+            side: Side::Buy,
+            ..sell_order.clone()
+        };
+        let (_, trades) = order_book.process_order(buy_order.clone()).unwrap();
+        let fills: Vec<(&str, Decimal)> = trades
+            .iter()
+            .map(|t| {
+                (
+                    t.maker_order_id.as_str(),
+                    Decimal::from_str(&t.amount).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                ("1", Decimal::from_str("0.5").unwrap()),
+                ("2", Decimal::from(1)),
+                ("3", Decimal::from_str("1.5").unwrap()),
+            ]
+        );
+
+        let resting: Vec<String> = order_book
+            .generate_order_book_output()
+            .into_iter()
+            .map(|e| e.amount)
+            .collect();
+        assert_eq!(resting, vec!["0.5", "1", "1.5"]);
+
+        // With a lot size of 1, splitting 1 over 1 + 1 + 2 rounds every share down
+        // to 0 and the leftover goes to the largest order
+        let mut order_book = OrderBook::default();
+        order_book.set_matching_algorithm(MatchingAlgorithm::ProRata);
+        order_book.set_lot_size(Some(Decimal::ONE)).unwrap();
+        for (id, amount) in [("5", "1"), ("6", "1"), ("7", "2")] {
+            order_book
+                .process_order(Order {
+                    order_id: id.to_string(),
+                    amount: amount.to_string(),
+                    ..sell_order.clone()
+                })
+                .unwrap();
+        }
+        let (_, trades) = order_book
+            .process_order(Order {
+                order_id: "8".to_string(),
+                amount: "1".to_string(),
+                ..buy_order.clone()
+            })
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, "7");
+
+        // A leftover bigger than one order is handed round the level rather than
+        // spilling to a worse price while the better one still has liquidity
+        let mut order_book = OrderBook::default();
+        order_book.set_matching_algorithm(MatchingAlgorithm::ProRata);
+        order_book.set_lot_size(Some(Decimal::ONE)).unwrap();
+        for (id, price) in [("9", "100"), ("10", "100"), ("11", "100"), ("12", "101")] {
+            order_book
+                .process_order(Order {
+                    order_id: id.to_string(),
+                    limit_price: price.to_string(),
+                    ..sell_order.clone()
+                })
+                .unwrap();
+        }
+        let (_, trades) = order_book
+            .process_order(Order {
+                order_id: "13".to_string(),
+                amount: "2".to_string(),
+                limit_price: "101".to_string(),
+                ..buy_order
+            })
+            .unwrap();
+        let fills: Vec<(&str, &str, &str)> = trades
+            .iter()
+            .map(|t| {
+                (
+                    t.maker_order_id.as_str(),
+                    t.price.as_str(),
+                    t.amount.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(fills, [("9", "100", "1"), ("10", "100", "1")]);
+        assert_eq!(
+            order_book.get_volume_at_price(Side::Sell, Decimal::from(101)),
+            Decimal::ONE
+        );
+    }
+
+    #[test]
+    fn test_trading_pair_parsing() {
+        let pair: TradingPair = "BTC/USDC".parse().unwrap();
+        assert_eq!((pair.base(), pair.quote()), ("BTC", "USDC"));
+        assert_eq!(pair.to_string(), "BTC/USDC");
+
+        for invalid in [
+            "BTCUSDC",
+            "BTC/",
+            "B/USDC",
+            "BTC/BTC",
+            "BTC/US1C",
+            "BTC/ABCDEFGHIJK",
+        ] {
+            assert!(invalid.parse::<TradingPair>().is_err(), "{}", invalid);
+        }
+
+        // Misformatted pairs are rejected when the order is deserialized
+        let json = r#"{
+            "type_op": "CREATE",
+            "account_id": "1",
+            "amount": "1.0",
+            "order_id": "1",
+            "pair": "BTC-USDC",
+            "limit_price": "50000.0",
+            "side": "BUY"
+        }"#;
+        assert!(serde_json::from_str::<Order>(json).is_err());
+
+        let order: Order = serde_json::from_str(&json.replace("BTC-USDC", "BTC/USDC")).unwrap();
+        assert_eq!(order.pair, pair);
+        assert!(
+            serde_json::to_string(&order)
+                .unwrap()
+                .contains(r#""pair":"BTC/USDC""#)
+        );
+    }
+
+    #[test]
+    fn test_order_builder() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = OrderBuilder::sell("BTC/USDC", "1.0", "100")
+            .account("1")
+            .order_id("1")
+            .build()
+            .unwrap();
+        assert_eq!(sell_order.type_op, Operation::Create);
+        assert_eq!(sell_order.side, Side::Sell);
+        assert_eq!(sell_order.order_id, "1");
+        order_book.process_order(sell_order).unwrap();
+
+        // Ids are generated when not given
+        let buy_order = OrderBuilder::buy("BTC/USDC", "1.0", "100")
+            .account("2")
+            .time_in_force(TimeInForce::Ioc)
+            .build()
+            .unwrap();
+        assert!(!buy_order.order_id.is_empty());
+        let (_, trades) = order_book.process_order(buy_order.clone()).unwrap();
+        assert_eq!(trades[0].taker_order_id, buy_order.order_id);
+
+        assert_eq!(
+            OrderBuilder::buy("BTCUSDC", "1", "100")
+                .build()
+                .unwrap_err(),
+            OrderBuilderError::InvalidPair("BTCUSDC".to_string())
+        );
+        assert_eq!(
+            OrderBuilder::buy("BTC/USDC", "0", "100")
+                .build()
+                .unwrap_err(),
+            OrderBuilderError::InvalidAmount("0".to_string())
+        );
+        assert_eq!(
+            OrderBuilder::buy("BTC/USDC", "1", "abc")
+                .build()
+                .unwrap_err(),
+            OrderBuilderError::InvalidPrice("abc".to_string())
+        );
+    }
+
+    #[test]
+    fn test_time_ordered_ids() {
+        let before = get_current_timestamp();
+        let first = generate_trade_id();
+        let second = generate_trade_id();
+        let after = get_current_timestamp();
+
+        // v7 ids sort in creation order and carry their creation time
+        assert!(first < second);
+        let timestamp = timestamp_from_trade_id(&first).unwrap();
+        assert!(before / 1_000_000 <= timestamp && timestamp <= after / 1_000_000);
+
+        assert!(generate_order_id() > first);
+        assert_eq!(timestamp_from_trade_id("not-a-uuid"), None);
+        assert_eq!(
+            timestamp_from_trade_id("67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sequence_numbers_break_ties() {
+        let mut order_book = OrderBook::default();
+
+        // Clock skew: later orders carry earlier timestamps
+        for (id, timestamp) in [("1", 300), ("2", 100), ("3", 100)] {
+            let mut order = OrderBuilder::sell("BTC/USDC", "1", "100")
+                .account("1")
+                .order_id(id)
+                .build()
+                .unwrap();
+            order.timestamp = timestamp;
+            order_book.process_order(order).unwrap();
+        }
+
+        let entries = order_book.generate_order_book_output();
+        let queue: Vec<(&str, u64)> = entries
+            .iter()
+            .map(|e| (e.order_id.as_str(), e.sequence_number))
+            .collect();
+        assert_eq!(queue, vec![("1", 1), ("2", 2), ("3", 3)]);
+
+        let buy_order = OrderBuilder::buy("BTC/USDC", "2", "100")
+            .account("2")
+            .build()
+            .unwrap();
+        let (_, trades) = order_book.process_order(buy_order).unwrap();
+        let makers: Vec<&str> = trades.iter().map(|t| t.maker_order_id.as_str()).collect();
+        assert_eq!(makers, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_process_orders_batch() {
+        let mut order_book = OrderBook::default();
+
+        let sell_order = OrderBuilder::sell("BTC/USDC", "1", "100")
+            .account("1")
+            .order_id("1")
+            .build()
+            .unwrap();
+        let buy_order = OrderBuilder::buy("BTC/USDC", "0.5", "100")
+            .account("2")
+            .order_id("2")
+            .build()
+            .unwrap();
+
+        // A malformed order rejects the whole batch before anything is processed
+        let malformed = Order {
+            order_id: "3".to_string(),
+            amount: "abc".to_string(),
+            ..buy_order.clone()
+        };
+        let err: BatchError = order_book
+            .process_orders_batch(vec![sell_order.clone(), malformed])
+            .unwrap_err();
+        assert_eq!(err.index, 1);
+        assert_eq!(err.order_id, "3");
+        assert_eq!(err.error, OrderBookError::InvalidAmount("abc".to_string()));
+        assert!(order_book.get_order_by_id("1").is_none());
+
+        // Well-formed orders the book rejects are skipped
+        let unknown_delete = Order {
+            type_op: Operation::Delete,
+            order_id: "9".to_string(),
+            ..buy_order.clone()
+        };
+        let trades = order_book
+            .process_orders_batch(vec![
+                sell_order,
+                unknown_delete,
+                buy_order.clone(),
+                Order {
+                    order_id: "4".to_string(),
+                    ..buy_order
+                },
+            ])
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+        assert!(order_book.get_order_by_id("1").is_none());
+    }
+
+    #[test]
+    fn test_trade_history_management() {
+        let mut order_book = OrderBook::default();
+        order_book.set_max_trade_history(Some(2));
+
+        for id in 1..=3 {
+            let sell_order = OrderBuilder::sell("BTC/USDC", "1", "100")
+                .account("1")
+                .order_id(&format!("s{}", id))
+                .build()
+                .unwrap();
+            let buy_order = OrderBuilder::buy("BTC/USDC", "1", "100")
+                .account("2")
+                .order_id(&format!("b{}", id))
+                .build()
+                .unwrap();
+            order_book.process_order(sell_order).unwrap();
+            order_book.process_order(buy_order).unwrap();
+        }
+
+        // Only the two most recent trades are kept
+        let makers: Vec<&str> = order_book
+            .trades
+            .iter()
+            .map(|t| t.maker_order_id.as_str())
+            .collect();
+        assert_eq!(makers, vec!["s2", "s3"]);
+
+        let latest_ms = order_book.trades[1].timestamp / 1_000_000;
+        assert!(!order_book.trades_since(latest_ms).is_empty());
+        assert!(order_book.trades_since(latest_ms + 1).is_empty());
+        assert_eq!(order_book.trades_since(0).len(), 2);
+
+        let archived = order_book.clear_trades();
+        assert_eq!(archived.len(), 2);
+        assert!(order_book.trades.is_empty());
+    }
+
+    #[test]
+    fn test_get_trade_by_id() {
+        let mut order_book = OrderBook::default();
+        order_book.set_max_trade_history(Some(3));
+
+        let mut trade_ids = Vec::new();
+        for (id, price) in [("1", "100"), ("2", "101"), ("3", "102"), ("4", "103")] {
+            let sell_order = OrderBuilder::sell("BTC/USDC", "1", price)
+                .account("1")
+                .order_id(&format!("s{}", id))
+                .build()
+                .unwrap();
+            let buy_order = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("2")
+                .order_id(&format!("b{}", id))
+                .build()
+                .unwrap();
+            order_book.process_order(sell_order).unwrap();
+            let (_, trades) = order_book.process_order(buy_order).unwrap();
+            trade_ids.push(trades[0].trade_id.clone());
+        }
+
+        // The first trade was evicted by the history cap
+        assert!(order_book.get_trade_by_id(&trade_ids[0]).is_none());
+        for (trade_id, price) in trade_ids[1..].iter().zip(["101", "102", "103"]) {
+            let trade = order_book.get_trade_by_id(trade_id).unwrap();
+            assert_eq!(trade.price, price);
+        }
+
+        order_book.clear_trades();
+        assert!(order_book.get_trade_by_id(&trade_ids[3]).is_none());
+        assert!(order_book.get_trade_by_id("unknown").is_none());
+    }
+
+    #[test]
+    fn test_filter_trades() {
+        let mut order_book = OrderBook::default();
+
+        for (id, price, buyer) in [("1", "100", "2"), ("2", "105", "3"), ("3", "110", "2")] {
+            let sell_order = OrderBuilder::sell("BTC/USDC", "1", price)
+                .account("1")
+                .order_id(&format!("s{}", id))
+                .build()
+                .unwrap();
+            let buy_order = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account(buyer)
+                .order_id(&format!("b{}", id))
+                .build()
+                .unwrap();
+            order_book.process_order(sell_order).unwrap();
+            order_book.process_order(buy_order).unwrap();
+        }
+
+        let prices = |filter: TradeFilter| -> Vec<String> {
+            order_book
+                .filter_trades(filter)
+                .into_iter()
+                .map(|t| t.price.clone())
+                .collect()
+        };
+
+        assert_eq!(prices(TradeFilter::default()).len(), 3);
+        assert_eq!(
+            prices(TradeFilter {
+                account_id: Some("2".to_string()),
+                min_price: Some(Decimal::from(101)),
+                ..TradeFilter::default()
+            }),
+            vec!["110"]
+        );
+        assert_eq!(
+            prices(TradeFilter {
+                pair: Some("BTC/USDC".to_string()),
+                max_price: Some(Decimal::from(105)),
+                ..TradeFilter::default()
+            }),
+            vec!["100", "105"]
+        );
+        assert!(
+            prices(TradeFilter {
+                pair: Some("ETH/USDC".to_string()),
+                ..TradeFilter::default()
+            })
+            .is_empty()
+        );
+
+        let now_ms = get_current_timestamp() / 1_000_000;
+        assert_eq!(
+            prices(TradeFilter {
+                from_ms: Some(0),
+                to_ms: Some(now_ms + 1),
+                ..TradeFilter::default()
+            })
+            .len(),
+            3
+        );
+        assert!(
+            prices(TradeFilter {
+                from_ms: Some(now_ms + 1),
+                ..TradeFilter::default()
+            })
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_format_order_book_ascii() {
+        let mut order_book = OrderBook::default();
+        assert!(order_book.format_order_book_ascii(5).contains(" mid - "));
+
+        for (id, side, amount, price) in [
+            ("1", Side::Buy, "1", "99"),
+            ("2", Side::Buy, "2", "98"),
+            ("3", Side::Sell, "0.5", "101"),
+            ("4", Side::Sell, "2", "102"),
+            ("5", Side::Sell, "1", "103"),
+        ] {
+            let order = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", amount, price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", amount, price),
+            };
+            order_book
+                .process_order(order.account("1").order_id(id).build().unwrap())
+                .unwrap();
+        }
+
+        let output = order_book.format_order_book_ascii(2);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].contains("PRICE") && lines[0].contains("AMOUNT"));
+
+        // Asks descend towards the mid price line, bids descend below it
+        assert!(lines[1].trim_start().starts_with("102"));
+        assert!(lines[2].trim_start().starts_with("101"));
+        assert!(lines[3].contains(" mid 100 "));
+        assert!(lines[4].trim_start().starts_with("99"));
+        assert!(lines[5].trim_start().starts_with("98"));
+
+        // Bars scale with the amount at each level
+        let bar = |line: &str| line.matches('#').count();
+        assert_eq!(bar(lines[1]), 20);
+        assert_eq!(bar(lines[2]), 5);
+        assert_eq!(bar(lines[4]), 10);
+    }
+
+    #[test]
+    fn test_order_book_config() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            tick_size: Some(Decimal::ONE),
+            default_time_in_force: TimeInForce::Ioc,
+            ..OrderBookConfig::default()
+        })
+        .unwrap();
+        assert_eq!(order_book.config().tick_size, Some(Decimal::ONE));
+
+        // Orders without a time in force take the book's default
+        let buy_order = OrderBuilder::buy("BTC/USDC", "1", "100")
+            .account("1")
+            .order_id("1")
+            .build()
+            .unwrap();
+        assert_eq!(buy_order.time_in_force, None);
+        order_book.process_order(buy_order).unwrap();
+        assert!(order_book.get_order_by_id("1").is_none());
+
+        let gtc_order = OrderBuilder::buy("BTC/USDC", "1", "100")
+            .account("1")
+            .order_id("2")
+            .time_in_force(TimeInForce::Gtc)
+            .build()
+            .unwrap();
+        order_book.process_order(gtc_order).unwrap();
+        assert!(order_book.get_order_by_id("2").is_some());
+
+        let off_tick = OrderBuilder::buy("BTC/USDC", "1", "100.5")
+            .account("1")
+            .order_id("3")
+            .time_in_force(TimeInForce::Gtc)
+            .build()
+            .unwrap();
+        assert_eq!(
+            order_book.process_order(off_tick).unwrap_err(),
+            OrderBookError::InvalidTickSize("100.5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            max_depth: Some(2),
+            ..OrderBookConfig::default()
+        })
+        .unwrap();
+
+        let bid = |id: &str, price: &str| {
+            OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .order_id(id)
+                .build()
+                .unwrap()
+        };
+        order_book.process_order(bid("1", "99")).unwrap();
+        order_book.process_order(bid("2", "98")).unwrap();
+
+        // A bid below the second level is dropped straight away
+        order_book.process_order(bid("3", "97")).unwrap();
+        assert!(order_book.get_order_by_id("3").is_none());
+
+        // A better bid pushes out the worst level
+        order_book.process_order(bid("4", "100")).unwrap();
+        assert!(order_book.get_order_by_id("2").is_none());
+        assert_eq!(order_book.bids.len(), 2);
+
+        let evicted = order_book.trim_to_depth(1);
+        let evicted_ids: Vec<&str> = evicted.iter().map(|o| o.order_id.as_str()).collect();
+        assert_eq!(evicted_ids, vec!["1"]);
+        assert_eq!(order_book.get_best_bid(), Some(Decimal::from(100)));
+    }
+
+    #[test]
+    fn test_order_counts() {
+        let mut order_book = OrderBook::default();
+        assert!(order_book.is_empty());
+
+        for (id, price) in [("1", "99"), ("2", "99"), ("3", "98")] {
+            let order = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .order_id(id)
+                .build()
+                .unwrap();
+            order_book.process_order(order).unwrap();
+        }
+        let sell_order = OrderBuilder::sell("BTC/USDC", "1", "101")
+            .account("2")
+            .order_id("4")
+            .build()
+            .unwrap();
+        order_book.process_order(sell_order).unwrap();
+        assert_eq!((order_book.bid_count(), order_book.ask_count()), (3, 1));
+        assert_eq!(order_book.total_order_count(), 4);
+
+        // Fills and cancels both reduce the counts
+        let sell_order = OrderBuilder::sell("BTC/USDC", "1", "99")
+            .account("2")
+            .order_id("5")
+            .build()
+            .unwrap();
+        order_book.process_order(sell_order).unwrap();
+        order_book.cancel_all_orders_for_account("2");
+        assert_eq!((order_book.bid_count(), order_book.ask_count()), (2, 0));
+        assert!(!order_book.is_empty());
+
+        order_book.cancel_all_orders_for_account("1");
+        assert!(order_book.is_empty());
+    }
+
+    #[test]
+    fn test_merge_order_books() {
+        let order = |id: &str, side: Side, price: &str| {
+            let builder = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", "1", price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", "1", price),
+            };
+            builder.account(id).order_id(id).build().unwrap()
+        };
+
+        let mut order_book = OrderBook::default();
+        order_book
+            .process_order(order("1", Side::Sell, "101"))
+            .unwrap();
+        order_book
+            .process_order(order("2", Side::Buy, "101"))
+            .unwrap();
+        order_book
+            .process_order(order("3", Side::Buy, "99"))
+            .unwrap();
+
+        let mut other = OrderBook::default();
+        other.process_order(order("4", Side::Buy, "99")).unwrap();
+        other.process_order(order("5", Side::Sell, "102")).unwrap();
+
+        let mut duplicate = OrderBook::default();
+        duplicate
+            .process_order(order("3", Side::Buy, "98"))
+            .unwrap();
+        assert_eq!(
+            order_book.merge(duplicate).unwrap_err(),
+            OrderBookError::DuplicateOrderId("3".to_string())
+        );
+        assert_eq!(order_book.total_order_count(), 1);
+
+        order_book.merge(other).unwrap();
+        assert_eq!(order_book.total_order_count(), 3);
+        assert_eq!(order_book.trades.len(), 1);
+
+        // Older trades from the other book go ahead of this book's in the history
+        let old_trade = Trade {
+            trade_id: "old".to_string(),
+            price: "90".to_string(),
+            amount: "2".to_string(),
+            timestamp: 1_000 * 1_000_000,
+            ..order_book.trades[0].clone()
+        };
+        order_book
+            .merge(OrderBook::default().with_trades_from_history(vec![old_trade]))
+            .unwrap();
+        assert_eq!(order_book.trades[0].trade_id, "old");
+        assert_eq!(order_book.trades_since(2_000).len(), 1);
+        assert!(order_book.get_trade_by_id("old").is_some());
+        assert_eq!(order_book.last_trade_price(), Some(Decimal::from(101)));
+        assert_eq!(order_book.session_low(), Some(Decimal::from(90)));
+        assert_eq!(order_book.session_volume(), Decimal::from(3));
+
+        // Merged orders queue behind existing ones and trade normally
+        let (_, trades) = order_book
+            .process_order(order("6", Side::Sell, "99"))
+            .unwrap();
+        assert_eq!(trades[0].maker_order_id, "3");
+        let (_, trades) = order_book
+            .process_order(order("7", Side::Buy, "102"))
+            .unwrap();
+        assert_eq!(trades[0].maker_order_id, "5");
+        assert!(order_book.get_order_by_id("4").is_some());
+    }
+
+    #[test]
+    fn test_duplicate_order_id_rejected() {
+        let mut order_book = OrderBook::default();
+
+        let buy_order = OrderBuilder::buy("BTC/USDC", "1", "99")
+            .account("1")
+            .order_id("1")
+            .build()
+            .unwrap();
+        order_book.process_order(buy_order.clone()).unwrap();
+
+        assert_eq!(
+            order_book.process_order(buy_order.clone()).unwrap_err(),
+            OrderBookError::DuplicateOrderId("1".to_string())
+        );
+        assert_eq!(
+            order_book.add_order(buy_order.clone()).unwrap_err(),
+            OrderBookError::DuplicateOrderId("1".to_string())
+        );
+        assert_eq!(order_book.bid_count(), 1);
+
+        // A single DELETE removes the order and frees its id
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..buy_order.clone()
+            })
+            .unwrap();
+        assert!(order_book.is_empty());
+        order_book.process_order(buy_order).unwrap();
+        assert_eq!(order_book.bid_count(), 1);
+    }
+
+    #[test]
+    fn test_dedup_redelivered_orders() {
+        let mut order_book = OrderBook::default();
+        order_book.set_dedup_cache_size(2);
+
+        let sell_order = OrderBuilder::sell("BTC/USDC", "1", "100")
+            .account("1")
+            .order_id("1")
+            .build()
+            .unwrap();
+        order_book.process_order(sell_order.clone()).unwrap();
+
+        let buy_order = OrderBuilder::buy("BTC/USDC", "0.5", "100")
+            .account("2")
+            .order_id("2")
+            .build()
+            .unwrap();
+        assert_eq!(
+            order_book.process_order(buy_order.clone()).unwrap().1.len(),
+            1
+        );
+
+        // The redelivered buy is a no-op instead of a second fill
+        assert!(
+            order_book
+                .process_order(buy_order.clone())
+                .unwrap()
+                .1
+                .is_empty()
+        );
+        assert_eq!(order_book.trades.len(), 1);
+
+        // Cancels for a seen id still go through
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..sell_order
+            })
+            .unwrap();
+        assert!(order_book.is_empty());
+
+        // Once evicted from the cache, the id is processed again
+        for id in ["3", "4"] {
+            let order = OrderBuilder::buy("BTC/USDC", "1", "90")
+                .account("2")
+                .order_id(id)
+                .build()
+                .unwrap();
+            order_book.process_order(order).unwrap();
+        }
+        order_book.process_order(buy_order).unwrap();
+        assert!(order_book.get_order_by_id("2").is_some());
+    }
+
+    #[test]
+    fn test_validate_invariants() {
+        let mut order_book = OrderBook::default();
+
+        for (id, side, price) in [("1", Side::Buy, "99"), ("2", Side::Sell, "101")] {
+            let builder = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", "1", price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", "1", price),
+            };
+            order_book
+                .process_order(builder.account(id).order_id(id).build().unwrap())
+                .unwrap();
+        }
+        assert_eq!(order_book.validate_invariants(), Ok(()));
+
+        // Corrupt the public maps directly: a sell resting on the bid side above the
+        // best ask, with a zero amount, that the book never indexed
+        let mut rogue = OrderBuilder::sell("BTC/USDC", "1", "102")
+            .account("3")
+            .order_id("3")
+            .build()
+            .unwrap();
+        rogue.amount = "0".to_string();
+        order_book
+            .bids
+            .entry(Decimal::from(102))
+            .or_default()
+            .push(rogue);
+        order_book.asks.clear();
+
+        let violations = order_book.validate_invariants().unwrap_err();
+        assert!(violations.contains(&InvariantViolation::WrongSide("3".to_string(), Side::Buy)));
+        assert!(violations.contains(&InvariantViolation::NonPositiveAmount(
+            "3".to_string(),
+            "0".to_string()
+        )));
+        assert!(violations.contains(&InvariantViolation::UntrackedOrder("3".to_string())));
+        assert!(violations.contains(&InvariantViolation::MissingOrder("2".to_string())));
+
+        order_book.asks.insert(Decimal::from(100), Vec::new());
+        let violations = order_book.validate_invariants().unwrap_err();
+        assert!(violations.contains(&InvariantViolation::CrossedBook(
+            Decimal::from(102),
+            Decimal::from(100)
+        )));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_matching_respects_limits_and_amounts(
+            orders in prop::collection::vec((any::<bool>(), 90u32..110, 1u32..50), 1..60)
+        ) {
+            let mut order_book = OrderBook::default();
+            // order_id -> (side, limit price, original amount)
+            let mut submitted = std::collections::HashMap::new();
+            let mut filled: std::collections::HashMap<String, Decimal> =
+                std::collections::HashMap::new();
+            let mut buy_volume = Decimal::ZERO;
+            let mut buy_fills = Decimal::ZERO;
+
+            for (id, (is_buy, price, amount)) in orders.into_iter().enumerate() {
+                let id = id.to_string();
+                let price = Decimal::from(price);
+                // Amounts in tenths, so partial fills leave fractional remainders
+                let amount = Decimal::new(amount as i64, 1);
+                let builder = if is_buy {
+                    buy_volume += amount;
+                    OrderBuilder::buy("BTC/USDC", &amount.to_string(), &price.to_string())
+                } else {
+                    OrderBuilder::sell("BTC/USDC", &amount.to_string(), &price.to_string())
+                };
+                let side = if is_buy { Side::Buy } else { Side::Sell };
+                submitted.insert(id.clone(), (side, price, amount));
+
+                let (_, trades) = order_book
+                    .process_order(builder.account(&id).order_id(&id).build().unwrap())
+                    .unwrap();
+
+                for trade in &trades {
+                    let trade_price = Decimal::from_str(&trade.price).unwrap();
+                    let trade_amount = Decimal::from_str(&trade.amount).unwrap();
+                    buy_fills += trade_amount;
+
+                    for order_id in [&trade.taker_order_id, &trade.maker_order_id] {
+                        let (side, limit, original) = submitted[order_id];
+                        match side {
+                            // (1) a buyer never pays above their limit
+                            Side::Buy => prop_assert!(trade_price <= limit),
+                            // (2) a seller never receives below their limit
+                            Side::Sell => prop_assert!(trade_price >= limit),
+                        }
+                        // (3) an order never trades more than it was submitted with
+                        let total = filled.entry(order_id.clone()).or_default();
+                        *total += trade_amount;
+                        prop_assert!(*total <= original);
+                    }
+                }
+
+                // (4) every submitted buy unit is either resting or filled
+                let resting_bids: Decimal = order_book
+                    .bids
+                    .values()
+                    .flatten()
+                    .map(|order| Decimal::from_str(&order.amount).unwrap())
+                    .sum();
+                prop_assert_eq!(resting_bids + buy_fills, buy_volume);
+            }
+        }
+    }
+
+    #[test]
+    fn test_trade_aggressor_tick_rule() {
+        let mut order_book = OrderBook::default();
+
+        // Rests a maker order at the price, then crosses it with a taker on the
+        // given side and returns the resulting trade's classification
+        let mut next_id = 0;
+        let mut aggressor = |taker_side: Side, price: &str| {
+            let mut order = |side: Side, account: &str| {
+                next_id += 1;
+                let builder = match side {
+                    Side::Buy => OrderBuilder::buy("BTC/USDC", "1", price),
+                    Side::Sell => OrderBuilder::sell("BTC/USDC", "1", price),
+                };
+                builder
+                    .account(account)
+                    .order_id(&next_id.to_string())
+                    .build()
+                    .unwrap()
+            };
+            let maker = order(taker_side.opposite(), "maker");
+            let taker = order(taker_side, "taker");
+            order_book.process_order(maker).unwrap();
+            order_book.process_order(taker).unwrap().1[0].aggressor
+        };
+
+        // Nothing to compare the first trade against, so the taker's side is used
+        assert_eq!(aggressor(Side::Sell, "100"), TradeSide::Seller);
+        // Uptick
+        assert_eq!(aggressor(Side::Buy, "101"), TradeSide::Buyer);
+        // Unchanged price carries the previous classification forward
+        assert_eq!(aggressor(Side::Sell, "101"), TradeSide::Buyer);
+        // Downtick
+        assert_eq!(aggressor(Side::Buy, "99"), TradeSide::Seller);
+    }
+
+    #[test]
+    fn test_fill_event() {
+        let mut order_book = OrderBook::default();
+        let order = |builder: OrderBuilder, id: &str| builder.order_id(id).build().unwrap();
+
+        let (fill, _) = order_book
+            .process_order(order(OrderBuilder::sell("BTC/USDC", "3", "100"), "1"))
+            .unwrap();
+        assert_eq!(fill.status, OrderStatus::Open);
+        assert_eq!(fill.remaining_amount, Decimal::from(3));
+
+        // Partially filled, with the remainder resting
+        let (fill, trades) = order_book
+            .process_order(order(OrderBuilder::buy("BTC/USDC", "5", "100"), "2"))
+            .unwrap();
+        assert_eq!(fill.order_id, "2");
+        assert_eq!(fill.filled_amount, Decimal::from(3));
+        assert_eq!(fill.remaining_amount, Decimal::from(2));
+        assert_eq!(
+            fill.status,
+            OrderStatus::PartiallyFilled {
+                filled: Decimal::from(3)
+            }
+        );
+        assert_eq!(fill.trades.len(), trades.len());
+
+        // Fully filled against the resting remainder
+        let (fill, _) = order_book
+            .process_order(order(OrderBuilder::sell("BTC/USDC", "2", "100"), "3"))
+            .unwrap();
+        assert_eq!(fill.status, OrderStatus::Filled);
+        assert_eq!(fill.remaining_amount, Decimal::ZERO);
+
+        // An IOC with nothing to trade against is cancelled in full
+        let (fill, _) = order_book
+            .process_order(order(
+                OrderBuilder::sell("BTC/USDC", "1", "100").time_in_force(TimeInForce::Ioc),
+                "4",
+            ))
+            .unwrap();
+        assert_eq!(fill.status, OrderStatus::Cancelled);
+        assert_eq!(fill.remaining_amount, Decimal::ONE);
+
+        // Deleting reports the amount that was still working
+        order_book
+            .process_order(order(OrderBuilder::buy("BTC/USDC", "4", "90"), "5"))
+            .unwrap();
+        let (fill, _) = order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..order(OrderBuilder::buy("BTC/USDC", "4", "90"), "5")
+            })
+            .unwrap();
+        assert_eq!(fill.status, OrderStatus::Cancelled);
+        assert_eq!(fill.filled_amount, Decimal::ZERO);
+        assert_eq!(fill.remaining_amount, Decimal::from(4));
+
+        // So does amending the amount to zero, which counts as a cancel
+        let bid = order(OrderBuilder::buy("BTC/USDC", "2", "90").account("7"), "6");
+        order_book.process_order(bid.clone()).unwrap();
+        let (fill, _) = order_book
+            .process_order(Order {
+                type_op: Operation::Modify,
+                new_amount: Some("0".to_string()),
+                ..bid
+            })
+            .unwrap();
+        assert_eq!(fill.status, OrderStatus::Cancelled);
+        assert_eq!(fill.remaining_amount, Decimal::from(2));
+        assert_eq!(
+            order_book.get_order_status("6"),
+            Some(OrderStatus::Cancelled)
+        );
+        assert_eq!(order_book.bid_count(), 0);
+        assert_eq!(order_book.cancel_rate("7", 60_000), 1.0);
+    }
+
+    #[test]
+    fn test_order_status_lifecycle() {
+        let mut order_book = OrderBook::default();
+        let order = |builder: OrderBuilder, id: &str| builder.order_id(id).build().unwrap();
+
+        order_book
+            .process_order(order(OrderBuilder::sell("BTC/USDC", "3", "100"), "1"))
+            .unwrap();
+        assert_eq!(order_book.get_order_status("1"), Some(OrderStatus::Open));
+
+        // Each fill against the maker accumulates until it is filled in full
+        order_book
+            .process_order(order(OrderBuilder::buy("BTC/USDC", "1", "100"), "2"))
+            .unwrap();
+        assert_eq!(
+            order_book.get_order_status("1"),
+            Some(OrderStatus::PartiallyFilled {
+                filled: Decimal::ONE
+            })
+        );
+        assert_eq!(order_book.get_order_status("2"), Some(OrderStatus::Filled));
+        order_book
+            .process_order(order(OrderBuilder::buy("BTC/USDC", "1.5", "100"), "3"))
+            .unwrap();
+        assert_eq!(
+            order_book.get_order_status("1"),
+            Some(OrderStatus::PartiallyFilled {
+                filled: Decimal::from_str("2.5").unwrap()
+            })
+        );
+        order_book
+            .process_order(order(OrderBuilder::buy("BTC/USDC", "1", "100"), "4"))
+            .unwrap();
+        assert_eq!(order_book.get_order_status("1"), Some(OrderStatus::Filled));
+        assert_eq!(
+            order_book.get_order_status("4"),
+            Some(OrderStatus::PartiallyFilled {
+                filled: Decimal::from_str("0.5").unwrap()
+            })
+        );
+
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..order(OrderBuilder::buy("BTC/USDC", "1", "100"), "4")
+            })
+            .unwrap();
+        assert_eq!(
+            order_book.get_order_status("4"),
+            Some(OrderStatus::Cancelled)
+        );
+
+        let err = order_book
+            .process_order(Order {
+                amount: "0".to_string(),
+                ..order(OrderBuilder::buy("BTC/USDC", "1", "100"), "5")
+            })
+            .unwrap_err();
+        assert_eq!(
+            order_book.get_order_status("5"),
+            Some(OrderStatus::Rejected(err))
+        );
+        assert_eq!(order_book.get_order_status("6"), None);
+    }
+
+    #[test]
+    fn test_weighted_mid_price() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(order_book.weighted_mid_price(), None);
+
+        for (id, builder) in [
+            ("1", OrderBuilder::buy("BTC/USDC", "3", "99")),
+            ("2", OrderBuilder::buy("BTC/USDC", "5", "98")),
+            ("3", OrderBuilder::sell("BTC/USDC", "1", "101")),
+        ] {
+            order_book
+                .process_order(builder.order_id(id).build().unwrap())
+                .unwrap();
+        }
+
+        // (101 * 3 + 99 * 1) / (3 + 1): deeper bids pull the price toward the ask
+        assert_eq!(
+            order_book.weighted_mid_price(),
+            Some(Decimal::from_str("100.5").unwrap())
+        );
+        assert_eq!(order_book.get_mid_price(), Some(Decimal::from(100)));
+    }
+
+    #[test]
+    fn test_twap() {
+        let mut order_book = OrderBook::default();
+        let trade = |price: &str, timestamp_ms: u64| Trade {
+            trade_id: timestamp_ms.to_string(),
+            taker_order_id: "2".to_string(),
+            maker_order_id: "1".to_string(),
+            taker_account_id: "2".to_string(),
+            maker_account_id: "1".to_string(),
+            pair: "BTC/USDC".parse().unwrap(),
+            price: price.to_string(),
+            amount: "1".to_string(),
+            timestamp: timestamp_ms * 1_000_000,
+            maker_fee: Decimal::ZERO,
+            taker_fee: Decimal::ZERO,
+            aggressor: TradeSide::Buyer,
+            tick_direction: TickDirection::default(),
+            taker_side: Some(Side::Buy),
+        };
+        order_book.trades = vec![
+            trade("90", 500),
+            trade("100", 1_000),
+            trade("110", 1_500),
+            trade("130", 3_200),
+        ];
+
+        // Buckets: [1000, 2000) averages 105, [2000, 3000) carries 110 forward and
+        // [3000, 4000) is 130
+        assert_eq!(
+            order_book.twap(1_000, 4_000, 1_000),
+            Some(Decimal::from(115))
+        );
+        // The leading empty bucket [600, 900) carries 90 from before the range
+        assert_eq!(order_book.twap(600, 1_200, 300), Some(Decimal::from(95)));
+        assert_eq!(order_book.twap(4_000, 5_000, 1_000), None);
+        assert_eq!(order_book.twap(1_000, 4_000, 0), None);
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let mut order_book = OrderBook::default();
+        order_book
+            .process_order(OrderBuilder::sell("BTC/USDC", "1", "100").build().unwrap())
+            .unwrap();
+        assert!(order_book.audit_log().is_empty());
+
+        let mut order_book = OrderBook::default().with_audit_log();
+        order_book
+            .process_order(
+                OrderBuilder::sell("BTC/USDC", "2", "100")
+                    .account("maker")
+                    .order_id("1")
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        let (_, trades) = order_book
+            .process_order(
+                OrderBuilder::buy("BTC/USDC", "1", "100")
+                    .account("taker")
+                    .order_id("2")
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..OrderBuilder::sell("BTC/USDC", "1", "100")
+                    .account("maker")
+                    .order_id("1")
+                    .build()
+                    .unwrap()
+            })
+            .unwrap();
+        // Rejected actions change nothing and aren't recorded
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..OrderBuilder::sell("BTC/USDC", "1", "100")
+                    .order_id("missing")
+                    .build()
+                    .unwrap()
+            })
+            .unwrap_err();
+
+        let entries = order_book.audit_log().entries();
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].operation, Operation::Create);
+        assert!(entries[0].before_state.is_none());
+        assert_eq!(entries[0].after_state.as_ref().unwrap().amount, "2");
+
+        assert_eq!(entries[1].order_id, "2");
+        assert_eq!(entries[1].account_id, "taker");
+        assert!(entries[1].after_state.is_none());
+        assert_eq!(
+            entries[1].resulting_trades,
+            vec![trades[0].trade_id.clone()]
+        );
+
+        assert_eq!(entries[2].operation, Operation::Delete);
+        assert_eq!(entries[2].before_state.as_ref().unwrap().amount, "1");
+        assert!(entries[2].after_state.is_none());
+        assert!(entries[2].timestamp_ns >= entries[1].timestamp_ns);
+    }
+
+    #[test]
+    fn test_csv_orders_and_trades() {
+        let csv = "\
+type_op,account_id,amount,order_id,pair,limit_price,side,time_in_force,new_amount
+CREATE,1,2.5,1,BTC/USDC,100,SELL,GTC,
+CREATE,2,1,2,BTC/USDC,101,BUY,,
+";
+        let orders = orders_from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].time_in_force, Some(TimeInForce::Gtc));
+        assert_eq!(orders[1].time_in_force, None);
+        assert_eq!(orders[1].new_amount, None);
+
+        let mut order_book = OrderBook::default();
+        for order in orders {
+            order_book.process_order(order).unwrap();
+        }
+
+        let mut output = Vec::new();
+        trades_to_csv(&order_book.trades, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "trade_id,taker_order_id,maker_order_id,taker_account_id,maker_account_id,pair,\
+             price,amount,timestamp,maker_fee,taker_fee,aggressor,tick_direction,taker_side"
+        );
+        let row: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(&row[1..8], ["2", "1", "2", "1", "BTC/USDC", "100", "1"]);
+        assert_eq!(lines.next(), None);
+
+        // A malformed row fails the whole import
+        assert!(orders_from_csv("type_op,amount\nCREATE,1\n".as_bytes()).is_err());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_round_trip() {
+        let mut order_book = OrderBook::default();
+        for (id, builder) in [
+            ("1", OrderBuilder::sell("BTC/USDC", "2", "101")),
+            ("2", OrderBuilder::sell("BTC/USDC", "1.5", "102")),
+            ("3", OrderBuilder::buy("BTC/USDC", "1", "101")),
+            ("4", OrderBuilder::buy("BTC/USDC", "3", "99")),
+        ] {
+            order_book
+                .process_order(builder.order_id(id).account(id).build().unwrap())
+                .unwrap();
+        }
+        let mut iceberg = OrderBuilder::buy("BTC/USDC", "5", "98")
+            .order_id("5")
+            .build()
+            .unwrap();
+        iceberg.visible_amount = Some(Decimal::ONE);
+        order_book.process_order(iceberg).unwrap();
+
+        let bytes = order_book.to_bincode().unwrap();
+        let restored = OrderBook::from_bincode(&bytes).unwrap();
+
+        // Sequence numbers are reassigned on restore, but queues keep their order
+        let orders = |book: &OrderBook| {
+            book.snapshot()
+                .orders
+                .into_iter()
+                .map(|o| {
+                    (
+                        o.order_id,
+                        o.amount,
+                        o.limit_price,
+                        o.visible_amount,
+                        o.status,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(orders(&restored), orders(&order_book));
+        assert_eq!(
+            serde_json::to_value(&restored.trades).unwrap(),
+            serde_json::to_value(&order_book.trades).unwrap()
+        );
+        assert_eq!(restored.get_depth(10), order_book.get_depth(10));
+        assert!(OrderBook::from_bincode(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut order_book = OrderBook::default();
+        for (id, builder) in [
+            ("1", OrderBuilder::sell("BTC/USDC", "2", "101")),
+            ("2", OrderBuilder::buy("BTC/USDC", "1", "101")),
+            ("3", OrderBuilder::buy("BTC/USDC", "3", "99")),
+        ] {
+            order_book
+                .process_order(builder.order_id(id).account(id).build().unwrap())
+                .unwrap();
+        }
+        assert_eq!(order_book.session_number(), 0);
+
+        let cancelled = order_book.reset(true);
+        let ids: Vec<&str> = cancelled.iter().map(|o| o.order_id.as_str()).collect();
+        assert_eq!(ids, ["3", "1"]);
+        assert!(order_book.is_empty());
+        assert_eq!(
+            order_book.get_order_status("1"),
+            Some(OrderStatus::Cancelled)
+        );
+        assert_eq!(order_book.session_volume(), Decimal::ZERO);
+        assert_eq!(order_book.trades.len(), 1);
+        assert_eq!(order_book.session_number(), 1);
+
+        assert!(order_book.reset(false).is_empty());
+        assert!(order_book.trades.is_empty());
+        assert_eq!(order_book.session_number(), 2);
+    }
+
+    #[test]
+    fn test_with_trades_from_history() {
+        let mut source = OrderBook::default();
+        for (id, builder) in [
+            ("1", OrderBuilder::sell("BTC/USDC", "1", "101")),
+            ("2", OrderBuilder::sell("BTC/USDC", "2", "103")),
+            ("3", OrderBuilder::buy("BTC/USDC", "3", "103")),
+            ("4", OrderBuilder::buy("BTC/USDC", "1", "99")),
+        ] {
+            source
+                .process_order(builder.order_id(id).account(id).build().unwrap())
+                .unwrap();
+        }
+
+        let order_book = OrderBook::default().with_trades_from_history(source.trades.clone());
+        assert_eq!(order_book.trades.len(), 2);
+        assert!(order_book.is_empty());
+        assert_eq!(order_book.last_trade_price(), Some(Decimal::from(103)));
+        assert_eq!(order_book.session_high(), Some(Decimal::from(103)));
+        assert_eq!(order_book.session_low(), Some(Decimal::from(101)));
+        assert_eq!(order_book.session_volume(), Decimal::from(3));
+        let trade_id = &source.trades[0].trade_id;
+        assert!(order_book.get_trade_by_id(trade_id).is_some());
+
+        // Restoring a snapshot rebuilds the same statistics
+        let restored = OrderBook::restore(source.snapshot()).unwrap();
+        assert_eq!(restored.session_volume(), source.session_volume());
+        assert_eq!(restored.last_trade_price(), source.last_trade_price());
+    }
+
+    #[test]
+    fn test_market_order_protection() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            market_order_protection_pct: Some(Decimal::from_str("0.05").unwrap()),
+            ..OrderBookConfig::default()
+        })
+        .unwrap();
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+
+        for (id, price) in [("1", "100"), ("2", "104"), ("3", "110")] {
+            order_book
+                .process_order(
+                    OrderBuilder::sell("BTC/USDC", "1", price)
+                        .account("maker")
+                        .order_id(id)
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+
+        let market_buy = Order {
+            order_type: OrderType::Market,
+            limit_price: String::new(),
+            ..OrderBuilder::buy("BTC/USDC", "3", "1")
+                .account("taker")
+                .order_id("4")
+                .build()
+                .unwrap()
+        };
+        let (fill, trades) = order_book.process_order(market_buy).unwrap();
+
+        // Capped at 105, so the ask at 110 is left alone and the rest is cancelled
+        let prices: Vec<&str> = trades.iter().map(|t| t.price.as_str()).collect();
+        assert_eq!(prices, ["100", "104"]);
+        assert_eq!(fill.status, OrderStatus::Cancelled);
+        assert_eq!(fill.remaining_amount, Decimal::ONE);
+        assert!(order_book.get_order_by_id("3").is_some());
+        assert!(order_book.get_order_by_id("4").is_none());
+
+        let mut protected = None;
+        while let Ok(event) = receiver.try_recv() {
+            if let OrderBookEvent::MarketOrderProtected(order_id, limit_price) = event {
+                protected = Some((order_id, limit_price));
+            }
+        }
+        assert_eq!(protected, Some(("4".to_string(), Decimal::from(105))));
+    }
+
+    #[test]
+    fn test_oco_orders() {
+        let mut order_book = OrderBook::default();
+        let order = |builder: OrderBuilder, id: &str| {
+            builder.account("trader").order_id(id).build().unwrap()
+        };
+
+        // Two resting exits for the same position
+        let result = order_book
+            .process_oco(OcoOrderPair {
+                take_profit: order(OrderBuilder::sell("BTC/USDC", "2", "110"), "tp"),
+                stop_loss: order(OrderBuilder::sell("BTC/USDC", "2", "120"), "sl"),
+            })
+            .unwrap();
+        assert_eq!(result.take_profit.status, OrderStatus::Open);
+        assert_eq!(result.stop_loss.unwrap().status, OrderStatus::Open);
+        assert!(result.trades.is_empty());
+
+        // A partial fill on the take-profit cancels the stop-loss
+        order_book
+            .process_order(
+                OrderBuilder::buy("BTC/USDC", "1", "110")
+                    .account("other")
+                    .order_id("b1")
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(order_book.get_order_by_id("tp").is_some());
+        assert!(order_book.get_order_by_id("sl").is_none());
+        assert_eq!(
+            order_book.get_order_status("sl"),
+            Some(OrderStatus::Cancelled)
+        );
+
+        // A rejected stop-loss withdraws the take-profit
+        let err = order_book
+            .process_oco(OcoOrderPair {
+                take_profit: order(OrderBuilder::sell("BTC/USDC", "1", "130"), "tp2"),
+                stop_loss: Order {
+                    amount: "-1".to_string(),
+                    ..order(OrderBuilder::sell("BTC/USDC", "1", "140"), "sl2")
+                },
+            })
+            .unwrap_err();
+        assert_eq!(err, OrderBookError::InvalidAmount("-1".to_string()));
+        assert!(order_book.get_order_by_id("tp2").is_none());
+    }
+
+    #[test]
+    fn test_repeg_all() {
+        let mut order_book = OrderBook::default();
+        let place = |order_book: &mut OrderBook, builder: OrderBuilder, id: &str, peg| {
+            let mut order = builder.account(id).order_id(id).build().unwrap();
+            order.peg = peg;
+            order_book.process_order(order).unwrap();
+        };
+
+        place(
+            &mut order_book,
+            OrderBuilder::buy("BTC/USDC", "1", "99"),
+            "bid",
+            None,
+        );
+        place(
+            &mut order_book,
+            OrderBuilder::sell("BTC/USDC", "1", "103"),
+            "ask",
+            None,
+        );
+        place(
+            &mut order_book,
+            OrderBuilder::buy("BTC/USDC", "1", "90"),
+            "mid",
+            Some(PegType::MidPeg),
+        );
+        place(
+            &mut order_book,
+            OrderBuilder::buy("BTC/USDC", "2", "95"),
+            "join",
+            Some(PegType::BidPeg),
+        );
+        // Pegging a buy to the ask would cross, so it stays put
+        place(
+            &mut order_book,
+            OrderBuilder::buy("BTC/USDC", "1", "80"),
+            "cross",
+            Some(PegType::AskPeg),
+        );
+
+        let mut moved = order_book.repeg_all();
+        moved.sort();
+        assert_eq!(
+            moved,
+            [
+                ("join".to_string(), Decimal::from(99)),
+                ("mid".to_string(), Decimal::from(101)),
+            ]
+        );
+        assert_eq!(order_book.get_best_bid(), Some(Decimal::from(101)));
+        let level: Vec<&str> = order_book.bids[&Decimal::from(99)]
+            .iter()
+            .map(|o| o.order_id.as_str())
+            .collect();
+        assert_eq!(level, ["bid", "join"]);
+        assert_eq!(
+            order_book.get_order_by_id("cross").unwrap().limit_price,
+            "80"
+        );
+
+        // Nothing moves until the reference prices do
+        assert!(order_book.repeg_all().is_empty());
+        place(
+            &mut order_book,
+            OrderBuilder::sell("BTC/USDC", "1", "102"),
+            "ask2",
+            None,
+        );
+        assert_eq!(
+            order_book.repeg_all(),
+            [("mid".to_string(), Decimal::from_str("100.5").unwrap())]
+        );
+    }
+
+    #[test]
+    fn test_halt_trading() {
+        let mut order_book = OrderBook::default();
+        let resting = OrderBuilder::sell("BTC/USDC", "1", "100")
+            .order_id("1")
+            .build()
+            .unwrap();
+        order_book.process_order(resting.clone()).unwrap();
+
+        order_book.halt_trading(HaltReason::Regulatory);
+        assert!(order_book.is_halted());
+        let (halted_at, reason) = order_book.halted_since().unwrap();
+        assert_eq!(reason, HaltReason::Regulatory);
+        order_book.halt_trading(HaltReason::ScheduledMaintenance);
+        assert_eq!(
+            order_book.halted_since(),
+            Some((halted_at, HaltReason::Regulatory))
+        );
+
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "100")
+            .order_id("2")
+            .build()
+            .unwrap();
+        assert_eq!(
+            order_book.process_order(buy.clone()).unwrap_err(),
+            OrderBookError::TradingHalted("2".to_string())
+        );
+        // Cancellations still go through
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..resting
+            })
+            .unwrap();
+        assert!(order_book.is_empty());
+
+        order_book.resume_trading();
+        assert!(!order_book.is_halted());
+        assert_eq!(order_book.halted_since(), None);
+        order_book.process_order(buy).unwrap();
+        assert_eq!(order_book.bid_count(), 1);
+    }
+
+    #[test]
+    fn test_opening_auction() {
+        let mut order_book = OrderBook::default();
+        order_book.set_auction_mode(AuctionMode::Opening);
+        for (order_id, side, amount, price) in [
+            ("b1", Side::Buy, "2", "101"),
+            ("b2", Side::Buy, "1", "100"),
+            ("a1", Side::Sell, "1", "99"),
+            ("a2", Side::Sell, "2", "100"),
+        ] {
+            let builder = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", amount, price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", amount, price),
+            };
+            let (fill, trades) = order_book
+                .process_order(builder.order_id(order_id).build().unwrap())
+                .unwrap();
+            assert!(trades.is_empty());
+            assert_eq!(fill.status, OrderStatus::Open);
+        }
+        // Crossed orders rest until the uncross
+        assert_eq!(order_book.bid_count(), 2);
+        assert_eq!(order_book.ask_count(), 2);
+        let mut market = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        market.order_type = OrderType::Market;
+        assert!(order_book.process_order(market).is_err());
+
+        // 100 executes 3, against 1 at 99 and 2 at 101
+        let result = order_book.run_auction();
+        assert_eq!(result.clearing_price, Some(Decimal::from(100)));
+        assert_eq!(result.volume, Decimal::from(3));
+        let fills: Vec<(&str, &str, &str)> = result
+            .trades
+            .iter()
+            .map(|t| {
+                (
+                    t.price.as_str(),
+                    t.amount.as_str(),
+                    t.maker_order_id.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fills,
+            [("100", "1", "b1"), ("100", "1", "b1"), ("100", "1", "b2")]
+        );
+        assert_eq!(order_book.trades.len(), 3);
+        assert!(order_book.is_empty());
+        assert_eq!(order_book.get_order_status("b1"), Some(OrderStatus::Filled));
+        assert_eq!(order_book.auction_mode(), AuctionMode::Continuous);
+
+        // Nothing crosses, so there is no clearing price
+        assert_eq!(order_book.run_auction().clearing_price, None);
+    }
+
+    #[test]
+    fn test_trailing_stop() {
+        let mut order_book = OrderBook::default();
+        let bid = OrderBuilder::buy("BTC/USDC", "5", "95")
+            .order_id("bid")
+            .build()
+            .unwrap();
+        order_book.process_order(bid).unwrap();
+
+        let mut stop = OrderBuilder::sell("BTC/USDC", "1", "1")
+            .order_id("stop")
+            .build()
+            .unwrap();
+        stop.trail_amount = Some(Decimal::from(5));
+        let (fill, trades) = order_book.process_order(stop.clone()).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(fill.status, OrderStatus::Open);
+        assert_eq!(order_book.ask_count(), 0);
+        assert_eq!(
+            order_book.process_order(stop).unwrap_err(),
+            OrderBookError::DuplicateOrderId("stop".to_string())
+        );
+
+        let mut pct_stop = OrderBuilder::sell("BTC/USDC", "1", "1")
+            .order_id("pct")
+            .build()
+            .unwrap();
+        pct_stop.trail_pct = Some(Decimal::from(10));
+        order_book.process_order(pct_stop.clone()).unwrap();
+
+        // The stop follows the price up but not back down
+        assert!(
+            order_book
+                .update_trailing_stops(Decimal::from(100))
+                .is_empty()
+        );
+        assert!(
+            order_book
+                .update_trailing_stops(Decimal::from(110))
+                .is_empty()
+        );
+        let stop_prices: Vec<Option<Decimal>> = order_book
+            .trailing_stop_orders()
+            .iter()
+            .map(|(_, spec)| spec.stop_price)
+            .collect();
+        assert_eq!(
+            stop_prices,
+            [Some(Decimal::from(105)), Some(Decimal::from(99))]
+        );
+
+        let trades = order_book.update_trailing_stops(Decimal::from(104));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_order_id, "stop");
+        assert_eq!(trades[0].price, "95");
+        assert_eq!(
+            order_book.get_order_status("stop"),
+            Some(OrderStatus::Filled)
+        );
+        assert_eq!(order_book.get_order_status("pct"), Some(OrderStatus::Open));
+
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..pct_stop
+            })
+            .unwrap();
+        assert!(order_book.trailing_stop_orders().is_empty());
+        assert_eq!(
+            order_book.get_order_status("pct"),
+            Some(OrderStatus::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_expected_fill_price() {
+        let mut order_book = OrderBook::default();
+        for (amount, price) in [("1", "100"), ("3", "102")] {
+            let ask = OrderBuilder::sell("BTC/USDC", amount, price)
+                .build()
+                .unwrap();
+            order_book.process_order(ask).unwrap();
+        }
+
+        // 1 at 100 and 1 at 102
+        assert_eq!(
+            order_book.expected_fill_price(Side::Buy, Decimal::from(2)),
+            Some(Decimal::from(101))
+        );
+        let buy = OrderBuilder::buy("BTC/USDC", "2", "102").build().unwrap();
+        assert_eq!(
+            order_book.simulate_order(&buy).average_fill_price,
+            Some(Decimal::from(101))
+        );
+        assert_eq!(
+            order_book.expected_fill_price(Side::Buy, Decimal::from(5)),
+            None
+        );
+        assert_eq!(
+            order_book.expected_fill_price(Side::Sell, Decimal::ONE),
+            None
+        );
+    }
+
+    #[test]
+    fn test_account_exposure_limit() {
+        let mut order_book = OrderBook::default();
+        order_book.set_account_limit("1", Decimal::from(1000));
+        let bid = OrderBuilder::buy("BTC/USDC", "6", "100")
+            .account("1")
+            .build()
+            .unwrap();
+        order_book.process_order(bid).unwrap();
+        assert_eq!(order_book.account_open_notional("1"), Decimal::from(600));
+
+        let over = OrderBuilder::sell("BTC/USDC", "5", "101")
+            .account("1")
+            .order_id("over")
+            .build()
+            .unwrap();
+        assert_eq!(
+            order_book.process_order(over).unwrap_err(),
+            OrderBookError::ExposureLimitExceeded("over".to_string())
+        );
+        assert_eq!(order_book.ask_count(), 0);
+
+        // Exactly at the limit is allowed, and other accounts aren't limited
+        let at_limit = OrderBuilder::sell("BTC/USDC", "2", "200")
+            .account("1")
+            .build()
+            .unwrap();
+        order_book.process_order(at_limit).unwrap();
+        assert_eq!(order_book.account_open_notional("1"), Decimal::from(1000));
+        let other = OrderBuilder::sell("BTC/USDC", "50", "101")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(other).unwrap();
+    }
+
+    #[test]
+    fn test_account_exposure_limit_on_amend_and_replace() {
+        let mut order_book = OrderBook::default();
+        order_book.set_account_limit("1", Decimal::from(150));
+        let bid = OrderBuilder::buy("BTC/USDC", "1", "100")
+            .account("1")
+            .order_id("bid")
+            .build()
+            .unwrap();
+        order_book.process_order(bid.clone()).unwrap();
+
+        let modify = Order {
+            type_op: Operation::Modify,
+            new_amount: Some("1000".to_string()),
+            ..bid.clone()
+        };
+        assert_eq!(
+            order_book.process_order(modify).unwrap_err(),
+            OrderBookError::ExposureLimitExceeded("bid".to_string())
+        );
+        let replace = Order {
+            type_op: Operation::CancelReplace,
+            amount: "5000".to_string(),
+            ..bid.clone()
+        };
+        assert_eq!(
+            order_book.process_order(replace).unwrap_err(),
+            OrderBookError::ExposureLimitExceeded("bid".to_string())
+        );
+        assert_eq!(order_book.account_open_notional("1"), Decimal::from(100));
+
+        // The order's own exposure is netted out, so growing it up to the limit is allowed
+        let modify = Order {
+            type_op: Operation::Modify,
+            new_amount: Some("1.5".to_string()),
+            ..bid.clone()
+        };
+        order_book.process_order(modify).unwrap();
+        let replace = Order {
+            type_op: Operation::CancelReplace,
+            amount: "1".to_string(),
+            limit_price: "150".to_string(),
+            ..bid
+        };
+        order_book.process_order(replace).unwrap();
+        assert_eq!(order_book.account_open_notional("1"), Decimal::from(150));
+    }
+
+    #[test]
+    fn test_account_rate_limit() {
+        let mut order_book = OrderBook::default();
+        order_book.set_account_rate_limit("1", 2);
+        let order = |order_id: &str, account_id: &str| {
+            OrderBuilder::buy("BTC/USDC", "1", "100")
+                .account(account_id)
+                .order_id(order_id)
+                .build()
+                .unwrap()
+        };
+
+        order_book.process_order(order("1", "1")).unwrap();
+        let second = order("2", "1");
+        order_book.process_order(second.clone()).unwrap();
+        assert_eq!(
+            order_book.process_order(order("3", "1")).unwrap_err(),
+            OrderBookError::RateLimitExceeded("3".to_string())
+        );
+        assert!(matches!(
+            order_book.get_order_status("3"),
+            Some(OrderStatus::Rejected(OrderBookError::RateLimitExceeded(_)))
+        ));
+
+        // Other accounts and cancellations aren't affected
+        order_book.process_order(order("4", "2")).unwrap();
+        order_book
+            .process_order(Order {
+                type_op: Operation::Delete,
+                ..second
+            })
+            .unwrap();
+        assert_eq!(order_book.bid_count(), 2);
+    }
+
+    #[test]
+    fn test_order_size_limits() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            min_order_size: Some(Decimal::ONE),
+            max_order_size: Some(Decimal::from(10)),
+            ..OrderBookConfig::default()
+        })
+        .unwrap();
+        let order = |amount: &str| {
+            OrderBuilder::buy("BTC/USDC", amount, "100")
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(
+            order_book.process_order(order("0.5")).unwrap_err(),
+            OrderBookError::OrderTooSmall {
+                minimum: Decimal::ONE,
+                submitted: Decimal::from_str("0.5").unwrap(),
+            }
+        );
+        assert_eq!(
+            order_book.process_order(order("11")).unwrap_err(),
+            OrderBookError::OrderTooLarge {
+                maximum: Decimal::from(10),
+                submitted: Decimal::from(11),
+            }
+        );
+        order_book.process_order(order("1")).unwrap();
+        let resting = order("10");
+        order_book.process_order(resting.clone()).unwrap();
+        assert_eq!(order_book.bid_count(), 2);
+
+        // Amendments are held to the same bounds
+        let modify = Order {
+            type_op: Operation::Modify,
+            new_amount: Some("20".to_string()),
+            ..resting
+        };
+        assert!(matches!(
+            order_book.process_order(modify).unwrap_err(),
+            OrderBookError::OrderTooLarge { .. }
+        ));
+    }
+
+    #[test]
+    fn test_get_position() {
+        let mut order_book = OrderBook::default();
+        for price in ["100", "110"] {
+            let sell = OrderBuilder::sell("BTC/USDC", "1", price)
+                .account("2")
+                .build()
+                .unwrap();
+            order_book.process_order(sell).unwrap();
+            let buy = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .build()
+                .unwrap();
+            order_book.process_order(buy).unwrap();
+        }
+
+        let long = order_book.get_position("1", "BTC/USDC").unwrap();
+        assert_eq!(long.quantity, Decimal::from(2));
+        assert_eq!(long.avg_entry_price, Decimal::from(105));
+        let short = order_book.get_position("2", "BTC/USDC").unwrap();
+        assert_eq!(short.quantity, Decimal::from(-2));
+        assert_eq!(short.avg_entry_price, Decimal::from(105));
+
+        assert!(order_book.get_position("1", "ETH/USDC").is_none());
+        assert!(order_book.get_position("3", "BTC/USDC").is_none());
+    }
+
+    #[test]
+    fn test_unrealized_pnl() {
+        let mut order_book = OrderBook::default();
+        for price in ["100", "110"] {
+            let sell = OrderBuilder::sell("BTC/USDC", "1", price)
+                .account("2")
+                .build()
+                .unwrap();
+            order_book.process_order(sell).unwrap();
+            let buy = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .build()
+                .unwrap();
+            order_book.process_order(buy).unwrap();
+        }
+
+        // Long 2 and short 2, both entered at 105
+        let mark = Decimal::from(120);
+        assert_eq!(
+            order_book.unrealized_pnl("1", "BTC/USDC", mark),
+            Some(Decimal::from(30))
+        );
+        assert_eq!(
+            order_book.unrealized_pnl("2", "BTC/USDC", mark),
+            Some(Decimal::from(-30))
+        );
+        assert_eq!(order_book.unrealized_pnl("3", "BTC/USDC", mark), None);
+
+        let prices = HashMap::from([("BTC/USDC".to_string(), Decimal::from(100))]);
+        let pnl = order_book.all_account_pnl(&prices);
+        assert_eq!(pnl.len(), 2);
+        assert_eq!(pnl["1"], Decimal::from(-10));
+        assert_eq!(pnl["2"], Decimal::from(10));
+        assert!(order_book.all_account_pnl(&HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_liquidity_at_price_impact() {
+        let mut order_book = OrderBook::default();
+        for (amount, price) in [("1", "200"), ("2", "201"), ("4", "202")] {
+            let ask = OrderBuilder::sell("BTC/USDC", amount, price)
+                .build()
+                .unwrap();
+            order_book.process_order(ask).unwrap();
+        }
+        for (amount, price) in [("1", "100"), ("3", "99")] {
+            let bid = OrderBuilder::buy("BTC/USDC", amount, price)
+                .build()
+                .unwrap();
+            order_book.process_order(bid).unwrap();
+        }
+
+        let impact = Decimal::from_str("0.005").unwrap();
+        // Up to 201, 0.5% above the best ask
+        assert_eq!(
+            order_book.liquidity_at_price_impact(Side::Buy, impact),
+            Decimal::from(3)
+        );
+        // Down to 99.5 only reaches the best bid
+        assert_eq!(
+            order_book.liquidity_at_price_impact(Side::Sell, impact),
+            Decimal::ONE
+        );
+        assert_eq!(
+            order_book.liquidity_at_price_impact(Side::Sell, Decimal::from_str("0.01").unwrap()),
+            Decimal::from(4)
+        );
+        assert_eq!(
+            OrderBook::default().liquidity_at_price_impact(Side::Buy, impact),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_compute_checksum() {
+        let orders = [
+            OrderBuilder::buy("BTC/USDC", "1", "99")
+                .order_id("1")
+                .build()
+                .unwrap(),
+            OrderBuilder::buy("BTC/USDC", "2", "99")
+                .order_id("2")
+                .build()
+                .unwrap(),
+            OrderBuilder::sell("BTC/USDC", "1.5", "101")
+                .order_id("3")
+                .build()
+                .unwrap(),
+        ];
+        let mut order_book = OrderBook::default();
+        for order in orders.iter().cloned() {
+            order_book.process_order(order).unwrap();
+        }
+        // Same orders, arriving in a different order and with trailing zeros
+        let mut replica = OrderBook::default();
+        for mut order in orders.iter().rev().cloned() {
+            order.amount = format!("{:.2}", Decimal::from_str(&order.amount).unwrap());
+            replica.process_order(order).unwrap();
+        }
+
+        let checksum = order_book.compute_checksum();
+        assert_eq!(replica.compute_checksum(), checksum);
+        assert_eq!(order_book.snapshot().checksum(), checksum);
+        assert_ne!(OrderBook::default().compute_checksum(), checksum);
+
+        order_book
+            .process_order(Order {
+                type_op: Operation::Modify,
+                new_amount: Some("0.5".to_string()),
+                ..orders[1].clone()
+            })
+            .unwrap();
+        assert_ne!(order_book.compute_checksum(), checksum);
+    }
+
+    #[test]
+    fn test_rolling_window_stats() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(
+            order_book.rolling_window_stats(60_000),
+            RollingStats::default()
+        );
+
+        // A buyer takes 1 @ 100, then a seller hits 3 @ 96
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(ask).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(buy).unwrap();
+        let bid = OrderBuilder::buy("BTC/USDC", "3", "96").build().unwrap();
+        order_book.process_order(bid).unwrap();
+        let sell = OrderBuilder::sell("BTC/USDC", "3", "96").build().unwrap();
+        order_book.process_order(sell).unwrap();
+
+        let stats = order_book.rolling_window_stats(60_000);
+        assert_eq!(stats.trade_count, 2);
+        assert_eq!(stats.total_volume, Decimal::from(4));
+        assert_eq!(stats.avg_price, Decimal::from(97));
+        assert_eq!(stats.price_stddev, Decimal::from(2));
+        assert_eq!(stats.buy_volume, Decimal::ONE);
+        assert_eq!(stats.sell_volume, Decimal::from(3));
+
+        // Trades older than the window are left out
+        order_book.trades[0].timestamp -= 120_000 * 1_000_000;
+        let stats = order_book.rolling_window_stats(60_000);
+        assert_eq!(stats.trade_count, 1);
+        assert_eq!(stats.price_stddev, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_order_arrival_rate() {
+        let mut order_book = OrderBook::default();
+        for (account_id, price) in [("1", "100"), ("1", "101"), ("2", "102")] {
+            let order = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account(account_id)
+                .build()
+                .unwrap();
+            order_book.process_order(order).unwrap();
+        }
+        // Rejected orders still arrived
+        let mut rejected = OrderBuilder::buy("BTC/USDC", "1", "1")
+            .account("2")
+            .build()
+            .unwrap();
+        rejected.amount = "0".to_string();
+        assert!(order_book.process_order(rejected).is_err());
+
+        assert_eq!(order_book.order_arrival_rate(10_000), 0.4);
+        assert_eq!(order_book.order_arrival_rate(2_000), 2.0);
+        assert_eq!(order_book.account_order_arrival_rate("1", 10_000), 0.2);
+        assert_eq!(order_book.account_order_arrival_rate("2", 10_000), 0.2);
+        assert_eq!(order_book.account_order_arrival_rate("3", 10_000), 0.0);
+        assert_eq!(order_book.order_arrival_rate(0), 0.0);
+    }
+
+    #[test]
+    fn test_cancel_rate() {
+        let mut order_book = OrderBook::default();
+        let mut orders = Vec::new();
+        for price in ["100", "101", "102", "103"] {
+            let order = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .build()
+                .unwrap();
+            order_book.process_order(order.clone()).unwrap();
+            orders.push(order);
+        }
+        let other = OrderBuilder::sell("BTC/USDC", "1", "110")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(other).unwrap();
+
+        for order in orders.drain(..3) {
+            order_book
+                .process_order(Order {
+                    type_op: Operation::Delete,
+                    ..order
+                })
+                .unwrap();
+        }
+        assert_eq!(order_book.cancel_rate("1", 60_000), 0.75);
+        order_book.cancel_all_orders_for_account("1");
+        assert_eq!(order_book.cancel_rate("1", 60_000), 1.0);
+        assert_eq!(order_book.cancel_rate("2", 60_000), 0.0);
+        assert_eq!(order_book.cancel_rate("3", 60_000), 0.0);
+
+        let rates = order_book.all_account_cancel_rates(60_000);
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates["1"], 1.0);
+        assert_eq!(rates["2"], 0.0);
+    }
+
+    #[test]
+    fn test_fill_rate() {
+        let mut order_book = OrderBook::default();
+        let ask = OrderBuilder::sell("BTC/USDC", "3", "100")
+            .account("1")
+            .build()
+            .unwrap();
+        order_book.process_order(ask).unwrap();
+        let bid = OrderBuilder::buy("BTC/USDC", "4", "100")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(bid).unwrap();
+
+        // The maker filled all 3, the taker 3 of its 4
+        assert_eq!(order_book.fill_rate("1", 60_000), 1.0);
+        assert_eq!(order_book.fill_rate("2", 60_000), 0.75);
+        assert_eq!(order_book.fill_rate("3", 60_000), 0.0);
+    }
+
+    #[test]
+    fn test_fix44_execution_report_and_new_order_single() {
+        let mut order_book = OrderBook::default();
+        let ask = OrderBuilder::sell("BTC/USDC", "2", "100")
+            .order_id("ask")
+            .build()
+            .unwrap();
+        order_book.process_order(ask).unwrap();
+        let message = "8=FIX.4.4\x0135=D\x0111=bid\x011=7\x0155=BTC/USDC\x0154=1\x0138=2\x0140=2\x0144=100\x01";
+        let bid = parse_fix44_new_order_single(message).unwrap();
+        assert_eq!(bid.account_id, "7");
+        assert_eq!(bid.side, Side::Buy);
+        assert_eq!(bid.order_type, OrderType::Limit);
+        let (_, mut trades) = order_book.process_order(bid).unwrap();
+
+        trades[0].timestamp = 1_700_000_000_123 * 1_000_000;
+        let report = order_book.trade_to_fix44(&trades[0]).unwrap();
+        let fields: Vec<&str> = report.trim_end_matches('\x01').split('\x01').collect();
+        assert_eq!(fields[0], "8=FIX.4.4");
+        assert_eq!(fields[2], "35=8");
+        for field in [
+            "37=bid",
+            &format!("1003={}", trades[0].trade_id),
+            "150=F",
+            "39=2",
+            "54=1",
+            "44=100",
+            "32=2",
+            "31=100",
+            "60=20231114-22:13:20.123",
+        ] {
+            assert!(
+                fields.contains(&field),
+                "{} missing from {:?}",
+                field,
+                fields
+            );
+        }
+        let body_start = report.find("35=").unwrap();
+        let trailer = report.rfind("10=").unwrap();
+        assert_eq!(fields[1], format!("9={}", trailer - body_start));
+        let checksum = report[..trailer].bytes().map(u32::from).sum::<u32>() % 256;
+        assert_eq!(fields.last().unwrap(), &format!("10={:03}", checksum));
+
+        // An older trade without the taker's side between orders the book never saw
+        let unknown = Trade {
+            taker_order_id: "gone".to_string(),
+            maker_order_id: "also gone".to_string(),
+            taker_side: None,
+            ..trades[0].clone()
+        };
+        assert_eq!(order_book.trade_to_fix44(&unknown), None);
+
+        assert_eq!(
+            parse_fix44_new_order_single("11=x\x0155=BTC/USDC\x0154=3\x0138=1\x0140=1\x01")
+                .unwrap_err(),
+            FixParseError::InvalidField(54, "3".to_string())
+        );
+        assert_eq!(
+            parse_fix44_new_order_single("11=x\x0155=BTC/USDC\x0154=1\x0138=1\x0140=2\x01")
+                .unwrap_err(),
+            FixParseError::MissingField(44)
+        );
+    }
+
+    #[test]
+    fn test_parse_fix44_new_order() {
+        let order =
+            parse_fix44_new_order("8=FIX.4.4|35=D|11=42|1=acct|55=ETH/USDC|54=2|38=1.5|40=1|")
+                .unwrap();
+        assert_eq!(order.order_id, "42");
+        assert_eq!(order.account_id, "acct");
+        assert_eq!(order.pair.to_string(), "ETH/USDC");
+        assert_eq!(order.side, Side::Sell);
+        assert_eq!(order.amount, "1.5");
+        assert_eq!(order.order_type, OrderType::Market);
+        assert_eq!(order.type_op, Operation::Create);
+
+        let limit = parse_fix44_new_order("11=43|55=ETH/USDC|54=1|38=2|40=2|44=1800.5").unwrap();
+        assert_eq!(limit.order_type, OrderType::Limit);
+        assert_eq!(limit.limit_price, "1800.5");
+        assert_eq!(limit.account_id, "");
+
+        for (message, error) in [
+            (
+                "55=ETH/USDC|54=1|38=2|40=1",
+                FixParseError::MissingField(11),
+            ),
+            (
+                "11=1|55=ETH/USDC|54=1|38=-2|40=1",
+                FixParseError::InvalidField(38, "-2".to_string()),
+            ),
+            (
+                "11=1|55=ETH/USDC|54=1|38=2|40=3",
+                FixParseError::InvalidField(40, "3".to_string()),
+            ),
+            (
+                "11=1|55=ETHUSDC|54=1|38=2|40=1",
+                FixParseError::InvalidField(55, "ETHUSDC".to_string()),
+            ),
+            (
+                "35=F|11=1|55=ETH/USDC|54=1|38=2|40=1",
+                FixParseError::InvalidField(35, "F".to_string()),
+            ),
+            (
+                "11=1|garbage",
+                FixParseError::Malformed("garbage".to_string()),
+            ),
+        ] {
+            assert_eq!(parse_fix44_new_order(message).unwrap_err(), error);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_order_book() {
+        use futures_util::StreamExt;
+        use trading_engine::AsyncOrderBook;
+
+        let order_book = AsyncOrderBook::default();
+        let ask = OrderBuilder::sell("BTC/USDC", "3", "100").build().unwrap();
+        assert!(order_book.process_order(ask).await.unwrap().is_empty());
+
+        // Tasks share the book through clones
+        let task_book = order_book.clone();
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        let trades = tokio::spawn(async move { task_book.process_order(buy).await })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+
+        let mut invalid = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        invalid.amount = "0".to_string();
+        let orders = futures_util::stream::iter([
+            OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap(),
+            invalid,
+            OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap(),
+        ]);
+        let trade_counts: Vec<usize> = order_book
+            .process_order_batch(orders)
+            .map(|trades| trades.len())
+            .collect()
+            .await;
+        assert_eq!(trade_counts, [1, 0, 1]);
+
+        let snapshot = order_book.snapshot().await;
+        assert!(snapshot.orders.is_empty());
+        assert_eq!(snapshot.trades.len(), 3);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_order_book_subscribe() {
+        use trading_engine::AsyncOrderBook;
+
+        let order_book = AsyncOrderBook::default();
+        let mut first = order_book.subscribe();
+        let mut second = order_book.subscribe();
+
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "100")
+            .order_id("ask")
+            .build()
+            .unwrap();
+        order_book.process_order(ask).await.unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(buy).await.unwrap();
+
+        // Every subscriber sees every event, in processing order
+        for receiver in [&mut first, &mut second] {
+            let mut events = Vec::new();
+            while let Ok(event) = receiver.try_recv() {
+                events.push(event);
+            }
+            assert_eq!(events.len(), 5);
+            assert!(
+                matches!(&events[0], OrderBookEvent::OrderAdded(entry) if entry.order_id == "ask")
+            );
+            assert!(matches!(
+                &events[1],
+                OrderBookEvent::DepthChanged(Side::Sell, _, _)
+            ));
+            assert!(matches!(&events[2], OrderBookEvent::OrderRemoved(id) if id == "ask"));
+            assert!(matches!(&events[3], OrderBookEvent::TradeExecuted(_)));
+            assert!(matches!(
+                &events[4],
+                OrderBookEvent::DepthChanged(Side::Sell, _, amount) if amount.is_zero()
+            ));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_order_book_actor() {
+        use trading_engine::{ActorError, spawn_actor};
+
+        let (handle, task) = spawn_actor(OrderBookConfig::default()).unwrap();
+        let ask = OrderBuilder::sell("BTC/USDC", "2", "100").build().unwrap();
+        assert!(handle.submit(ask).await.unwrap().is_empty());
+
+        // Submissions from other tasks go through the same book
+        let buyers: Vec<_> = (0..2)
+            .map(|_| {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let buy = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+                    handle.submit(buy).await
+                })
+            })
+            .collect();
+        for buyer in buyers {
+            assert_eq!(buyer.await.unwrap().unwrap().len(), 1);
+        }
+
+        let mut invalid = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        invalid.amount = "0".to_string();
+        assert!(handle.submit(invalid).await.is_err());
+
+        let snapshot = handle.snapshot().await.unwrap();
+        assert!(snapshot.orders.is_empty());
+        assert_eq!(snapshot.trades.len(), 2);
+
+        // The actor stops when the last handle goes away
+        drop(handle);
+        task.await.unwrap();
+
+        // Requests to an actor that has stopped fail rather than panic
+        let (handle, task) = spawn_actor(OrderBookConfig::default()).unwrap();
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "100").build().unwrap();
+        assert_eq!(handle.submit(ask).await.unwrap_err(), ActorError::Stopped);
+        assert_eq!(handle.snapshot().await.unwrap_err(), ActorError::Stopped);
+    }
+
+    #[test]
+    fn test_positions_from_trades() {
+        let mut order_book = OrderBook::default();
+        for price in ["100", "110"] {
+            let sell = OrderBuilder::sell("BTC/USDC", "1", price)
+                .account("2")
+                .build()
+                .unwrap();
+            order_book.process_order(sell).unwrap();
+            let buy = OrderBuilder::buy("BTC/USDC", "1", price)
+                .account("1")
+                .build()
+                .unwrap();
+            order_book.process_order(buy).unwrap();
+        }
+        let bid = OrderBuilder::buy("BTC/USDC", "1", "105")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(bid).unwrap();
+        let sell = OrderBuilder::sell("BTC/USDC", "1", "105")
+            .account("1")
+            .build()
+            .unwrap();
+        order_book.process_order(sell).unwrap();
+        // A buy on a downtick, which the tick rule calls seller-initiated
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "104")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(ask).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "104")
+            .account("1")
+            .build()
+            .unwrap();
+        let (_, trades) = order_book.process_order(buy).unwrap();
+        assert_eq!(trades[0].aggressor, TradeSide::Seller);
+        assert_eq!(trades[0].taker_side, Some(Side::Buy));
+
+        // Out of order trades are replayed by timestamp
+        let mut trades = order_book.trades.clone();
+        trades.reverse();
+        for account_id in ["1", "2"] {
+            let positions = positions_from_trades(&trades, account_id);
+            assert_eq!(positions.len(), 1);
+            assert_eq!(
+                positions.get("BTC/USDC"),
+                order_book.get_position(account_id, "BTC/USDC")
+            );
+        }
+        let long = &positions_from_trades(&trades, "1")["BTC/USDC"];
+        assert_eq!(long.quantity, Decimal::from(2));
+        assert_eq!(long.realized_pnl, Decimal::from(5));
+
+        assert!(positions_from_trades(&trades, "3").is_empty());
+        // Trades from before taker sides were recorded can't be attributed
+        let legacy: Vec<Trade> = trades
+            .into_iter()
+            .map(|trade| Trade {
+                taker_side: None,
+                ..trade
+            })
+            .collect();
+        assert!(positions_from_trades(&legacy, "1").is_empty());
+    }
+
+    #[test]
+    fn test_trade_gross_and_net_value() {
+        let mut order_book = OrderBook::default();
+        let sell = OrderBuilder::sell("BTC/USDC", "0.5", "50000")
+            .build()
+            .unwrap();
+        order_book.process_order(sell).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "0.5", "50000")
+            .build()
+            .unwrap();
+        let (_, trades) = order_book.process_order(buy).unwrap();
+
+        let trade = &trades[0];
+        assert_eq!(trade.gross_value().unwrap(), Decimal::from(25000));
+        assert_eq!(
+            trade
+                .net_value(Decimal::from_str("0.001").unwrap())
+                .unwrap(),
+            Decimal::from(24975)
+        );
+        assert_eq!(
+            trade.net_value(Decimal::ZERO).unwrap(),
+            Decimal::from(25000)
+        );
+
+        let unparseable = Trade {
+            price: "n/a".to_string(),
+            ..trade.clone()
+        };
+        assert!(unparseable.gross_value().is_err());
+        assert!(unparseable.net_value(Decimal::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_order_notional_value_and_display() {
+        let order = OrderBuilder::buy("BTC/USDC", "1.5", "100")
+            .account("2")
+            .order_id("7")
+            .build()
+            .unwrap();
+        assert_eq!(order.notional_value().unwrap(), Decimal::from(150));
+        assert_eq!(
+            order.to_string(),
+            "CREATE BUY 1.5 BTC/USDC @ 100 (order 7, account 2)"
+        );
+
+        let market = Order {
+            order_type: OrderType::Market,
+            limit_price: String::new(),
+            ..order
+        };
+        assert!(market.notional_value().is_err());
+        assert_eq!(
+            market.to_string(),
+            "CREATE BUY 1.5 BTC/USDC @ MARKET (order 7, account 2)"
+        );
+    }
+
+    #[test]
+    fn test_price_and_amount_precision() {
+        let mut order_book = OrderBook::default();
+        order_book.set_price_precision(2);
+        order_book.set_amount_precision(4);
+
+        let sell = OrderBuilder::sell("BTC/USDC", "1.5", "50000")
+            .build()
+            .unwrap();
+        order_book.process_order(sell).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "0.12345", "50000.00")
+            .build()
+            .unwrap();
+        let (_, trades) = order_book.process_order(buy).unwrap();
+        assert_eq!(trades[0].price, "50000");
+        assert_eq!(trades[0].amount, "0.12345");
+        let formatted = order_book.format_trade(&trades[0]);
+        assert_eq!(formatted.price, "50000.00");
+        assert_eq!(formatted.amount, "0.1234");
+
+        let output = order_book.generate_order_book_output();
+        assert_eq!(output[0].price, "50000.00");
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].amount, "1.3766");
+
+        // Without a precision values are written as calculated
+        assert_eq!(OrderBook::default().config().price_precision, None);
+
+        // Rounding only applies to output, so fills, volume and positions stay exact
+        let mut order_book = OrderBook::default();
+        order_book.set_price_precision(0);
+        order_book.set_amount_precision(0);
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+        let sell = OrderBuilder::sell("BTC/USDC", "1.4", "100.4")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(sell).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "1.4", "100.4")
+            .account("1")
+            .build()
+            .unwrap();
+        let (fill, trades) = order_book.process_order(buy).unwrap();
+        assert_eq!(fill.status, OrderStatus::Filled);
+        assert_eq!(fill.remaining_amount, Decimal::ZERO);
+        assert_eq!(
+            (trades[0].price.as_str(), trades[0].amount.as_str()),
+            ("100.4", "1.4")
+        );
+        assert_eq!(
+            order_book.session_volume(),
+            Decimal::from_str("1.4").unwrap()
+        );
+        let mut executed = None;
+        while let Ok(event) = receiver.try_recv() {
+            if let OrderBookEvent::TradeExecuted(trade) = event {
+                executed = Some((trade.price, trade.amount));
+            }
+        }
+        assert_eq!(executed, Some(("100".to_string(), "1".to_string())));
+    }
+
+    #[test]
+    fn test_volume_profile() {
+        let mut order_book = OrderBook::default();
+        // Up, up, down and unchanged by the tick rule
+        for (price, amount) in [("100", "1"), ("100.5", "2"), ("100", "3"), ("100", "4")] {
+            let sell = OrderBuilder::sell("BTC/USDC", amount, price)
+                .build()
+                .unwrap();
+            order_book.process_order(sell).unwrap();
+            let buy = OrderBuilder::buy("BTC/USDC", amount, price)
+                .build()
+                .unwrap();
+            order_book.process_order(buy).unwrap();
+        }
+
+        let profile = order_book.volume_profile();
+        assert_eq!(profile.len(), 2);
+        assert_eq!(
+            profile[&Decimal::from(100)],
+            VolumeProfileLevel {
+                buy_volume: Decimal::ONE,
+                sell_volume: Decimal::from(7),
+                trade_count: 3,
+            }
+        );
+        assert_eq!(
+            profile[&Decimal::from_str("100.5").unwrap()],
+            VolumeProfileLevel {
+                buy_volume: Decimal::from(2),
+                sell_volume: Decimal::ZERO,
+                trade_count: 1,
+            }
+        );
+
+        let bucketed = order_book.volume_profile_bucketed(Decimal::ONE);
+        assert_eq!(bucketed.len(), 1);
+        assert_eq!(bucketed[&Decimal::from(100)].trade_count, 4);
+        assert_eq!(bucketed[&Decimal::from(100)].buy_volume, Decimal::from(3));
+    }
+
+    #[test]
+    fn test_tick_direction() {
+        let mut order_book = OrderBook::default();
+        let mut directions = Vec::new();
+        for price in ["100", "101", "101", "100", "100", "100.5"] {
+            let sell = OrderBuilder::sell("BTC/USDC", "1", price).build().unwrap();
+            order_book.process_order(sell).unwrap();
+            let buy = OrderBuilder::buy("BTC/USDC", "1", price).build().unwrap();
+            let (_, trades) = order_book.process_order(buy).unwrap();
+            directions.push(trades[0].tick_direction);
+        }
+
+        // The first trade has nothing to compare against and follows its taker
+        assert_eq!(
+            directions,
+            [
+                TickDirection::ZeroUpTick,
+                TickDirection::UpTick,
+                TickDirection::ZeroUpTick,
+                TickDirection::DownTick,
+                TickDirection::ZeroDownTick,
+                TickDirection::UpTick,
+            ]
+        );
+        assert_eq!(
+            order_book.trades.last().unwrap().tick_direction,
+            TickDirection::UpTick
+        );
+    }
+
+    #[test]
+    fn test_order_book_pressure() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(order_book.order_book_pressure(5), 0.0);
+
+        for (side, price, amount) in [
+            (Side::Buy, "100", "3"),
+            (Side::Buy, "99", "4"),
+            (Side::Sell, "101", "1"),
+            (Side::Sell, "103", "2"),
+        ] {
+            let order = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", amount, price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", amount, price),
+            };
+            order_book.process_order(order.build().unwrap()).unwrap();
+        }
+
+        // Mid 100.5: bids weigh 3/0.5 + 4/1.5, asks 1/0.5 + 2/2.5
+        assert_eq!(order_book.order_book_pressure(1), 0.5);
+        let pressure = order_book.order_book_pressure(5);
+        assert!((pressure - 5.8666 / 11.4666).abs() < 1e-4);
+
+        // Depth far from the mid counts for less than the same depth at the touch
+        let imbalance = order_book.order_book_imbalance(5).unwrap();
+        assert!(pressure > imbalance);
+
+        // A crossed auction book with a bid at the mid leaves that level out
+        let mut order_book = OrderBook::default();
+        order_book.set_auction_mode(AuctionMode::Opening);
+        for (side, price, amount) in [
+            (Side::Buy, "101", "2"),
+            (Side::Buy, "100", "5"),
+            (Side::Sell, "99", "1"),
+        ] {
+            let order = match side {
+                Side::Buy => OrderBuilder::buy("BTC/USDC", amount, price),
+                Side::Sell => OrderBuilder::sell("BTC/USDC", amount, price),
+            };
+            order_book.process_order(order.build().unwrap()).unwrap();
+        }
+        let pressure = order_book.order_book_pressure(5);
+        assert!((pressure - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mark_price() {
+        let mut order_book = OrderBook::default();
+        let sell = OrderBuilder::sell("BTC/USDC", "2", "100")
+            .account("2")
+            .build()
+            .unwrap();
+        order_book.process_order(sell).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "2", "100")
+            .account("1")
+            .build()
+            .unwrap();
+        order_book.process_order(buy).unwrap();
+
+        assert_eq!(order_book.get_mark_price("BTC/USDC"), None);
+        assert_eq!(order_book.unrealized_pnl_at_mark("1", "BTC/USDC"), None);
+        assert!(order_book.all_account_pnl_at_mark().is_empty());
+
+        // P&L follows the mark, not the last trade at 100
+        order_book.set_mark_price("BTC/USDC", Decimal::from(95));
+        assert_eq!(
+            order_book.get_mark_price("BTC/USDC"),
+            Some(Decimal::from(95))
+        );
+        assert_eq!(order_book.last_trade_price(), Some(Decimal::from(100)));
+        assert_eq!(
+            order_book.unrealized_pnl_at_mark("1", "BTC/USDC"),
+            Some(Decimal::from(-10))
+        );
+        let pnl = order_book.all_account_pnl_at_mark();
+        assert_eq!(pnl["1"], Decimal::from(-10));
+        assert_eq!(pnl["2"], Decimal::from(10));
+        assert_eq!(order_book.unrealized_pnl_at_mark("1", "ETH/USDC"), None);
+    }
+
+    #[test]
+    fn test_is_marketable() {
+        let mut order_book = OrderBook::default();
+        let market_buy = Order {
+            order_type: OrderType::Market,
+            limit_price: String::new(),
+            ..OrderBuilder::buy("BTC/USDC", "1", "1").build().unwrap()
+        };
+        assert!(!market_buy.is_marketable(&order_book));
+
+        for order in [
+            OrderBuilder::buy("BTC/USDC", "1", "99"),
+            OrderBuilder::sell("BTC/USDC", "1", "101"),
+        ] {
+            order_book.process_order(order.build().unwrap()).unwrap();
+        }
+
+        let buy = |price| OrderBuilder::buy("BTC/USDC", "1", price).build().unwrap();
+        let sell = |price| OrderBuilder::sell("BTC/USDC", "1", price).build().unwrap();
+        assert!(buy("101").is_marketable(&order_book));
+        assert!(buy("102").is_marketable(&order_book));
+        assert!(!buy("100").is_marketable(&order_book));
+        assert!(sell("99").is_marketable(&order_book));
+        assert!(!sell("100").is_marketable(&order_book));
+        assert!(market_buy.is_marketable(&order_book));
+
+        // Checking leaves the book as it was
+        assert_eq!(order_book.total_order_count(), 2);
+        assert!(order_book.trades.is_empty());
+    }
+
+    #[test]
+    fn test_fill_simulator() {
+        let mut clock = SimulatedClock::new(1_000);
+        clock.advance_to(500);
+        assert_eq!(clock.now_ms(), 1_000);
+
+        let mut simulator = FillSimulator::default();
+        let expiring = Order {
+            expires_at_ms: Some(2_000),
+            ..OrderBuilder::sell("BTC/USDC", "2", "100")
+                .order_id("expiring")
+                .build()
+                .unwrap()
+        };
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "101")
+            .order_id("ask")
+            .build()
+            .unwrap();
+        let buy = |order_id: &str| {
+            OrderBuilder::buy("BTC/USDC", "1", "101")
+                .order_id(order_id)
+                .build()
+                .unwrap()
+        };
+
+        // Orders are replayed by timestamp, so the 101 ask rests before the first buy
+        let trades =
+            simulator.fast_forward(vec![(1_500, buy("early")), (1_000, expiring), (1_200, ask)]);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, "expiring");
+        assert_eq!(trades[0].timestamp, 1_500 * 1_000_000);
+        assert_eq!(simulator.clock().now_ms(), 1_500);
+
+        // By the next simulated time the rest of the 100 ask has expired
+        let trades = simulator.step(buy("late"), 3_000);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, "ask");
+        assert_eq!(trades[0].timestamp, 3_000 * 1_000_000);
+        assert!(simulator.book().is_empty());
+    }
+
+    #[test]
+    fn test_fok_skips_self_trade_makers() {
+        let mut order_book = OrderBook::new(OrderBookConfig {
+            self_trade_prevention: SelfTradePrevention::CancelMaker,
+            ..OrderBookConfig::default()
+        })
+        .unwrap();
+        for (account_id, order_id) in [("1", "own"), ("2", "other")] {
+            let ask = OrderBuilder::sell("BTC/USDC", "1", "100")
+                .account(account_id)
+                .order_id(order_id)
+                .build()
+                .unwrap();
+            order_book.process_order(ask).unwrap();
+        }
+
+        // Only account 2's ask can fill account 1's buy, so a FOK for 2 is killed whole
+        let buy = OrderBuilder::buy("BTC/USDC", "2", "100")
+            .account("1")
+            .order_id("fok")
+            .time_in_force(TimeInForce::Fok)
+            .build()
+            .unwrap();
+        let (fill, trades) = order_book.process_order(buy).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(fill.filled_amount, Decimal::ZERO);
+        assert_eq!(fill.status, OrderStatus::Cancelled);
+        assert_eq!(order_book.total_order_count(), 2);
+    }
+
+    #[test]
+    fn test_fok_skips_expired_makers() {
+        let mut simulator = FillSimulator::default();
+        let expiring = Order {
+            expires_at_ms: Some(2_000),
+            ..OrderBuilder::sell("BTC/USDC", "1", "100")
+                .order_id("expiring")
+                .build()
+                .unwrap()
+        };
+        simulator.step(expiring, 1_000);
+        let ask = OrderBuilder::sell("BTC/USDC", "1", "100")
+            .order_id("ask")
+            .build()
+            .unwrap();
+        simulator.step(ask, 1_000);
+
+        let buy = OrderBuilder::buy("BTC/USDC", "2", "100")
+            .time_in_force(TimeInForce::Fok)
+            .build()
+            .unwrap();
+        assert!(simulator.step(buy, 3_000).is_empty());
+        assert!(simulator.book().get_order_by_id("ask").is_some());
+    }
+
+    #[test]
+    fn test_fok_stops_at_circuit_breaker() {
+        let mut order_book = OrderBook::default();
+        order_book.set_circuit_breaker(Some(CircuitBreaker {
+            reference_price: Decimal::from(100),
+            max_move_pct: Decimal::from(10),
+            cooldown_ms: 60_000,
+        }));
+        for price in ["105", "120"] {
+            let ask = OrderBuilder::sell("BTC/USDC", "1", price).build().unwrap();
+            order_book.process_order(ask).unwrap();
+        }
+
+        // The ask at 120 would trip the breaker, so only 1 is fillable
+        let buy = OrderBuilder::buy("BTC/USDC", "2", "120")
+            .time_in_force(TimeInForce::Fok)
+            .build()
+            .unwrap();
+        let (_, trades) = order_book.process_order(buy).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order_book.total_order_count(), 2);
+        assert!(!order_book.is_halted());
+    }
+
+    #[test]
+    fn test_millisecond_arguments_saturate() {
+        let mut order_book = OrderBook::default();
+        let sell = OrderBuilder::sell("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(sell).unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "1", "100").build().unwrap();
+        order_book.process_order(buy).unwrap();
+
+        // Times past the end of the nanosecond range are after every trade
+        assert_eq!(order_book.vwap(u64::MAX), None);
+        assert_eq!(order_book.vwap_for_pair("BTC/USDC", u64::MAX), None);
+        assert!(order_book.trades_since(u64::MAX).is_empty());
+        let filter = TradeFilter {
+            from_ms: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(order_book.filter_trades(filter).is_empty());
+
+        // And windows or intervals that long cover all of them
+        assert_eq!(order_book.generate_candles(u64::MAX).len(), 1);
+        assert_eq!(
+            order_book.twap(0, u64::MAX, u64::MAX),
+            Some(Decimal::from(100))
+        );
+        assert!(order_book.order_arrival_rate(u64::MAX) > 0.0);
+    }
+
+    #[test]
+    fn test_restore_keeps_trading_rules() {
+        let mut source = OrderBook::new(OrderBookConfig {
+            tick_size: Some(Decimal::ONE),
+            lot_size: Some(Decimal::ONE),
+            self_trade_prevention: SelfTradePrevention::CancelMaker,
+            matching_algorithm: MatchingAlgorithm::ProRata,
+            ..Default::default()
+        })
+        .unwrap();
+        source.set_fee_schedule(FeeSchedule {
+            fee_rate_maker: Some(Decimal::from_str("0.001").unwrap()),
+            fee_rate_taker: Some(Decimal::from_str("0.002").unwrap()),
+        });
+        source.set_circuit_breaker(Some(CircuitBreaker {
+            reference_price: Decimal::from(100),
+            max_move_pct: Decimal::from(5),
+            cooldown_ms: 60_000,
+        }));
+        for (id, account, amount, price) in [
+            ("1", "maker1", "3", "100"),
+            ("2", "maker2", "1", "100"),
+            ("3", "taker", "2", "100"),
+            ("4", "maker1", "1", "110"),
+        ] {
+            let ask = OrderBuilder::sell("BTC/USDC", amount, price)
+                .order_id(id)
+                .account(account)
+                .build()
+                .unwrap();
+            source.process_order(ask).unwrap();
+        }
+
+        let json = serde_json::to_string(&source.snapshot()).unwrap();
+        let snapshot: OrderBookSnapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = OrderBook::restore(snapshot).unwrap();
+        assert_eq!(restored.config(), source.config());
+        assert_eq!(restored.fee_schedule(), source.fee_schedule());
+
+        // Off-tick prices are refused, the own ask is cancelled, the rest of the level
+        // is shared pro rata with fees charged, and the breaker stops it at 110
+        let off_tick = OrderBuilder::buy("BTC/USDC", "1", "100.5")
+            .account("taker")
+            .build()
+            .unwrap();
+        let buy = OrderBuilder::buy("BTC/USDC", "5", "110")
+            .order_id("5")
+            .account("taker")
+            .build()
+            .unwrap();
+        let run = |book: &mut OrderBook| {
+            let rejected = book.process_order(off_tick.clone()).unwrap_err();
+            let (_, trades) = book.process_order(buy.clone()).unwrap();
+            let trades: Vec<_> = trades
+                .into_iter()
+                .map(|t| {
+                    (
+                        t.maker_order_id,
+                        t.price,
+                        t.amount,
+                        t.maker_fee,
+                        t.taker_fee,
+                    )
+                })
+                .collect();
+            let resting: Vec<_> = book
+                .generate_order_book_output()
+                .into_iter()
+                .map(|e| (e.order_id, e.amount))
+                .collect();
+            (rejected, trades, resting)
+        };
+        let expected = run(&mut source);
+        assert_eq!(expected.1.len(), 2);
+        assert_eq!(expected.2, vec![("4".to_string(), "1".to_string())]);
+        assert_eq!(run(&mut restored), expected);
+
+        let mut invalid = source.snapshot();
+        invalid.config.tick_size = Some(Decimal::ZERO);
+        assert!(matches!(
+            OrderBook::restore(invalid),
+            Err(OrderBookError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_iceberg_reserve_stays_hidden() {
+        let mut order_book = OrderBook::default();
+        #[cfg_attr(not(feature = "broadcast"), allow(unused_mut))]
+        let mut receiver = order_book.subscribe();
+        let mut iceberg = OrderBuilder::sell("BTC/USDC", "10", "100")
+            .order_id("iceberg")
+            .build()
+            .unwrap();
+        iceberg.visible_amount = Some(Decimal::ONE);
+        order_book.process_order(iceberg).unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        assert!(matches!(
+            &events[..],
+            [
+                OrderBookEvent::OrderAdded(entry),
+                OrderBookEvent::DepthChanged(Side::Sell, _, amount),
+            ] if entry.amount == "1" && *amount == Decimal::ONE
+        ));
+
+        assert_eq!(order_book.get_depth(1).asks[0].total_amount, Decimal::ONE);
+        assert_eq!(
+            order_book.get_volume_at_price(Side::Sell, Decimal::from(100)),
+            Decimal::ONE
+        );
+    }
 }